@@ -0,0 +1,24 @@
+-- confusable skeleton of the username (UTS #39), used to reject look-alike usernames
+ALTER TABLE users ADD COLUMN IF NOT EXISTS username_skeleton text;
+
+-- the skeletons are computed by the server, the existing users get their lowercase NFKC form
+-- which is their skeleton unless they contain confusable characters,
+-- look-alikes among them keep apart by their id
+UPDATE users AS u
+SET username_skeleton = CASE
+  WHEN s.rank = 1 THEN s.skeleton
+  ELSE s.skeleton || '#' || u.id
+END
+FROM (
+  SELECT
+    id,
+    lower(normalize(username, NFKC)) AS skeleton,
+    row_number() OVER (PARTITION BY lower(normalize(username, NFKC)) ORDER BY id) AS rank
+  FROM users
+  WHERE username_skeleton IS NULL
+) AS s
+WHERE u.id = s.id;
+
+ALTER TABLE users ALTER COLUMN username_skeleton SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_skeleton_idx ON users (username_skeleton);
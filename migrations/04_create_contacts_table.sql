@@ -0,0 +1,20 @@
+BEGIN;
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name text;
+-- users can opt out of the user search
+ALTER TABLE users ADD COLUMN IF NOT EXISTS searchable boolean NOT NULL DEFAULT true;
+
+CREATE INDEX IF NOT EXISTS users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_display_name_trgm_idx ON users USING GIN (display_name gin_trgm_ops);
+
+CREATE TABLE IF NOT EXISTS contacts(
+  owner_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  contact_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  added_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (owner_id, contact_id),
+  CHECK (owner_id <> contact_id)
+);
+
+COMMIT;
@@ -0,0 +1,25 @@
+BEGIN;
+
+-- a missing rule means the setting is visible to everybody
+CREATE TABLE IF NOT EXISTS privacy_rules(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  key VARCHAR(20) NOT NULL CHECK (key IN ('last_seen', 'profile_photo', 'group_invites', 'private_chats')),
+  visibility VARCHAR(20) NOT NULL CHECK (visibility IN ('everybody', 'contacts', 'nobody')),
+  PRIMARY KEY (user_id, key)
+);
+
+-- per-user exceptions always win over the visibility of the rule
+CREATE TABLE IF NOT EXISTS privacy_exceptions(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  key VARCHAR(20) NOT NULL CHECK (key IN ('last_seen', 'profile_photo', 'group_invites', 'private_chats')),
+  target_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  allow boolean NOT NULL,
+  PRIMARY KEY (user_id, key, target_id)
+);
+
+-- shown to the users passing the profile_photo privacy rule
+ALTER TABLE users ADD COLUMN IF NOT EXISTS photo_media_id bigint;
+
+CREATE INDEX IF NOT EXISTS users_photo_media_id_idx ON users (photo_media_id) WHERE photo_media_id IS NOT NULL;
+
+COMMIT;
@@ -0,0 +1,2 @@
+-- rounded down to the configured granularity, so the exact activity is not exposed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_seen_at timestamptz;
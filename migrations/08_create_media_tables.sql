@@ -0,0 +1,32 @@
+BEGIN;
+
+-- one row per distinct content, the content is stored in the media store under its SHA-256
+CREATE TABLE IF NOT EXISTS media(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  sha256 char(64) NOT NULL UNIQUE,
+  size bigint NOT NULL,
+  content_type text NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+-- users who uploaded the content, only they can attach it to messages
+CREATE TABLE IF NOT EXISTS media_uploads(
+  media_id bigint NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (media_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS message_media(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  media_id bigint NOT NULL REFERENCES media(id) ON DELETE RESTRICT,
+  PRIMARY KEY (message_id, media_id)
+);
+
+CREATE INDEX IF NOT EXISTS message_media_media_id_idx ON message_media (media_id);
+
+-- the profile photo keeps the media from being purged
+ALTER TABLE users ADD CONSTRAINT users_photo_media_id_fkey
+  FOREIGN KEY (photo_media_id) REFERENCES media(id) ON DELETE SET NULL;
+
+COMMIT;
@@ -0,0 +1,22 @@
+BEGIN;
+
+-- resumable uploads, the chunks are kept in the media store until the session is finalized
+CREATE TABLE IF NOT EXISTS upload_sessions(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  total_size bigint NOT NULL,
+  chunk_size integer NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  -- refreshed by every uploaded chunk, idle sessions are garbage-collected
+  updated_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS upload_sessions_updated_at_idx ON upload_sessions (updated_at);
+
+CREATE TABLE IF NOT EXISTS upload_chunks(
+  session_id bigint NOT NULL REFERENCES upload_sessions(id) ON DELETE CASCADE,
+  chunk_index integer NOT NULL,
+  PRIMARY KEY (session_id, chunk_index)
+);
+
+COMMIT;
@@ -0,0 +1,20 @@
+BEGIN;
+
+-- only set for the images which could be decoded
+ALTER TABLE media
+  ADD COLUMN IF NOT EXISTS width integer,
+  ADD COLUMN IF NOT EXISTS height integer,
+  ADD COLUMN IF NOT EXISTS blurhash text;
+
+-- JPEG previews, stored in the media store next to the image
+CREATE TABLE IF NOT EXISTS media_thumbnails(
+  media_id bigint NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+  -- the thumbnail fits into a square of this many pixels
+  size integer NOT NULL,
+  width integer NOT NULL,
+  height integer NOT NULL,
+  byte_size bigint NOT NULL,
+  PRIMARY KEY (media_id, size)
+);
+
+COMMIT;
@@ -0,0 +1,10 @@
+BEGIN;
+
+-- set by the garbage collector when no upload or message references the media anymore,
+-- the media is purged once it stayed unreferenced for the grace period
+ALTER TABLE media ADD COLUMN IF NOT EXISTS orphaned_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS media_uploads_user_id_idx ON media_uploads (user_id);
+CREATE INDEX IF NOT EXISTS messages_sender_id_idx ON messages (sender_id);
+
+COMMIT;
@@ -0,0 +1,13 @@
+BEGIN;
+
+-- only set for the Opus voice notes which could be parsed
+ALTER TABLE media
+  ADD COLUMN IF NOT EXISTS duration_ms integer,
+  -- one byte per value in the range 0-31
+  ADD COLUMN IF NOT EXISTS waveform bytea;
+
+-- a voice message has its audio attached as the only media
+ALTER TABLE messages
+  ADD COLUMN IF NOT EXISTS kind VARCHAR(20) NOT NULL DEFAULT 'text' CHECK (kind IN ('text', 'voice'));
+
+COMMIT;
@@ -0,0 +1,15 @@
+BEGIN;
+
+-- a user can react to a message with several distinct emoji
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  emoji VARCHAR(64) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- emoji the participants may react with, null allows every emoji and empty disables reactions
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS allowed_reactions text[];
+
+COMMIT;
@@ -0,0 +1,17 @@
+BEGIN;
+
+-- service messages record events of the chat instead of user content
+ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_kind_check;
+ALTER TABLE messages
+  ADD CONSTRAINT messages_kind_check CHECK (kind IN ('text', 'voice', 'service')),
+  ADD COLUMN IF NOT EXISTS service jsonb;
+
+CREATE TABLE IF NOT EXISTS pinned_messages(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  pinned_by bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  pinned_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+COMMIT;
@@ -0,0 +1,9 @@
+BEGIN;
+
+-- the simple configuration does no stemming, so every language is matched by its words
+ALTER TABLE messages
+  ADD COLUMN IF NOT EXISTS content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+CREATE INDEX IF NOT EXISTS messages_content_tsv_idx ON messages USING GIN (content_tsv);
+
+COMMIT;
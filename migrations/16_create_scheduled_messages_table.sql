@@ -0,0 +1,18 @@
+BEGIN;
+
+-- posted into the chat by the scheduler once due, the permissions are checked again at that time
+CREATE TABLE IF NOT EXISTS scheduled_messages(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  sender_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  content text NOT NULL,
+  kind VARCHAR(20) NOT NULL DEFAULT 'text' CHECK (kind IN ('text', 'voice')),
+  media_ids bigint[] NOT NULL DEFAULT '{}',
+  send_at timestamptz NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_idx ON scheduled_messages (send_at);
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_id_idx ON scheduled_messages (sender_id, chat_id);
+
+COMMIT;
@@ -0,0 +1,14 @@
+BEGIN;
+
+-- expired messages are deleted by the sweeper
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS expires_at timestamptz;
+CREATE INDEX IF NOT EXISTS messages_expires_at_idx ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- seconds after which new messages of the chat expire
+ALTER TABLE chats
+  ADD COLUMN IF NOT EXISTS auto_delete_after integer CHECK (auto_delete_after IN (86400, 604800, 2592000));
+
+-- seconds the message lives once posted
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS ttl integer;
+
+COMMIT;
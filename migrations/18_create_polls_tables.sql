@@ -0,0 +1,33 @@
+BEGIN;
+
+ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_kind_check;
+ALTER TABLE messages
+  ADD CONSTRAINT messages_kind_check CHECK (kind IN ('text', 'voice', 'service', 'poll'));
+
+-- the question of a poll is the content of its message
+CREATE TABLE IF NOT EXISTS polls(
+  message_id bigint PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+  options text[] NOT NULL,
+  multiple_choice boolean NOT NULL DEFAULT false,
+  anonymous boolean NOT NULL DEFAULT true,
+  -- only set for quizzes, the index into the options
+  correct_option smallint,
+  explanation text,
+  closed_at timestamptz
+);
+
+-- one row per chosen option
+CREATE TABLE IF NOT EXISTS poll_votes(
+  message_id bigint NOT NULL REFERENCES polls(message_id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  option smallint NOT NULL,
+  voted_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (message_id, user_id, option)
+);
+
+ALTER TABLE scheduled_messages DROP CONSTRAINT IF EXISTS scheduled_messages_kind_check;
+ALTER TABLE scheduled_messages
+  ADD CONSTRAINT scheduled_messages_kind_check CHECK (kind IN ('text', 'voice', 'poll')),
+  ADD COLUMN IF NOT EXISTS poll jsonb;
+
+COMMIT;
@@ -0,0 +1,28 @@
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS invite_links(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  -- random and unguessable, the link is shared as this token
+  token VARCHAR(64) NOT NULL UNIQUE,
+  created_by bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  expires_at timestamptz,
+  max_uses integer CHECK (max_uses > 0),
+  uses integer NOT NULL DEFAULT 0,
+  requires_approval boolean NOT NULL DEFAULT false,
+  -- revoked links are kept for their statistics
+  revoked_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS invite_links_chat_id_idx ON invite_links (chat_id);
+
+-- who joined through each link, kept after the user leaves the chat
+CREATE TABLE IF NOT EXISTS invite_link_joins(
+  link_id bigint NOT NULL REFERENCES invite_links(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  joined_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (link_id, user_id)
+);
+
+COMMIT;
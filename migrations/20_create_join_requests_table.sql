@@ -0,0 +1,14 @@
+BEGIN;
+
+-- pending requests to join through links which require approval, deleted once decided
+CREATE TABLE IF NOT EXISTS join_requests(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  link_id bigint REFERENCES invite_links(id) ON DELETE SET NULL,
+  -- optional message of the applicant to the admins
+  message text,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+COMMIT;
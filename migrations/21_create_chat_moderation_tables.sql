@@ -0,0 +1,29 @@
+BEGIN;
+
+-- banned users cannot rejoin the chat until unbanned or the ban expires
+CREATE TABLE IF NOT EXISTS chat_bans(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- NULL once the admin is deleted, the ban outlives them
+  banned_by bigint REFERENCES users(id) ON DELETE SET NULL,
+  -- NULL bans forever
+  until timestamptz,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+-- permissions taken away from a member, expired rows are ignored
+CREATE TABLE IF NOT EXISTS chat_restrictions(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- NULL once the admin is deleted, the restriction outlives them
+  restricted_by bigint REFERENCES users(id) ON DELETE SET NULL,
+  -- bitmask of the revoked permissions
+  permissions integer NOT NULL CHECK (permissions <> 0),
+  -- NULL restricts until lifted
+  until timestamptz,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+COMMIT;
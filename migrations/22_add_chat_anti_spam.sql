@@ -0,0 +1,28 @@
+BEGIN;
+
+-- seconds a member waits between two messages, NULL disables the slow mode
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS slow_mode_interval integer CHECK (slow_mode_interval > 0);
+
+-- members sending more than flood_max_messages in flood_interval seconds
+-- cannot send messages for flood_restrict_for seconds
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS flood_max_messages integer CHECK (flood_max_messages > 0);
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS flood_interval integer CHECK (flood_interval > 0);
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS flood_restrict_for integer CHECK (flood_restrict_for > 0);
+ALTER TABLE chats ADD CONSTRAINT chats_flood_limit_check CHECK (
+  (flood_max_messages IS NULL) = (flood_interval IS NULL)
+  AND (flood_max_messages IS NULL) = (flood_restrict_for IS NULL)
+);
+
+-- members exceeding the flood limit cannot send messages until the restriction expires,
+-- kept apart from the restrictions of the admins so either can end on its own
+CREATE TABLE IF NOT EXISTS chat_flood_restrictions(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  until timestamptz NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS messages_sender_id_chat_id_idx ON messages (sender_id, chat_id, created_at);
+
+COMMIT;
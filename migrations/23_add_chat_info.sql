@@ -0,0 +1,10 @@
+BEGIN;
+
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS title VARCHAR(128);
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS description text;
+-- the photo keeps the media from being purged
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS photo_media_id bigint REFERENCES media(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS chats_photo_media_id_idx ON chats (photo_media_id) WHERE photo_media_id IS NOT NULL;
+
+COMMIT;
@@ -0,0 +1,60 @@
+BEGIN;
+
+-- shown next to the name of the admin
+ALTER TABLE chat_participants ADD COLUMN IF NOT EXISTS custom_title VARCHAR(16);
+
+-- at most one owner per chat
+CREATE UNIQUE INDEX IF NOT EXISTS chat_participants_owner_idx ON chat_participants (chat_id) WHERE role = 'owner';
+
+-- groups whose owner left before the owners were enforced pass the ownership to the earliest member,
+-- which is the creator while they are still in the group
+UPDATE chat_participants AS cp
+SET role = 'owner'
+FROM (
+  SELECT DISTINCT ON (p.chat_id) p.chat_id, p.user_id
+  FROM chat_participants AS p
+  JOIN chats AS c ON c.id = p.chat_id
+  WHERE c.type <> 'private' AND NOT EXISTS (
+    SELECT 1 FROM chat_participants WHERE chat_id = p.chat_id AND role = 'owner'
+  )
+  ORDER BY p.chat_id, p.added_at, p.user_id
+) AS earliest
+WHERE cp.chat_id = earliest.chat_id AND cp.user_id = earliest.user_id;
+
+-- at least one owner per group and channel, checked at commit so the ownership can change hands
+CREATE OR REPLACE FUNCTION check_chat_owner() RETURNS trigger AS $$
+DECLARE
+  target_chat_id bigint;
+BEGIN
+  IF TG_TABLE_NAME = 'chats' THEN
+    target_chat_id := NEW.id;
+  ELSE
+    target_chat_id := OLD.chat_id;
+  END IF;
+
+  IF EXISTS (SELECT 1 FROM chats WHERE id = target_chat_id AND type <> 'private')
+    AND NOT EXISTS (
+      SELECT 1 FROM chat_participants WHERE chat_id = target_chat_id AND role = 'owner'
+    )
+  THEN
+    RAISE EXCEPTION 'chat % has no owner', target_chat_id USING ERRCODE = 'check_violation';
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- a private chat turned into a group needs an owner as well
+DROP TRIGGER IF EXISTS chats_owner_check ON chats;
+CREATE CONSTRAINT TRIGGER chats_owner_check
+  AFTER INSERT OR UPDATE OF type ON chats
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW EXECUTE FUNCTION check_chat_owner();
+
+DROP TRIGGER IF EXISTS chat_participants_owner_check ON chat_participants;
+CREATE CONSTRAINT TRIGGER chat_participants_owner_check
+  AFTER UPDATE OF role OR DELETE ON chat_participants
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW WHEN (OLD.role = 'owner') EXECUTE FUNCTION check_chat_owner();
+
+COMMIT;
@@ -0,0 +1,18 @@
+BEGIN;
+
+-- administrative actions taken in the chat, kept for the other admins to review
+CREATE TABLE IF NOT EXISTS chat_admin_log(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  actor_id bigint NOT NULL REFERENCES users(id) ON DELETE RESTRICT,
+  -- tagged by type, with the details of the action
+  action jsonb NOT NULL,
+  action_type VARCHAR(32) GENERATED ALWAYS AS (action->>'type') STORED,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS chat_admin_log_chat_id_idx ON chat_admin_log (chat_id, id);
+CREATE INDEX IF NOT EXISTS chat_admin_log_action_type_idx ON chat_admin_log (chat_id, action_type, id);
+CREATE INDEX IF NOT EXISTS chat_admin_log_actor_id_idx ON chat_admin_log (chat_id, actor_id, id);
+
+COMMIT;
@@ -0,0 +1,36 @@
+BEGIN;
+
+-- groups with topics enabled split their messages into topics
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS topics_enabled boolean NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS forum_topics(
+  id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  title VARCHAR(128) NOT NULL,
+  -- NULL for the General topic
+  created_by bigint REFERENCES users(id) ON DELETE SET NULL,
+  -- the General topic holds the messages sent before the topics were enabled
+  is_general boolean NOT NULL DEFAULT false,
+  -- only the admins can write into closed topics
+  closed_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS forum_topics_general_idx ON forum_topics (chat_id) WHERE is_general;
+CREATE INDEX IF NOT EXISTS forum_topics_chat_id_idx ON forum_topics (chat_id, id);
+
+-- NULL in chats without topics
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS topic_id bigint REFERENCES forum_topics(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS messages_topic_id_idx ON messages (topic_id, id) WHERE topic_id IS NOT NULL;
+
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS topic_id bigint REFERENCES forum_topics(id) ON DELETE CASCADE;
+
+-- the newest message of the topic the user has read
+CREATE TABLE IF NOT EXISTS forum_topic_reads(
+  topic_id bigint NOT NULL REFERENCES forum_topics(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  last_read_message_id bigint NOT NULL,
+  PRIMARY KEY (topic_id, user_id)
+);
+
+COMMIT;
@@ -17,8 +17,12 @@ use jsonwebtoken::EncodingKey;
 use jsonwebtoken::Header;
 use jsonwebtoken::Validation;
 use tracing::event;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::RestrictionLevel;
+use unicode_security::RestrictionLevelDetection;
 
 use crate::error::create_error_json;
+use crate::presence::Presence;
 use crate::startup::TokenSecret;
 
 pub struct Credentials {
@@ -34,22 +38,64 @@ impl Credentials {
         username: impl Into<String>,
         password: impl Into<String>,
     ) -> Result<Self, CredentialsVerifyError> {
-        let username = username.into();
-        let password = password.into();
+        let Self { username, password } = Self::normalized(username, password);
 
-        // check the chars in username and password
-        if !username.is_ascii() || !password.is_ascii() {
+        let username_length = username.chars().count();
+        if !(1..=64).contains(&username_length) {
+            return Err(CredentialsVerifyError::BadUsernameLength);
+        }
+        // only letters, marks, digits and underscores are allowed in username
+        if !username.chars().all(unicode_ident::is_xid_continue) {
+            return Err(CredentialsVerifyError::InvalidCharacter);
+        }
+
+        // reject restricted characters and usernames mixing scripts (e.g. Cyrillic with Latin)
+        if !username.check_restriction_level(RestrictionLevel::SingleScript) {
+            return Err(CredentialsVerifyError::MixedScript);
+        }
+
+        // control characters are never a part of a password
+        if password.chars().any(char::is_control) {
             return Err(CredentialsVerifyError::InvalidCharacter);
         }
 
         // check the password length
-        let password_length = password.len();
+        let password_length = password.chars().count();
         if !(8..=256).contains(&password_length) {
             return Err(CredentialsVerifyError::BadPasswordLength);
         }
 
         Ok(Self { username, password })
     }
+
+    /// Normalize credentials without checks
+    ///
+    /// Use for login propose, the stored credentials were normalized in the same way
+    pub fn normalized(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: normalize_username(&username.into()),
+            password: normalize_password(&password.into()),
+        }
+    }
+}
+
+/// Normalize a username into its NFKC form
+pub fn normalize_username(username: &str) -> String {
+    username.nfkc().collect()
+}
+
+/// Normalize a password into its NFKC form, the form it is hashed in
+pub fn normalize_password(password: &str) -> String {
+    password.nfkc().collect()
+}
+
+/// Compute the confusable skeleton of a username
+///
+/// Two usernames with the same skeleton look alike (`раvel` and `pavel`),
+/// so only one of them can be registered.
+pub fn username_skeleton(username: &str) -> String {
+    let lowercase = username.to_lowercase();
+    unicode_security::skeleton(&lowercase).collect()
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -59,8 +105,16 @@ pub enum CredentialsVerifyError {
     )]
     BadPasswordLength,
 
+    #[error(
+        "Username length not match the requirement: only length in the range 1-64 is acceptable"
+    )]
+    BadUsernameLength,
+
     #[error("Invalid character")]
     InvalidCharacter,
+
+    #[error("Username mixes characters from multiple scripts")]
+    MixedScript,
 }
 
 pub fn hash_password(raw_pwd: &str) -> Result<String, argon2::password_hash::Error> {
@@ -160,8 +214,13 @@ impl FromRequest for BearerAuth {
             }
         };
 
-        ready(Ok(Self {
-            user_id: claims.claims.user_id,
-        }))
+        let user_id = claims.claims.user_id;
+
+        // every authenticated request keeps the user online
+        if let Some(presence) = req.app_data::<web::Data<Presence>>() {
+            presence.touch(user_id);
+        }
+
+        ready(Ok(Self { user_id }))
     }
 }
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::realtime::{Event, Hub};
+
+/// Seconds a chat action lasts unless the client repeats it
+pub const CHAT_ACTION_TIMEOUT: u64 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatAction {
+    Typing,
+    UploadPhoto,
+    UploadVideo,
+    UploadDocument,
+    RecordVoice,
+    Cancel,
+}
+
+/// Ongoing chat actions of this instance
+///
+/// Chat actions are ephemeral, they are never written into the database
+#[derive(Default)]
+pub struct ChatActions {
+    next_generation: AtomicU64,
+    /// (chat id, user id) => generation of the latest action
+    actions: Mutex<HashMap<(i64, i64), u64>>,
+}
+
+impl ChatActions {
+    /// Broadcast the action of the user to the recipients
+    ///
+    /// A cancel event is broadcast when the action is not repeated before the timeout
+    pub fn start(
+        self: Arc<Self>,
+        chat_id: i64,
+        user_id: i64,
+        action: ChatAction,
+        recipients: Vec<i64>,
+        hub: Arc<Hub>,
+    ) {
+        if action == ChatAction::Cancel {
+            let existed = self
+                .actions
+                .lock()
+                .unwrap()
+                .remove(&(chat_id, user_id))
+                .is_some();
+            if existed {
+                broadcast(&hub, &recipients, chat_id, user_id, ChatAction::Cancel);
+            }
+            return;
+        }
+
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        self.actions
+            .lock()
+            .unwrap()
+            .insert((chat_id, user_id), generation);
+        broadcast(&hub, &recipients, chat_id, user_id, action);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(CHAT_ACTION_TIMEOUT)).await;
+
+            // the action was repeated or cancelled in the meantime
+            let expired = {
+                let mut actions = self.actions.lock().unwrap();
+                if actions.get(&(chat_id, user_id)) == Some(&generation) {
+                    actions.remove(&(chat_id, user_id));
+                    true
+                } else {
+                    false
+                }
+            };
+            if expired {
+                broadcast(&hub, &recipients, chat_id, user_id, ChatAction::Cancel);
+            }
+        });
+    }
+}
+
+fn broadcast(hub: &Hub, recipients: &[i64], chat_id: i64, user_id: i64, action: ChatAction) {
+    let expires_in = match action {
+        ChatAction::Cancel => 0,
+        _ => CHAT_ACTION_TIMEOUT,
+    };
+
+    hub.send_many(
+        recipients,
+        Event::ChatAction {
+            chat_id,
+            user_id,
+            action,
+            expires_in,
+        },
+    );
+}
@@ -6,6 +6,10 @@ pub struct Settings {
     pub application: ApplicationConfig,
     pub database: DatabaseConfig,
     pub security: SecurityConfig,
+    pub search: SearchConfig,
+    pub presence: PresenceConfig,
+    pub media: MediaConfig,
+    pub messages: MessagesConfig,
 }
 
 #[derive(serde::Deserialize)]
@@ -25,6 +29,81 @@ pub struct SecurityConfig {
     pub token_secret: String,
 }
 
+#[derive(serde::Deserialize)]
+pub struct SearchConfig {
+    pub rate_limit: RateLimitConfig,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitConfig {
+    pub max_requests: usize,
+    /// Window of the limit in seconds
+    pub interval: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PresenceConfig {
+    /// Seconds a user without real-time connections stays online after a request
+    pub online_timeout: u64,
+    /// Seconds the last seen time is rounded down to
+    pub last_seen_granularity: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct MessagesConfig {
+    /// Seconds between two runs of the scheduled messages worker
+    pub scheduler_interval: u64,
+    /// Seconds between two runs of the expired messages sweeper
+    pub sweeper_interval: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct MediaConfig {
+    /// Maximum size of an uploaded file in bytes
+    pub max_file_size: usize,
+    /// Images get a JPEG thumbnail fitting into a square of each of these sizes
+    pub thumbnail_sizes: Vec<u32>,
+    /// Bytes of media each user may reference
+    pub quota_per_user: i64,
+    pub retention: MediaRetentionConfig,
+    pub upload_session: UploadSessionConfig,
+    pub storage: MediaStorageConfig,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct MediaRetentionConfig {
+    /// Seconds an unreferenced media is kept before it is purged
+    pub grace_period: u64,
+    /// Seconds between two runs of the garbage collector
+    pub gc_interval: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct UploadSessionConfig {
+    /// Size of every chunk in bytes except the last one
+    pub chunk_size: usize,
+    /// Seconds after the last uploaded chunk an unfinished session is removed
+    pub expire: u64,
+    /// Seconds between two runs of the garbage collector
+    pub gc_interval: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaStorageConfig {
+    Local {
+        path: String,
+    },
+    S3 {
+        bucket: String,
+        region: String,
+        /// Endpoint of a S3 compatible storage, e.g. MinIO
+        endpoint: Option<String>,
+        access_key: String,
+        secret_key: String,
+    },
+}
+
 impl DatabaseConfig {
     pub fn set_db_name(&mut self, db_name: &str) {
         self.url.set_path(db_name);
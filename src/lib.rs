@@ -1,6 +1,13 @@
 pub mod auth;
+pub mod chat_actions;
 pub mod configuration;
 pub mod error;
+pub mod media;
+pub mod message_expiry;
+pub mod presence;
+pub mod rate_limit;
+pub mod realtime;
 pub mod routes;
+pub mod scheduled_messages;
 pub mod startup;
 pub mod telemetry;
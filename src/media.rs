@@ -0,0 +1,66 @@
+mod images;
+mod local;
+mod opus;
+mod retention;
+mod s3;
+mod upload_sessions;
+mod voice;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+pub use images::{ProcessImageError, ProcessedImage, Thumbnail, process_image};
+pub use local::LocalMediaStore;
+pub use opus::{LoudnessDecoder, SILENCE_DB};
+pub use retention::{clear_orphaned, purge_unreferenced_media, run_media_gc};
+pub use s3::S3MediaStore;
+pub use upload_sessions::{chunk_count, run_upload_session_gc, upload_chunk_key};
+pub use voice::{VoiceMetadata, process_voice};
+
+use crate::configuration::MediaStorageConfig;
+
+/// Blob storage of the uploaded files
+///
+/// Keys are derived from the SHA-256 of the content, so the same content is stored once
+#[async_trait::async_trait]
+pub trait MediaStore: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> anyhow::Result<()>;
+
+    /// Returns None if the key does not exist
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    /// Deleting a missing key is not an error
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+pub fn build_media_store(config: &MediaStorageConfig) -> anyhow::Result<Arc<dyn MediaStore>> {
+    let store: Arc<dyn MediaStore> = match config {
+        MediaStorageConfig::Local { path } => Arc::new(LocalMediaStore::new(path)),
+        MediaStorageConfig::S3 {
+            bucket,
+            region,
+            endpoint,
+            access_key,
+            secret_key,
+        } => Arc::new(S3MediaStore::new(
+            bucket,
+            region,
+            endpoint.as_deref(),
+            access_key,
+            secret_key,
+        )?),
+    };
+
+    Ok(store)
+}
+
+/// Storage key of the content with the given SHA-256 hex digest
+pub fn media_key(sha256: &str) -> String {
+    format!("{}/{}", &sha256[..2], sha256)
+}
+
+/// Storage key of a thumbnail of the image with the given SHA-256 hex digest
+pub fn thumbnail_key(sha256: &str, size: u32) -> String {
+    format!("{}/{}_{}", &sha256[..2], sha256, size)
+}
@@ -0,0 +1,127 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use bytes::Bytes;
+use image::{
+    DynamicImage, ImageDecoder, ImageFormat, ImageReader, codecs::jpeg::JpegEncoder,
+    imageops::FilterType,
+};
+
+/// Image with the metadata removed and its previews
+pub struct ProcessedImage {
+    /// Re-encoded image without EXIF and other metadata, the orientation is applied
+    pub data: Bytes,
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+/// JPEG preview fitting into a square of `size` pixels
+pub struct Thumbnail {
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub data: Bytes,
+}
+
+/// Strip the metadata of an image and generate its thumbnails and placeholder
+///
+/// Returns None for the content types other than images. Images which cannot be decoded
+/// (corrupted, or in a format other than JPEG, PNG and WebP) are rejected,
+/// their metadata could not be stripped.
+pub fn process_image(
+    data: &[u8],
+    content_type: &str,
+    thumbnail_sizes: &[u32],
+) -> Result<Option<ProcessedImage>, ProcessImageError> {
+    let format = match content_type {
+        "image/jpeg" => ImageFormat::Jpeg,
+        "image/png" => ImageFormat::Png,
+        "image/webp" => ImageFormat::WebP,
+        _ if content_type.starts_with("image/") => return Err(ProcessImageError::Unsupported),
+        _ => return Ok(None),
+    };
+
+    let Some(image) = decode(data, format) else {
+        return Err(ProcessImageError::Unsupported);
+    };
+
+    // re-encoding drops every metadata block, including the GPS position
+    let data = match format {
+        ImageFormat::Jpeg => encode_jpeg(&image, 90)?,
+        ImageFormat::Png => encode(&image, ImageFormat::Png)?,
+        _ => encode(&DynamicImage::ImageRgba8(image.to_rgba8()), format)?,
+    };
+
+    let mut thumbnails = Vec::new();
+    for &size in thumbnail_sizes {
+        // thumbnails are never larger than the image itself
+        if size >= image.width().max(image.height()) {
+            continue;
+        }
+
+        let thumbnail = image.resize(size, size, FilterType::Triangle);
+        thumbnails.push(Thumbnail {
+            size,
+            width: thumbnail.width(),
+            height: thumbnail.height(),
+            data: encode_jpeg(&thumbnail, 80)?,
+        });
+    }
+
+    Ok(Some(ProcessedImage {
+        data,
+        width: image.width(),
+        height: image.height(),
+        blurhash: blurhash(&image)?,
+        thumbnails,
+    }))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessImageError {
+    #[error("Image cannot be decoded")]
+    Unsupported,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+fn decode(data: &[u8], format: ImageFormat) -> Option<DynamicImage> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder.orientation().ok()?;
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+
+    Some(image)
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> anyhow::Result<Bytes> {
+    let mut buf = Cursor::new(Vec::new());
+    image
+        .write_to(&mut buf, format)
+        .context("Failed to encode image")?;
+
+    Ok(Bytes::from(buf.into_inner()))
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> anyhow::Result<Bytes> {
+    let mut buf = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))
+        .context("Failed to encode JPEG")?;
+
+    Ok(Bytes::from(buf))
+}
+
+/// Placeholder shown while the image or its thumbnails are loading
+fn blurhash(image: &DynamicImage) -> anyhow::Result<String> {
+    // the placeholder is blurry anyway, a tiny image is enough
+    let small = image.thumbnail(32, 32).to_rgba8();
+
+    blurhash::encode(4, 3, small.width(), small.height(), small.as_raw())
+        .map_err(|err| anyhow::anyhow!("Failed to encode blurhash: {err:?}"))
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use bytes::Bytes;
+use uuid::Uuid;
+
+use super::MediaStore;
+
+/// Store the media in a directory of the local filesystem
+pub struct LocalMediaStore {
+    root: PathBuf,
+}
+
+impl LocalMediaStore {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MediaStore for LocalMediaStore {
+    async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> anyhow::Result<()> {
+        let path = self.root.join(key);
+        let dir = path.parent().context("Media key without parent")?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context("Failed to create media directory")?;
+
+        // write into a temporary file first, so readers never see a partial file
+        let tmp_path = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&tmp_path, &data)
+            .await
+            .context("Failed to write media file")?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .context("Failed to move media file")?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match tokio::fs::read(self.root.join(key)).await {
+            Ok(data) => Ok(Some(Bytes::from(data))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).context("Failed to read media file"),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).context("Failed to delete media file"),
+        }
+    }
+}
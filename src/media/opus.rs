@@ -0,0 +1,601 @@
+//! Just enough of an Opus decoder (RFC 6716) to tell how loud a packet is
+//!
+//! SILK frames carry the gains of their subframes and CELT frames the energy
+//! of their bands in the first symbols, so only those are range decoded.
+
+/// Level of silent frames in dB
+pub const SILENCE_DB: f64 = 0.0;
+
+/// Decoder state carried between the packets of a stream
+pub struct LoudnessDecoder {
+    /// Gain index of the last decoded SILK subframe
+    silk_gain_index: i32,
+    /// Coarse energy of the CELT bands per channel, in log2 units
+    celt_energy: [[f64; CELT_BANDS]; 2],
+}
+
+impl Default for LoudnessDecoder {
+    fn default() -> Self {
+        Self {
+            // the value after a decoder reset
+            silk_gain_index: 10,
+            celt_energy: [[0.0; CELT_BANDS]; 2],
+        }
+    }
+}
+
+impl LoudnessDecoder {
+    /// Loudness of an audio packet in dB relative to a 16 bit sample
+    ///
+    /// Returns None if the packet is malformed or its frames can't be measured
+    /// without decoding them completely
+    pub fn packet_loudness(&mut self, packet: &[u8]) -> Option<f64> {
+        let (&toc, frames) = packet.split_first()?;
+        let config = toc >> 3;
+        let channels = if toc & 0x04 != 0 { 2 } else { 1 };
+
+        let mut levels = Vec::new();
+        for frame in split_frames(toc, frames)? {
+            // empty frames are sent for silence with discontinuous transmission
+            if frame.len() <= 1 {
+                levels.push(SILENCE_DB);
+                continue;
+            }
+
+            let level = match config {
+                // SILK, the frames are 10, 20, 40 or 60 ms
+                0..=11 => {
+                    self.silk_loudness(frame, channels, [10, 20, 40, 60][config as usize % 4])
+                }
+                // hybrid, the low band is coded by SILK
+                12..=15 => self.silk_loudness(frame, channels, [10, 20][config as usize % 2]),
+                // CELT, the frames are 2.5, 5, 10 or 20 ms
+                _ => {
+                    let end = [13, 17, 19, 21][(config as usize - 16) / 4];
+                    self.celt_loudness(frame, channels, config as usize % 4, end)
+                }
+            };
+            levels.push(level?);
+        }
+
+        Some(levels.iter().sum::<f64>() / levels.len() as f64)
+    }
+
+    /// Average gain of the subframes of the first frame
+    fn silk_loudness(&mut self, frame: &[u8], channels: usize, duration_ms: usize) -> Option<f64> {
+        let mut dec = RangeDecoder::new(frame);
+        let frame_count = (duration_ms / 20).max(1);
+        let subframes = if duration_ms == 10 { 2 } else { 4 };
+
+        let mut vad = [[false; 3]; 2];
+        let mut lbrr = [false; 2];
+        for channel in 0..channels {
+            for flag in vad[channel].iter_mut().take(frame_count) {
+                *flag = dec.bit_logp(1);
+            }
+            lbrr[channel] = dec.bit_logp(1);
+        }
+
+        for &channel_lbrr in &lbrr[..channels] {
+            // which of the frames have a redundant copy
+            if channel_lbrr && frame_count > 1 {
+                dec.icdf(SILK_LBRR_FLAGS_ICDF[frame_count - 2], 8);
+            }
+        }
+
+        // redundant copies of the previous frames come first and can only be
+        // skipped by decoding them, so the first copy is measured instead
+        if lbrr[0] || lbrr[1] {
+            if channels == 2 {
+                return None;
+            }
+            let mut gain_index = self.silk_gain_index;
+            let indices = silk_gain_indices(&mut dec, true, subframes);
+            return Some(silk_gains_db(&mut gain_index, &indices[..subframes]));
+        }
+
+        if channels == 2 {
+            // mid-side prediction weights
+            dec.icdf(&SILK_STEREO_PRED_JOINT_ICDF, 8);
+            for _ in 0..2 {
+                dec.icdf(&SILK_UNIFORM3_ICDF, 8);
+                dec.icdf(&SILK_UNIFORM5_ICDF, 8);
+            }
+            if !vad[1][0] {
+                dec.icdf(&SILK_STEREO_ONLY_CODE_MID_ICDF, 8);
+            }
+        }
+
+        let indices = silk_gain_indices(&mut dec, vad[0][0], subframes);
+        Some(silk_gains_db(
+            &mut self.silk_gain_index,
+            &indices[..subframes],
+        ))
+    }
+
+    /// Total energy of the coarsely quantized bands
+    fn celt_loudness(
+        &mut self,
+        frame: &[u8],
+        channels: usize,
+        lm: usize,
+        end: usize,
+    ) -> Option<f64> {
+        let mut dec = RangeDecoder::new(frame);
+        let total_bits = frame.len() as u32 * 8;
+
+        let tell = dec.tell();
+        let silence = if tell >= total_bits {
+            true
+        } else if tell == 1 {
+            dec.bit_logp(15)
+        } else {
+            false
+        };
+        if silence {
+            self.celt_energy = [[-28.0; CELT_BANDS]; 2];
+            return Some(SILENCE_DB);
+        }
+
+        // post-filter parameters
+        if dec.tell() + 16 <= total_bits && dec.bit_logp(1) {
+            let octave = dec.uint(6);
+            dec.bits(4 + octave);
+            dec.bits(3);
+            if dec.tell() + 2 <= total_bits {
+                dec.icdf(&CELT_TAPSET_ICDF, 2);
+            }
+        }
+        // transient flag
+        if lm > 0 && dec.tell() + 3 <= total_bits {
+            dec.bit_logp(3);
+        }
+        let intra = dec.tell() + 3 <= total_bits && dec.bit_logp(3);
+
+        let (coef, beta) = if intra {
+            (0.0, CELT_BETA_INTRA)
+        } else {
+            (CELT_PRED_COEF[lm], CELT_BETA_COEF[lm])
+        };
+        let prob_model = &CELT_ENERGY_PROB_MODEL[lm][intra as usize];
+        let mut prev = [0.0; 2];
+        for band in 0..end {
+            for (channel, prev) in prev.iter_mut().enumerate().take(channels) {
+                let budget = total_bits.saturating_sub(dec.tell());
+                let qi = if budget >= 15 {
+                    let pi = 2 * band.min(20);
+                    dec.laplace(
+                        (prob_model[pi] as u32) << 7,
+                        (prob_model[pi + 1] as u32) << 6,
+                    )
+                } else if budget >= 2 {
+                    let qi = dec.icdf(&CELT_SMALL_ENERGY_ICDF, 2) as i32;
+                    (qi >> 1) ^ -(qi & 1)
+                } else if budget >= 1 {
+                    -(dec.bit_logp(1) as i32)
+                } else {
+                    -1
+                };
+                let q = qi as f64;
+
+                let energy = &mut self.celt_energy[channel][band];
+                *energy = coef * energy.max(-9.0) + *prev + q;
+                *prev += q - beta * q;
+            }
+        }
+
+        // the loudest channel of each band, the energies are log2 amplitudes
+        let power: f64 = (0..end)
+            .map(|band| {
+                let energy = self.celt_energy[0][band].max(self.celt_energy[channels - 1][band]);
+                (2.0 * (energy + CELT_MEANS[band])).exp2()
+            })
+            .sum();
+        Some((10.0 * power.log10()).max(SILENCE_DB))
+    }
+}
+
+/// Split a packet into its frames (RFC 6716 section 3.2)
+fn split_frames(toc: u8, data: &[u8]) -> Option<Vec<&[u8]>> {
+    match toc & 0x03 {
+        0 => Some(vec![data]),
+        1 => {
+            if !data.len().is_multiple_of(2) {
+                return None;
+            }
+            let (first, second) = data.split_at(data.len() / 2);
+            Some(vec![first, second])
+        }
+        2 => {
+            let mut rest = data;
+            let length = frame_length(&mut rest)?;
+            let (first, second) = rest.split_at_checked(length)?;
+            Some(vec![first, second])
+        }
+        _ => {
+            let (&count_byte, mut rest) = data.split_first()?;
+            let count = (count_byte & 0x3f) as usize;
+            if count == 0 {
+                return None;
+            }
+
+            if count_byte & 0x40 != 0 {
+                // padding length, 255 means 254 bytes and another length byte
+                let mut padding = 0;
+                loop {
+                    let (&byte, remaining) = rest.split_first()?;
+                    rest = remaining;
+                    if byte == 255 {
+                        padding += 254;
+                    } else {
+                        padding += byte as usize;
+                        break;
+                    }
+                }
+                rest = &rest[..rest.len().checked_sub(padding)?];
+            }
+
+            let mut frames = Vec::with_capacity(count);
+            if count_byte & 0x80 != 0 {
+                // variable bitrate, the lengths of all frames but the last are coded
+                let mut lengths = Vec::with_capacity(count - 1);
+                for _ in 1..count {
+                    lengths.push(frame_length(&mut rest)?);
+                }
+                for length in lengths {
+                    let (frame, remaining) = rest.split_at_checked(length)?;
+                    frames.push(frame);
+                    rest = remaining;
+                }
+                frames.push(rest);
+            } else {
+                if !rest.len().is_multiple_of(count) {
+                    return None;
+                }
+                let length = rest.len() / count;
+                frames.extend((0..count).map(|i| &rest[i * length..(i + 1) * length]));
+            }
+            Some(frames)
+        }
+    }
+}
+
+/// Read a frame length coded in one or two bytes
+fn frame_length(data: &mut &[u8]) -> Option<usize> {
+    let (&first, rest) = data.split_first()?;
+    if first < 252 {
+        *data = rest;
+        return Some(first as usize);
+    }
+    let (&second, rest) = rest.split_first()?;
+    *data = rest;
+    Some(first as usize + 4 * second as usize)
+}
+
+/// Decode the gain indices of a SILK frame which is coded independently
+///
+/// The first index is absolute and the others are deltas to the previous one
+fn silk_gain_indices(dec: &mut RangeDecoder, voice_activity: bool, subframes: usize) -> [i32; 4] {
+    let signal_type = if voice_activity {
+        (dec.icdf(&SILK_TYPE_OFFSET_VAD_ICDF, 8) + 2) >> 1
+    } else {
+        dec.icdf(&SILK_TYPE_OFFSET_NO_VAD_ICDF, 8) >> 1
+    };
+
+    let mut indices = [0; 4];
+    indices[0] = ((dec.icdf(&SILK_GAIN_ICDF[signal_type], 8) << 3)
+        + dec.icdf(&SILK_UNIFORM8_ICDF, 8)) as i32;
+    for index in indices.iter_mut().take(subframes).skip(1) {
+        *index = dec.icdf(&SILK_DELTA_GAIN_ICDF, 8) as i32 - 4;
+    }
+    indices
+}
+
+/// Average gain of the subframes in dB, `gain_index` is the index of the
+/// previous subframe
+fn silk_gains_db(gain_index: &mut i32, indices: &[i32]) -> f64 {
+    // the gain is not allowed to drop more than 16 steps
+    *gain_index = indices[0].max(*gain_index - 16).clamp(0, 63);
+    let mut total = silk_gain_db(*gain_index);
+
+    for &delta in &indices[1..] {
+        // large deltas are coded in double steps
+        let threshold = 2 * 36 - 64 + *gain_index;
+        if delta > threshold {
+            *gain_index += 2 * delta - threshold;
+        } else {
+            *gain_index += delta;
+        }
+        *gain_index = (*gain_index).clamp(0, 63);
+        total += silk_gain_db(*gain_index);
+    }
+
+    total / indices.len() as f64
+}
+
+/// Gain index of a SILK subframe in dB, the 64 steps span 2 to 88 dB
+fn silk_gain_db(index: i32) -> f64 {
+    2.0 + index as f64 * (88.0 - 2.0) / 63.0
+}
+
+/// Range decoder of the Opus entropy coder (RFC 6716 section 4.1)
+struct RangeDecoder<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    end_offset: usize,
+    end_window: u32,
+    end_bits: u32,
+    total_bits: u32,
+    range: u32,
+    value: u32,
+    remainder: u32,
+    ext: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    const CODE_BOT: u32 = 1 << 23;
+    const CODE_TOP: u32 = 1 << 31;
+
+    fn new(buf: &'a [u8]) -> Self {
+        let mut dec = Self {
+            buf,
+            offset: 0,
+            end_offset: 0,
+            end_window: 0,
+            end_bits: 0,
+            total_bits: 9,
+            range: 128,
+            value: 0,
+            remainder: 0,
+            ext: 0,
+        };
+        dec.remainder = dec.read_byte();
+        dec.value = dec.range - 1 - (dec.remainder >> 1);
+        dec.normalize();
+        dec
+    }
+
+    fn read_byte(&mut self) -> u32 {
+        let byte = self.buf.get(self.offset).copied().unwrap_or(0);
+        self.offset += 1;
+        byte as u32
+    }
+
+    fn read_byte_from_end(&mut self) -> u32 {
+        if self.end_offset >= self.buf.len() {
+            return 0;
+        }
+        self.end_offset += 1;
+        self.buf[self.buf.len() - self.end_offset] as u32
+    }
+
+    fn normalize(&mut self) {
+        while self.range <= Self::CODE_BOT {
+            self.total_bits += 8;
+            self.range <<= 8;
+            let symbol = self.remainder;
+            self.remainder = self.read_byte();
+            let symbol = ((symbol << 8) | self.remainder) >> 1;
+            self.value = ((self.value << 8) + (255 & !symbol)) & (Self::CODE_TOP - 1);
+        }
+    }
+
+    /// Number of bits read so far, rounded up
+    fn tell(&self) -> u32 {
+        self.total_bits - (32 - self.range.leading_zeros())
+    }
+
+    fn decode(&mut self, total: u32) -> u32 {
+        self.ext = self.range / total;
+        let symbol = self.value / self.ext;
+        total - (symbol + 1).min(total)
+    }
+
+    fn decode_bin(&mut self, bits: u32) -> u32 {
+        self.ext = self.range >> bits;
+        let symbol = self.value / self.ext;
+        (1 << bits) - (symbol + 1).min(1 << bits)
+    }
+
+    fn update(&mut self, low: u32, high: u32, total: u32) {
+        let symbol = self.ext * (total - high);
+        self.value -= symbol;
+        self.range = if low > 0 {
+            self.ext * (high - low)
+        } else {
+            self.range - symbol
+        };
+        self.normalize();
+    }
+
+    /// Decode a bit which is set with the probability 1/2^logp
+    fn bit_logp(&mut self, logp: u32) -> bool {
+        let symbol = self.range >> logp;
+        let bit = self.value < symbol;
+        if bit {
+            self.range = symbol;
+        } else {
+            self.value -= symbol;
+            self.range -= symbol;
+        }
+        self.normalize();
+        bit
+    }
+
+    /// Decode a symbol with an inverse cumulative distribution of 2^bits
+    fn icdf(&mut self, icdf: &[u8], bits: u32) -> usize {
+        let step = self.range >> bits;
+        let mut symbol = 0;
+        let mut high = self.range;
+        let mut low = step * icdf[0] as u32;
+        while self.value < low {
+            symbol += 1;
+            high = low;
+            low = step * icdf[symbol] as u32;
+        }
+        self.value -= low;
+        self.range = high - low;
+        self.normalize();
+        symbol
+    }
+
+    /// Decode a uniformly distributed integer below `total`
+    fn uint(&mut self, total: u32) -> u32 {
+        let max = total - 1;
+        let bits = 32 - max.leading_zeros();
+        if bits > 8 {
+            let extra = bits - 8;
+            let total = (max >> extra) + 1;
+            let symbol = self.decode(total);
+            self.update(symbol, symbol + 1, total);
+            ((symbol << extra) | self.bits(extra)).min(max)
+        } else {
+            let symbol = self.decode(total);
+            self.update(symbol, symbol + 1, total);
+            symbol
+        }
+    }
+
+    /// Read raw bits, they are stored from the end of the frame
+    fn bits(&mut self, bits: u32) -> u32 {
+        if self.end_bits < bits {
+            while self.end_bits <= 24 {
+                self.end_window |= self.read_byte_from_end() << self.end_bits;
+                self.end_bits += 8;
+            }
+        }
+        let value = self.end_window & ((1 << bits) - 1);
+        self.end_window >>= bits;
+        self.end_bits -= bits;
+        self.total_bits += bits;
+        value
+    }
+
+    /// Decode a CELT energy residual with a Laplace distribution
+    fn laplace(&mut self, mut frequency: u32, decay: u32) -> i32 {
+        let target = self.decode_bin(15);
+        let mut value = 0;
+        let mut low = 0;
+        if target >= frequency {
+            value += 1;
+            low = frequency;
+            frequency = (((32768 - 32 - frequency) * (16384 - decay)) >> 15) + 1;
+            while frequency > 1 && target >= low + 2 * frequency {
+                frequency *= 2;
+                low += frequency;
+                frequency = (((frequency - 2) * decay) >> 15) + 1;
+                value += 1;
+            }
+            // everything beyond has the minimum probability
+            if frequency <= 1 {
+                let steps = (target - low) >> 1;
+                value += steps as i32;
+                low += 2 * steps;
+            }
+            if target < low + frequency {
+                value = -value;
+            } else {
+                low += frequency;
+            }
+        }
+        self.update(low, (low + frequency).min(32768), 32768);
+        value
+    }
+}
+
+const SILK_TYPE_OFFSET_VAD_ICDF: [u8; 4] = [232, 158, 10, 0];
+const SILK_TYPE_OFFSET_NO_VAD_ICDF: [u8; 2] = [230, 0];
+const SILK_GAIN_ICDF: [[u8; 8]; 3] = [
+    [224, 112, 44, 15, 3, 2, 1, 0],
+    [254, 237, 192, 132, 70, 23, 4, 0],
+    [255, 252, 226, 155, 61, 11, 2, 0],
+];
+const SILK_DELTA_GAIN_ICDF: [u8; 41] = [
+    250, 245, 234, 203, 71, 50, 42, 38, 35, 33, 31, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18,
+    17, 16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+];
+const SILK_UNIFORM3_ICDF: [u8; 3] = [171, 85, 0];
+const SILK_UNIFORM5_ICDF: [u8; 5] = [205, 154, 102, 51, 0];
+const SILK_UNIFORM8_ICDF: [u8; 8] = [224, 192, 160, 128, 96, 64, 32, 0];
+const SILK_STEREO_PRED_JOINT_ICDF: [u8; 25] = [
+    249, 247, 246, 245, 244, 234, 210, 202, 201, 200, 197, 174, 82, 59, 56, 55, 54, 46, 22, 12, 11,
+    10, 9, 7, 0,
+];
+const SILK_STEREO_ONLY_CODE_MID_ICDF: [u8; 2] = [64, 0];
+const SILK_LBRR_FLAGS_ICDF: [&[u8]; 2] = [&[203, 150, 0], &[215, 195, 166, 125, 110, 82, 0]];
+
+const CELT_BANDS: usize = 21;
+const CELT_TAPSET_ICDF: [u8; 3] = [2, 1, 0];
+const CELT_SMALL_ENERGY_ICDF: [u8; 3] = [2, 1, 0];
+/// Prediction of the band energies from the previous frame per frame size
+const CELT_PRED_COEF: [f64; 4] = [
+    29440.0 / 32768.0,
+    26112.0 / 32768.0,
+    21248.0 / 32768.0,
+    16384.0 / 32768.0,
+];
+/// Prediction of the band energies from the lower band per frame size
+const CELT_BETA_COEF: [f64; 4] = [
+    30147.0 / 32768.0,
+    22282.0 / 32768.0,
+    12124.0 / 32768.0,
+    6554.0 / 32768.0,
+];
+const CELT_BETA_INTRA: f64 = 4915.0 / 32768.0;
+/// Mean energies of the bands, the coded energies are relative to them
+const CELT_MEANS: [f64; CELT_BANDS] = [
+    6.4375, 6.25, 5.75, 5.3125, 5.0625, 4.8125, 4.5, 4.375, 4.875, 4.6875, 4.5625, 4.4375, 4.875,
+    4.625, 4.3125, 4.5, 4.375, 4.625, 4.75, 4.4375, 3.75,
+];
+/// Laplace distributions of the energy residuals per frame size, for inter
+/// and intra frames
+const CELT_ENERGY_PROB_MODEL: [[[u8; 42]; 2]; 4] = [
+    [
+        [
+            72, 127, 65, 129, 66, 128, 65, 128, 64, 128, 62, 128, 64, 128, 64, 128, 92, 78, 92, 79,
+            92, 78, 90, 79, 116, 41, 115, 40, 114, 40, 132, 26, 132, 26, 145, 17, 161, 12, 176, 10,
+            177, 11,
+        ],
+        [
+            24, 179, 48, 138, 54, 135, 54, 132, 53, 134, 56, 133, 55, 132, 55, 132, 61, 114, 70,
+            96, 74, 88, 75, 88, 87, 74, 89, 66, 91, 67, 100, 59, 108, 50, 120, 40, 122, 37, 97, 43,
+            78, 50,
+        ],
+    ],
+    [
+        [
+            83, 78, 84, 81, 88, 75, 86, 74, 87, 71, 90, 73, 93, 74, 93, 74, 109, 40, 114, 36, 117,
+            34, 117, 34, 143, 17, 145, 18, 146, 19, 162, 12, 165, 10, 178, 7, 189, 6, 190, 8, 177,
+            9,
+        ],
+        [
+            23, 178, 54, 115, 63, 102, 66, 98, 69, 99, 74, 89, 71, 91, 73, 91, 78, 89, 86, 80, 92,
+            66, 93, 64, 102, 59, 103, 60, 104, 60, 117, 52, 123, 44, 138, 35, 133, 31, 97, 38, 77,
+            45,
+        ],
+    ],
+    [
+        [
+            61, 90, 93, 60, 105, 42, 107, 41, 110, 45, 116, 38, 113, 38, 112, 38, 124, 26, 132, 27,
+            136, 19, 140, 20, 155, 14, 159, 16, 158, 18, 170, 13, 177, 10, 187, 8, 192, 6, 175, 9,
+            159, 10,
+        ],
+        [
+            21, 178, 59, 110, 71, 86, 75, 85, 84, 83, 91, 66, 88, 73, 87, 72, 92, 75, 98, 72, 105,
+            58, 107, 54, 115, 52, 114, 55, 112, 56, 129, 51, 132, 40, 150, 33, 140, 29, 98, 35, 77,
+            42,
+        ],
+    ],
+    [
+        [
+            42, 121, 96, 66, 108, 43, 111, 40, 117, 44, 123, 32, 120, 36, 119, 33, 127, 33, 134,
+            34, 139, 21, 147, 23, 152, 20, 158, 25, 154, 26, 166, 21, 173, 16, 184, 13, 184, 10,
+            150, 13, 139, 15,
+        ],
+        [
+            22, 178, 63, 114, 74, 82, 84, 83, 92, 82, 103, 62, 96, 72, 96, 67, 101, 73, 107, 72,
+            113, 55, 118, 52, 125, 52, 118, 52, 117, 55, 135, 49, 137, 39, 157, 32, 145, 29, 97,
+            33, 77, 40,
+        ],
+    ],
+];
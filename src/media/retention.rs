@@ -0,0 +1,227 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tracing::event;
+
+use super::{MediaStore, media_key, thumbnail_key};
+
+/// Media purged by a single transaction
+const PURGE_BATCH_SIZE: i64 = 100;
+
+/// Purge the media which stayed unreferenced for longer than `grace_period`
+pub async fn run_media_gc(
+    pool: PgPool,
+    store: Arc<dyn MediaStore>,
+    grace_period: Duration,
+    gc_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(gc_interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(err) = collect_unreferenced_media(&pool, store.as_ref(), grace_period).await {
+            event!(
+                tracing::Level::ERROR,
+                "Failed to collect unreferenced media: {err:?}"
+            );
+        }
+    }
+}
+
+async fn collect_unreferenced_media(
+    pool: &PgPool,
+    store: &dyn MediaStore,
+    grace_period: Duration,
+) -> anyhow::Result<()> {
+    // the grace period starts when the media is first seen unreferenced
+    sqlx::query!(
+        r#"
+        UPDATE media AS m SET orphaned_at = now()
+        WHERE
+            m.orphaned_at IS NULL
+            AND NOT EXISTS(SELECT 1 FROM media_uploads WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM message_media WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM chats WHERE photo_media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM users WHERE photo_media_id = m.id)
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark unreferenced media")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE media AS m SET orphaned_at = NULL
+        WHERE
+            m.orphaned_at IS NOT NULL
+            AND (
+                EXISTS(SELECT 1 FROM media_uploads WHERE media_id = m.id)
+                OR EXISTS(SELECT 1 FROM message_media WHERE media_id = m.id)
+                OR EXISTS(SELECT 1 FROM chats WHERE photo_media_id = m.id)
+                OR EXISTS(SELECT 1 FROM users WHERE photo_media_id = m.id)
+            )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to unmark referenced media")?;
+
+    while purge_batch(pool, store, grace_period).await? == PURGE_BATCH_SIZE {}
+
+    Ok(())
+}
+
+/// Restart the grace period of the media referenced again
+///
+/// Called wherever a reference is inserted, so a media which becomes unreferenced later
+/// is kept for a whole grace period again
+pub async fn clear_orphaned(
+    media_ids: &[i64],
+    executor: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    if media_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE media SET orphaned_at = NULL WHERE id = ANY($1) AND orphaned_at IS NOT NULL",
+        media_ids,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to unmark referenced media")?;
+
+    Ok(())
+}
+
+/// Returns the number of purged media
+async fn purge_batch(
+    pool: &PgPool,
+    store: &dyn MediaStore,
+    grace_period: Duration,
+) -> anyhow::Result<i64> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // locked rows are skipped, so several instances can collect at the same time
+    let media = sqlx::query!(
+        r#"
+        SELECT m.id, m.sha256
+        FROM media AS m
+        WHERE
+            m.orphaned_at < now() - make_interval(secs => $1)
+            AND NOT EXISTS(SELECT 1 FROM media_uploads WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM message_media WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM chats WHERE photo_media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM users WHERE photo_media_id = m.id)
+        LIMIT $2
+        FOR UPDATE SKIP LOCKED
+        "#,
+        grace_period.as_secs_f64(),
+        PURGE_BATCH_SIZE,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query purgeable media")?;
+    if media.is_empty() {
+        return Ok(0);
+    }
+
+    let media: Vec<(i64, String)> = media.into_iter().map(|m| (m.id, m.sha256)).collect();
+    delete_media(&media, store, &mut transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(media.len() as i64)
+}
+
+/// Purge the given media right away if nothing references them anymore
+///
+/// Used when the messages they were attached to are deleted, media still referenced are kept
+pub async fn purge_unreferenced_media(
+    pool: &PgPool,
+    store: &dyn MediaStore,
+    media_ids: &[i64],
+) -> anyhow::Result<()> {
+    if media_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let media = sqlx::query!(
+        r#"
+        SELECT m.id, m.sha256
+        FROM media AS m
+        WHERE
+            m.id = ANY($1)
+            AND NOT EXISTS(SELECT 1 FROM media_uploads WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM message_media WHERE media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM chats WHERE photo_media_id = m.id)
+            AND NOT EXISTS(SELECT 1 FROM users WHERE photo_media_id = m.id)
+        FOR UPDATE SKIP LOCKED
+        "#,
+        media_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query unreferenced media")?;
+    if media.is_empty() {
+        return Ok(());
+    }
+
+    let media: Vec<(i64, String)> = media.into_iter().map(|m| (m.id, m.sha256)).collect();
+    delete_media(&media, store, &mut transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Delete the locked media rows with their thumbnails and blobs
+async fn delete_media(
+    media: &[(i64, String)],
+    store: &dyn MediaStore,
+    connection: &mut PgConnection,
+) -> anyhow::Result<()> {
+    let media_ids: Vec<i64> = media.iter().map(|(id, _)| *id).collect();
+    let thumbnails = sqlx::query!(
+        r#"
+        SELECT m.sha256, t.size
+        FROM media_thumbnails AS t
+        JOIN media AS m ON m.id = t.media_id
+        WHERE t.media_id = ANY($1)
+        "#,
+        &media_ids,
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .context("Failed to query thumbnails")?;
+
+    sqlx::query!("DELETE FROM media WHERE id = ANY($1)", &media_ids)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to delete media")?;
+
+    // the rows are kept locked until the blobs are gone, a failure retries on the next run
+    for thumbnail in thumbnails {
+        store
+            .delete(&thumbnail_key(&thumbnail.sha256, thumbnail.size as u32))
+            .await
+            .context("Failed to delete thumbnail")?;
+    }
+    for (_, sha256) in media {
+        store
+            .delete(&media_key(sha256))
+            .await
+            .context("Failed to delete media")?;
+    }
+
+    Ok(())
+}
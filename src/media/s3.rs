@@ -0,0 +1,69 @@
+use anyhow::Context;
+use bytes::Bytes;
+use s3::{Bucket, Region, creds::Credentials, error::S3Error};
+
+use super::MediaStore;
+
+/// Store the media in a S3 compatible object storage (AWS S3, MinIO...)
+pub struct S3MediaStore {
+    bucket: Box<Bucket>,
+}
+
+impl S3MediaStore {
+    /// Use a custom endpoint for S3 compatible storages, path style addressing is used then
+    pub fn new(
+        bucket: &str,
+        region: &str,
+        endpoint: Option<&str>,
+        access_key: &str,
+        secret_key: &str,
+    ) -> anyhow::Result<Self> {
+        let credentials = Credentials::new(Some(access_key), Some(secret_key), None, None, None)
+            .context("Failed to create S3 credentials")?;
+
+        let bucket = match endpoint {
+            Some(endpoint) => {
+                let region = Region::Custom {
+                    region: region.to_string(),
+                    endpoint: endpoint.to_string(),
+                };
+                Bucket::new(bucket, region, credentials)
+                    .context("Failed to create S3 bucket")?
+                    .with_path_style()
+            }
+            None => {
+                let region = region.parse().context("Invalid S3 region")?;
+                Bucket::new(bucket, region, credentials).context("Failed to create S3 bucket")?
+            }
+        };
+
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait::async_trait]
+impl MediaStore for S3MediaStore {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> anyhow::Result<()> {
+        self.bucket
+            .put_object_with_content_type(key, &data, content_type)
+            .await
+            .context("Failed to put S3 object")?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match self.bucket.get_object(key).await {
+            Ok(res) => Ok(Some(res.into_bytes())),
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Err(err) => Err(err).context("Failed to get S3 object"),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match self.bucket.delete_object(key).await {
+            Ok(_) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(()),
+            Err(err) => Err(err).context("Failed to delete S3 object"),
+        }
+    }
+}
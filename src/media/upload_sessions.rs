@@ -0,0 +1,103 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use tracing::event;
+
+use super::MediaStore;
+
+/// Storage key of a chunk of an unfinished upload session
+pub fn upload_chunk_key(session_id: i64, chunk_index: i32) -> String {
+    format!("uploads/{session_id}/{chunk_index}")
+}
+
+/// Number of chunks a file of the given size is split into
+pub fn chunk_count(total_size: i64, chunk_size: i32) -> i32 {
+    (total_size as u64).div_ceil(chunk_size as u64) as i32
+}
+
+/// Remove the upload sessions idle for longer than `expire` together with their chunks
+pub async fn run_upload_session_gc(
+    pool: PgPool,
+    store: Arc<dyn MediaStore>,
+    expire: Duration,
+    gc_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(gc_interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(err) = collect_expired_sessions(&pool, store.as_ref(), expire).await {
+            event!(
+                tracing::Level::ERROR,
+                "Failed to collect upload sessions: {err:?}"
+            );
+        }
+    }
+}
+
+async fn collect_expired_sessions(
+    pool: &PgPool,
+    store: &dyn MediaStore,
+    expire: Duration,
+) -> anyhow::Result<()> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // locked rows are skipped, so every session is collected by one instance only
+    let sessions = sqlx::query!(
+        r#"
+        SELECT id, total_size, chunk_size
+        FROM upload_sessions
+        WHERE updated_at < now() - make_interval(secs => $1)
+        FOR UPDATE SKIP LOCKED
+        "#,
+        expire.as_secs_f64(),
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query expired upload sessions")?;
+
+    // a session is deleted only after all of its chunks are gone, a failure retries on the next run
+    let mut collected = Vec::with_capacity(sessions.len());
+    for session in sessions {
+        if let Err(err) =
+            delete_chunks(session.id, session.total_size, session.chunk_size, store).await
+        {
+            event!(
+                tracing::Level::ERROR,
+                "Failed to delete the chunks of upload session {}: {err:?}",
+                session.id
+            );
+            continue;
+        }
+        collected.push(session.id);
+    }
+
+    sqlx::query!("DELETE FROM upload_sessions WHERE id = ANY($1)", &collected)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete expired upload sessions")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+async fn delete_chunks(
+    session_id: i64,
+    total_size: i64,
+    chunk_size: i32,
+    store: &dyn MediaStore,
+) -> anyhow::Result<()> {
+    for chunk_index in 0..chunk_count(total_size, chunk_size) {
+        store
+            .delete(&upload_chunk_key(session_id, chunk_index))
+            .await
+            .context("Failed to delete upload chunk")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use super::opus::{LoudnessDecoder, SILENCE_DB};
+
+/// Number of values of a waveform, shorter recordings get one value per packet
+pub const WAVEFORM_LENGTH: usize = 100;
+/// Values of a waveform are in the range 0-31
+pub const WAVEFORM_MAX: u8 = 31;
+
+/// Opus always counts the granule position in 48 kHz samples
+const OPUS_SAMPLE_RATE: i64 = 48000;
+/// An Opus packet holds at most 120 ms of audio
+const MAX_PACKET_SAMPLES: i64 = 5760;
+/// Levels this far below the loudest part of a recording are drawn as silence
+const DYNAMIC_RANGE_DB: f64 = 40.0;
+
+pub struct VoiceMetadata {
+    pub duration_ms: i32,
+    pub waveform: Vec<u8>,
+}
+
+/// Detect the duration and compute the waveform of an Ogg Opus recording
+///
+/// Returns None if the data is not a well-formed Ogg Opus stream
+pub fn process_voice(data: &[u8]) -> Option<VoiceMetadata> {
+    let mut serial = None;
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet_open = false;
+    let mut last_granule = 0;
+
+    let mut rest = data;
+    while !rest.is_empty() {
+        let page = OggPage::parse(&mut rest)?;
+
+        // only the first logical stream is read
+        if *serial.get_or_insert(page.serial) != page.serial {
+            continue;
+        }
+        if page.granule >= 0 {
+            last_granule = last_granule.max(page.granule);
+        }
+
+        let mut body = page.body;
+        for &lacing in page.lacing {
+            let (segment, remaining) = body.split_at_checked(lacing as usize)?;
+            body = remaining;
+
+            if packet_open {
+                packets.last_mut()?.extend_from_slice(segment);
+            } else {
+                packets.push(segment.to_vec());
+            }
+            // a packet continues in the next segment when the segment is full
+            packet_open = lacing == 255;
+        }
+    }
+
+    // the identification header and the comment header come first
+    let head = packets.first()?;
+    if head.len() < 19 || !head.starts_with(b"OpusHead") {
+        return None;
+    }
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]) as i64;
+    let audio_packets = packets.get(2..).unwrap_or_default();
+    if audio_packets.is_empty() {
+        return None;
+    }
+
+    // the granule position is not trusted beyond what the packets can hold
+    let samples = (last_granule - pre_skip).max(0);
+    if samples > (audio_packets.len() as i64).saturating_mul(MAX_PACKET_SAMPLES) {
+        return None;
+    }
+    let duration_ms = i32::try_from(samples.checked_mul(1000)? / OPUS_SAMPLE_RATE).ok()?;
+
+    Some(VoiceMetadata {
+        duration_ms,
+        waveform: waveform(&packet_levels(audio_packets)),
+    })
+}
+
+/// Loudness of the audio packets in dB
+///
+/// Packets which can't be measured keep the level of the previous one
+fn packet_levels(packets: &[Vec<u8>]) -> Vec<f64> {
+    let mut decoder = LoudnessDecoder::default();
+    let mut last_level = None;
+    let levels: Vec<Option<f64>> = packets
+        .iter()
+        .map(|packet| {
+            let level = decoder.packet_loudness(packet).or(last_level);
+            last_level = level;
+            level
+        })
+        .collect();
+
+    // the packets before the first measured one take its level
+    let first_level = levels
+        .iter()
+        .flatten()
+        .next()
+        .copied()
+        .unwrap_or(SILENCE_DB);
+    levels
+        .into_iter()
+        .map(|level| level.unwrap_or(first_level))
+        .collect()
+}
+
+/// Scale the peak level of each bin relative to the loudest bin
+fn waveform(levels: &[f64]) -> Vec<u8> {
+    let length = levels.len().min(WAVEFORM_LENGTH);
+    let peaks: Vec<f64> = (0..length)
+        .map(|i| {
+            levels[i * levels.len() / length..(i + 1) * levels.len() / length]
+                .iter()
+                .copied()
+                .fold(SILENCE_DB, f64::max)
+        })
+        .collect();
+
+    let max = peaks.iter().copied().fold(SILENCE_DB, f64::max);
+    let floor = (max - DYNAMIC_RANGE_DB).max(SILENCE_DB);
+    if max <= floor {
+        return vec![0; length];
+    }
+
+    peaks
+        .iter()
+        .map(|peak| ((peak - floor).max(0.0) / (max - floor) * WAVEFORM_MAX as f64).round() as u8)
+        .collect()
+}
+
+struct OggPage<'a> {
+    granule: i64,
+    serial: u32,
+    lacing: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> OggPage<'a> {
+    /// Parse the page at the start of `data` and advance `data` past it
+    fn parse(data: &mut &'a [u8]) -> Option<Self> {
+        let header = data.get(..27)?;
+        if &header[..4] != b"OggS" || header[4] != 0 {
+            return None;
+        }
+
+        let granule = i64::from_le_bytes(header[6..14].try_into().ok()?);
+        let serial = u32::from_le_bytes(header[14..18].try_into().ok()?);
+        let segments = header[26] as usize;
+        let lacing = data.get(27..27 + segments)?;
+        let body_length: usize = lacing.iter().map(|&lacing| lacing as usize).sum();
+        let body = data.get(27 + segments..27 + segments + body_length)?;
+
+        *data = &data[27 + segments + body_length..];
+
+        Some(Self {
+            granule,
+            serial,
+            lacing,
+            body,
+        })
+    }
+}
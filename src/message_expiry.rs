@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use tracing::event;
+
+use crate::{
+    media::{MediaStore, purge_unreferenced_media},
+    realtime::{Event, Hub},
+    routes::chats::load_participants,
+};
+
+/// Expired messages deleted by a single transaction
+const SWEEP_BATCH_SIZE: i64 = 100;
+
+/// Delete the messages once they expire, with the media nothing else references
+pub async fn run_message_sweeper(
+    pool: PgPool,
+    hub: Arc<Hub>,
+    store: Arc<dyn MediaStore>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(err) = sweep_expired_messages(&pool, &hub, store.as_ref()).await {
+            event!(
+                tracing::Level::ERROR,
+                "Failed to sweep expired messages: {err:?}"
+            );
+        }
+    }
+}
+
+async fn sweep_expired_messages(
+    pool: &PgPool,
+    hub: &Hub,
+    store: &dyn MediaStore,
+) -> anyhow::Result<()> {
+    while sweep_batch(pool, hub, store).await? == SWEEP_BATCH_SIZE {}
+
+    Ok(())
+}
+
+/// Returns the number of deleted messages
+async fn sweep_batch(pool: &PgPool, hub: &Hub, store: &dyn MediaStore) -> anyhow::Result<i64> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // locked rows are skipped, so several instances can sweep at the same time
+    let expired = sqlx::query!(
+        r#"
+        SELECT id, chat_id
+        FROM messages
+        WHERE expires_at <= now()
+        ORDER BY expires_at, id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        SWEEP_BATCH_SIZE,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query expired messages")?;
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    let message_ids: Vec<i64> = expired.iter().map(|message| message.id).collect();
+    let media_ids = sqlx::query_scalar!(
+        "SELECT DISTINCT media_id FROM message_media WHERE message_id = ANY($1)",
+        &message_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query attached media")?;
+
+    sqlx::query!("DELETE FROM messages WHERE id = ANY($1)", &message_ids)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete expired messages")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    let mut deleted: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for message in &expired {
+        deleted.entry(message.chat_id).or_default().push(message.id);
+    }
+    for (chat_id, message_ids) in deleted {
+        let participants = load_participants(chat_id, pool)
+            .await
+            .context("Failed to load participants")?;
+        hub.send_many(
+            &participants,
+            Event::MessagesDeleted {
+                chat_id,
+                message_ids,
+            },
+        );
+    }
+
+    // media still referenced by an upload or another message are kept
+    purge_unreferenced_media(pool, store, &media_ids).await?;
+
+    Ok(expired.len() as i64)
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::event;
+
+use crate::{
+    realtime::{Event, Hub},
+    routes::privacy::{PrivacyKey, filter_allowed},
+};
+
+/// Online status of the users connected to this instance
+///
+/// A user is online while holding a real-time connection, or for a while after an authenticated request
+pub struct Presence {
+    online_timeout: Duration,
+    last_seen_granularity: i64,
+    users: Mutex<HashMap<i64, UserPresence>>,
+    changes: mpsc::UnboundedSender<PresenceChange>,
+}
+
+struct UserPresence {
+    connections: usize,
+    last_activity: Instant,
+    /// Rounded unix timestamp of the last activity
+    last_seen: i64,
+    /// The last seen value written into the database
+    persisted_last_seen: Option<i64>,
+    online: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PresenceChange {
+    pub user_id: i64,
+    pub online: bool,
+    pub last_seen: i64,
+}
+
+impl Presence {
+    pub fn new(
+        online_timeout: Duration,
+        last_seen_granularity: Duration,
+    ) -> (Self, mpsc::UnboundedReceiver<PresenceChange>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let presence = Self {
+            online_timeout,
+            last_seen_granularity: last_seen_granularity.as_secs().max(1) as i64,
+            users: Mutex::new(HashMap::new()),
+            changes: tx,
+        };
+
+        (presence, rx)
+    }
+
+    /// Record an authenticated request of the user
+    pub fn touch(&self, user_id: i64) {
+        self.update(user_id, |_| {});
+    }
+
+    /// Record a new real-time connection of the user
+    pub fn connect(&self, user_id: i64) {
+        self.update(user_id, |user| user.connections += 1);
+    }
+
+    /// Record a closed real-time connection of the user
+    ///
+    /// The user stays online until the online timeout elapsed
+    pub fn disconnect(&self, user_id: i64) {
+        self.update(user_id, |user| {
+            user.connections = user.connections.saturating_sub(1)
+        });
+    }
+
+    pub fn is_online(&self, user_id: i64) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .is_some_and(|user| user.online)
+    }
+
+    /// Rounded unix timestamp of the last activity known by this instance
+    pub fn last_seen(&self, user_id: i64) -> Option<i64> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .map(|user| user.last_seen)
+    }
+
+    fn update(&self, user_id: i64, f: impl FnOnce(&mut UserPresence)) {
+        let last_seen = self.rounded_now();
+        let mut users = self.users.lock().unwrap();
+        let user = users.entry(user_id).or_insert_with(|| UserPresence {
+            connections: 0,
+            last_activity: Instant::now(),
+            last_seen,
+            persisted_last_seen: None,
+            online: false,
+        });
+
+        f(user);
+        user.last_activity = Instant::now();
+        user.last_seen = last_seen;
+
+        if !user.online {
+            user.online = true;
+            let _ = self.changes.send(PresenceChange {
+                user_id,
+                online: true,
+                last_seen,
+            });
+        }
+    }
+
+    /// Mark the idle users as offline
+    ///
+    /// Returns the last seen values which should be written into the database
+    fn sweep(&self) -> Vec<(i64, i64)> {
+        let now = Instant::now();
+        let rounded_now = self.rounded_now();
+        let mut users = self.users.lock().unwrap();
+        let mut dirty = Vec::new();
+
+        for (user_id, user) in users.iter_mut() {
+            if user.connections > 0 {
+                user.last_seen = rounded_now;
+            } else if user.online && now.duration_since(user.last_activity) >= self.online_timeout {
+                user.online = false;
+                let _ = self.changes.send(PresenceChange {
+                    user_id: *user_id,
+                    online: false,
+                    last_seen: user.last_seen,
+                });
+            }
+
+            if user.persisted_last_seen != Some(user.last_seen) {
+                user.persisted_last_seen = Some(user.last_seen);
+                dirty.push((*user_id, user.last_seen));
+            }
+        }
+
+        // offline users are remembered by the database only
+        users.retain(|_, user| user.online);
+
+        dirty
+    }
+
+    fn rounded_now(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time before unix epoch")
+            .as_secs() as i64;
+        now - now % self.last_seen_granularity
+    }
+}
+
+/// Persist the last seen values and push the presence changes to the contacts
+pub async fn run_presence_worker(
+    presence: Arc<Presence>,
+    mut changes: mpsc::UnboundedReceiver<PresenceChange>,
+    hub: Arc<Hub>,
+    pool: PgPool,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let Some(change) = change else {
+                    return;
+                };
+                if let Err(err) = broadcast_change(change, &hub, &pool).await {
+                    event!(tracing::Level::ERROR, "Failed to broadcast presence change: {err:?}");
+                }
+            }
+            _ = interval.tick() => {
+                let dirty = presence.sweep();
+                if let Err(err) = persist_last_seen(&dirty, &pool).await {
+                    event!(tracing::Level::ERROR, "Failed to persist last seen: {err:?}");
+                }
+            }
+        }
+    }
+}
+
+async fn persist_last_seen(dirty: &[(i64, i64)], pool: &PgPool) -> anyhow::Result<()> {
+    if dirty.is_empty() {
+        return Ok(());
+    }
+
+    let (user_ids, last_seen): (Vec<i64>, Vec<i64>) = dirty.iter().copied().unzip();
+    sqlx::query!(
+        r#"
+        UPDATE users SET last_seen_at = to_timestamp(data.last_seen)
+        FROM UNNEST($1::bigint[], $2::bigint[]) AS data(user_id, last_seen)
+        WHERE users.id = data.user_id
+        "#,
+        &user_ids,
+        &last_seen,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update last seen")?;
+
+    Ok(())
+}
+
+async fn broadcast_change(change: PresenceChange, hub: &Hub, pool: &PgPool) -> anyhow::Result<()> {
+    // users who have the changed user in their contacts
+    let watchers = sqlx::query_scalar!(
+        r#"
+        SELECT c.owner_id
+        FROM contacts AS c
+        WHERE
+            c.contact_id = $1
+            AND NOT EXISTS(
+                SELECT 1 FROM blocks
+                WHERE (blocker_id = $1 AND blocked_id = c.owner_id)
+                    OR (blocker_id = c.owner_id AND blocked_id = $1)
+            )
+        "#,
+        change.user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query watchers")?;
+
+    let watchers = filter_allowed(change.user_id, &watchers, PrivacyKey::LastSeen, pool)
+        .await
+        .context("Failed to check privacy rule")?;
+
+    hub.send_many(
+        &watchers,
+        Event::Presence {
+            user_id: change.user_id,
+            online: change.online,
+            last_seen_at: change.last_seen,
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Sliding window rate limiter keyed by user id
+///
+/// The state lives in the memory of the current instance
+pub struct RateLimiter {
+    max_requests: usize,
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    requests: HashMap<i64, VecDeque<Instant>>,
+    last_sweep: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: usize, interval: Duration) -> Self {
+        Self {
+            max_requests,
+            interval,
+            state: Mutex::new(State {
+                requests: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Record a request of the user
+    ///
+    /// Returns false if the user exceeded the limit, the rejected request is not recorded
+    pub fn check(&self, user_id: i64) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // drop the windows of idle users once per interval, so the map does not grow forever
+        if now.duration_since(state.last_sweep) >= self.interval {
+            state.requests.retain(|_, history| {
+                history
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < self.interval)
+            });
+            state.last_sweep = now;
+        }
+
+        let history = state.requests.entry(user_id).or_default();
+        while history
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.interval)
+        {
+            history.pop_front();
+        }
+
+        if history.len() >= self.max_requests {
+            return false;
+        }
+        history.push_back(now);
+
+        true
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::sync::mpsc;
+
+use crate::chat_actions::ChatAction;
+
+/// Event pushed to the clients through the real-time channel
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Presence {
+        user_id: i64,
+        online: bool,
+        /// Unix timestamp, rounded down
+        last_seen_at: i64,
+    },
+    ChatAction {
+        chat_id: i64,
+        user_id: i64,
+        action: ChatAction,
+        /// Seconds until the action is cancelled by the server, 0 for cancel
+        expires_in: u64,
+    },
+    Reaction {
+        chat_id: i64,
+        message_id: i64,
+        user_id: i64,
+        emoji: String,
+        /// False when the reaction is removed
+        added: bool,
+    },
+    MessagesDeleted {
+        chat_id: i64,
+        message_ids: Vec<i64>,
+    },
+    /// Sent to the applicant
+    JoinRequestDecided {
+        chat_id: i64,
+        approved: bool,
+    },
+    /// Sent to the member kicked or banned from the chat
+    RemovedFromChat {
+        chat_id: i64,
+        banned: bool,
+    },
+    PollUpdate {
+        chat_id: i64,
+        message_id: i64,
+        /// Votes of each option
+        votes: Vec<i64>,
+        total_voters: i64,
+        closed: bool,
+    },
+}
+
+/// Registry of the real-time connections of this instance
+#[derive(Default)]
+pub struct Hub {
+    next_connection_id: AtomicU64,
+    connections: Mutex<HashMap<i64, HashMap<u64, mpsc::UnboundedSender<Event>>>>,
+}
+
+impl Hub {
+    /// Register a new connection of the user
+    ///
+    /// Returns the connection id and the receiver of the events for this connection
+    pub fn connect(&self, user_id: i64) -> (u64, mpsc::UnboundedReceiver<Event>) {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(user_id)
+            .or_default()
+            .insert(connection_id, tx);
+
+        (connection_id, rx)
+    }
+
+    pub fn disconnect(&self, user_id: i64, connection_id: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(user_connections) = connections.get_mut(&user_id) {
+            user_connections.remove(&connection_id);
+            if user_connections.is_empty() {
+                connections.remove(&user_id);
+            }
+        }
+    }
+
+    /// Push an event to every connection of the user
+    pub fn send(&self, user_id: i64, event: Event) {
+        let connections = self.connections.lock().unwrap();
+        if let Some(user_connections) = connections.get(&user_id) {
+            for tx in user_connections.values() {
+                // the receiver is dropped when the connection is closing
+                let _ = tx.send(event.clone());
+            }
+        }
+    }
+
+    pub fn send_many(&self, user_ids: &[i64], event: Event) {
+        for user_id in user_ids {
+            self.send(*user_id, event.clone());
+        }
+    }
+}
@@ -1,5 +1,64 @@
-mod chats;
+pub(crate) mod admin_log;
+mod admins;
+pub(crate) mod anti_spam;
+mod auto_delete;
+mod blocks;
+mod chat_actions;
+mod chat_info;
+pub(crate) mod chats;
+mod contacts;
+pub(crate) mod invite_links;
+mod join_requests;
+mod media;
+mod message_search;
+pub(crate) mod messages;
+pub(crate) mod moderation;
+mod pins;
+pub(crate) mod polls;
+mod presence;
+pub(crate) mod privacy;
+mod reactions;
+mod scheduled_messages;
+mod topics;
+mod updates;
+mod upload_sessions;
 mod user;
 
-pub use chats::create_pm;
-pub use user::{login, register};
+pub use admin_log::get_admin_log;
+pub use admins::{demote_admin, list_admins, promote_member, transfer_ownership};
+pub use anti_spam::{get_anti_spam, set_anti_spam};
+pub use auto_delete::{get_auto_delete, set_auto_delete};
+pub use blocks::{block_user, list_blocked_users, unblock_user};
+pub use chat_actions::send_chat_action;
+pub use chat_info::{get_chat_info, set_chat_description, set_chat_photo, set_chat_title};
+pub use chats::{add_member, create_group, create_pm, leave_chat};
+pub use contacts::{add_contact, list_contacts, remove_contact};
+pub use invite_links::{
+    create_invite_link, join_chat, list_invite_link_joins, list_invite_links, revoke_invite_link,
+};
+pub use join_requests::{approve_join_requests, decline_join_requests, list_join_requests};
+pub use media::{
+    delete_media, download_media, download_thumbnail, get_media_info, get_storage_usage,
+    upload_media,
+};
+pub use message_search::{search_all_messages, search_chat_messages};
+pub use messages::{delete_message, get_chat_history, send_message};
+pub use moderation::{
+    ban_member, kick_member, list_bans, list_restrictions, restrict_member, unban_member,
+};
+pub use pins::{list_pinned_messages, pin_message, unpin_message};
+pub use polls::{close_poll, retract_vote, vote_poll};
+pub use presence::get_presence;
+pub use privacy::{list_privacy, set_privacy};
+pub use reactions::{add_reaction, get_allowed_reactions, remove_reaction, set_allowed_reactions};
+pub use scheduled_messages::{
+    cancel_scheduled_message, edit_scheduled_message, list_scheduled_messages, schedule_message,
+};
+pub use topics::{
+    close_topic, create_topic, list_topics, read_topic, rename_topic, reopen_topic, toggle_topics,
+};
+pub use updates::updates;
+pub use upload_sessions::{
+    create_upload_session, finalize_upload_session, get_upload_session, upload_chunk,
+};
+pub use user::{login, register, search_users, set_profile_photo, update_profile};
@@ -0,0 +1,278 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgExecutor, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        anti_spam::FloodLimit,
+        auto_delete::AutoDeletePeriod,
+        chats::{AdminRights, load_participant},
+        moderation::Permissions,
+    },
+};
+
+/// Administrative action recorded in the admin log of the chat
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminLogAction {
+    KickMember {
+        user_id: i64,
+    },
+    /// Unix timestamp, banned forever if None
+    BanMember {
+        user_id: i64,
+        until: Option<i64>,
+    },
+    UnbanMember {
+        user_id: i64,
+    },
+    /// An empty set of permissions when the restriction is lifted
+    RestrictMember {
+        user_id: i64,
+        permissions: Permissions,
+        until: Option<i64>,
+    },
+    PromoteMember {
+        user_id: i64,
+        rights: AdminRights,
+        custom_title: Option<String>,
+    },
+    DemoteAdmin {
+        user_id: i64,
+    },
+    TransferOwnership {
+        user_id: i64,
+    },
+    ChangeTitle {
+        title: String,
+    },
+    ChangeDescription {
+        description: Option<String>,
+    },
+    ChangePhoto {
+        media_id: Option<i64>,
+    },
+    DeleteMessage {
+        message_id: i64,
+        sender_id: i64,
+    },
+    PinMessage {
+        message_id: i64,
+    },
+    UnpinMessage {
+        message_id: i64,
+    },
+    CreateInviteLink {
+        link_id: i64,
+    },
+    RevokeInviteLink {
+        link_id: i64,
+    },
+    ToggleTopics {
+        enabled: bool,
+    },
+    RenameTopic {
+        topic_id: i64,
+        title: String,
+    },
+    CloseTopic {
+        topic_id: i64,
+    },
+    ReopenTopic {
+        topic_id: i64,
+    },
+    AddMember {
+        user_id: i64,
+    },
+    ApproveJoinRequests {
+        user_ids: Vec<i64>,
+    },
+    DeclineJoinRequests {
+        user_ids: Vec<i64>,
+    },
+    /// Slow mode interval in seconds, disabled if None
+    ChangeAntiSpam {
+        slow_mode: Option<i32>,
+        flood_limit: Option<FloodLimit>,
+    },
+    /// New messages are kept if None
+    ChangeAutoDelete {
+        period: Option<AutoDeletePeriod>,
+    },
+    /// Every emoji is allowed if None
+    ChangeAllowedReactions {
+        allowed: Option<Vec<String>>,
+    },
+}
+
+impl AdminLogAction {
+    /// The `type` tags of the actions, the admin log can be filtered by them
+    pub const TYPES: [&str; 25] = [
+        "kick_member",
+        "ban_member",
+        "unban_member",
+        "restrict_member",
+        "promote_member",
+        "demote_admin",
+        "transfer_ownership",
+        "change_title",
+        "change_description",
+        "change_photo",
+        "delete_message",
+        "pin_message",
+        "unpin_message",
+        "create_invite_link",
+        "revoke_invite_link",
+        "toggle_topics",
+        "rename_topic",
+        "close_topic",
+        "reopen_topic",
+        "add_member",
+        "approve_join_requests",
+        "decline_join_requests",
+        "change_anti_spam",
+        "change_auto_delete",
+        "change_allowed_reactions",
+    ];
+}
+
+#[derive(serde::Deserialize)]
+pub struct AdminLogQuery {
+    /// Only actions of this type
+    action: Option<String>,
+    /// Only actions taken by this admin
+    actor_id: Option<i64>,
+    /// Only entries older than this entry are returned
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AdminLogEntry {
+    pub entry_id: i64,
+    pub actor_id: i64,
+    pub action: AdminLogAction,
+    /// Unix timestamp
+    pub created_at: i64,
+}
+
+/// Administrative actions of the chat, the newest first
+#[instrument(name = "Get admin log", skip(query, pool, credentials))]
+pub async fn get_admin_log(
+    path: web::Path<i64>,
+    query: web::Query<AdminLogQuery>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AdminLogError> {
+    let chat_id = path.into_inner();
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    if let Some(action) = &query.action
+        && !AdminLogAction::TYPES.contains(&action.as_str())
+    {
+        return Err(AdminLogError::UnknownAction);
+    }
+
+    // every admin can review the others, whatever their rights
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AdminLogError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(AdminLogError::NotGroup);
+    }
+    if !participant.is_admin() {
+        return Err(AdminLogError::NoPermission);
+    }
+
+    let entries: Vec<AdminLogEntry> = sqlx::query!(
+        r#"
+        SELECT id, actor_id, action AS "action: sqlx::types::Json<AdminLogAction>", created_at
+        FROM chat_admin_log
+        WHERE
+            chat_id = $1
+            AND ($2::text IS NULL OR action_type = $2)
+            AND ($3::bigint IS NULL OR actor_id = $3)
+            AND ($4::bigint IS NULL OR id < $4)
+        ORDER BY id DESC
+        LIMIT $5
+        "#,
+        chat_id,
+        query.action,
+        query.actor_id,
+        query.before_id,
+        limit,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query admin log")?
+    .into_iter()
+    .map(|row| AdminLogEntry {
+        entry_id: row.id,
+        actor_id: row.actor_id,
+        action: row.action.0,
+        created_at: row.created_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "entries": entries,
+    })))
+}
+
+/// Record an action of the admin in the admin log of the chat
+pub(crate) async fn record_admin_action(
+    chat_id: i64,
+    actor_id: i64,
+    action: AdminLogAction,
+    executor: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO chat_admin_log (chat_id, actor_id, action) VALUES ($1, $2, $3)",
+        chat_id,
+        actor_id,
+        sqlx::types::Json(action) as _,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to insert admin log entry")?;
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdminLogError {
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to read the admin log")]
+    NoPermission,
+    #[error("Unknown action type")]
+    UnknownAction,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AdminLogError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminLogError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AdminLogError::NotGroup | AdminLogError::UnknownAction => StatusCode::BAD_REQUEST,
+            AdminLogError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AdminLogError::UnknownError(_) => "Internal Server Error",
+            AdminLogError::NotGroup => "Chat is not a group",
+            AdminLogError::NoPermission => "No permission to read the admin log",
+            AdminLogError::UnknownAction => "Unknown action type",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
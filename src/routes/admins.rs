@@ -0,0 +1,399 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::{BearerAuth, normalize_password, verify_password},
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, AdminRights, load_participant},
+    },
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// Characters of the custom title of an admin
+const MAX_CUSTOM_TITLE_LENGTH: usize = 16;
+
+#[derive(serde::Serialize)]
+pub struct ChatAdmin {
+    pub user_id: i64,
+    pub role: String,
+    pub custom_title: Option<String>,
+    pub rights: AdminRights,
+}
+
+/// The owner and the admins of the chat
+#[instrument(name = "List chat admins", skip(pool, credentials))]
+pub async fn list_admins(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AdminError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(AdminError::NoPermission);
+    }
+
+    let admins: Vec<ChatAdmin> = sqlx::query!(
+        r#"
+        SELECT user_id, role AS "role!", custom_title, permission
+        FROM chat_participants
+        WHERE chat_id = $1 AND role IN ('owner', 'admin')
+        ORDER BY role = 'owner' DESC, added_at, user_id
+        "#,
+        chat_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query admins")?
+    .into_iter()
+    .map(|row| ChatAdmin {
+        user_id: row.user_id,
+        // the owner has every right
+        rights: if row.role == "owner" {
+            AdminRights::from(AdminRight::ALL.to_vec())
+        } else {
+            AdminRights(row.permission)
+        },
+        role: row.role,
+        custom_title: row.custom_title,
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "admins": admins,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PromoteMemberModel {
+    user_id: i64,
+    rights: AdminRights,
+    /// None or blank removes the custom title
+    custom_title: Option<String>,
+}
+
+/// Make the member an admin, or change the rights of an admin
+///
+/// The restrictions of the member are lifted
+#[instrument(name = "Promote member", skip(payload, pool, credentials))]
+pub async fn promote_member(
+    path: web::Path<i64>,
+    payload: Json<PromoteMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AdminError> {
+    let chat_id = path.into_inner();
+    let custom_title = payload
+        .custom_title
+        .as_deref()
+        .map(str::trim)
+        .filter(|title| !title.is_empty());
+
+    check_owner(chat_id, credentials.user_id, &pool).await?;
+    check_target(chat_id, payload.user_id, &pool).await?;
+
+    if custom_title.is_some_and(|title| title.chars().count() > MAX_CUSTOM_TITLE_LENGTH) {
+        return Err(AdminError::BadCustomTitle);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE chat_participants SET role = 'admin', permission = $3, custom_title = $4
+        WHERE chat_id = $1 AND user_id = $2 AND role <> 'owner'
+        "#,
+        chat_id,
+        payload.user_id,
+        payload.rights.0,
+        custom_title,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update participant")?;
+    if res.rows_affected() == 0 {
+        return Err(AdminError::NotMember);
+    }
+
+    sqlx::query!(
+        "DELETE FROM chat_restrictions WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete restriction")?;
+    sqlx::query!(
+        "DELETE FROM chat_flood_restrictions WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete flood restriction")?;
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::PromoteMember {
+            user_id: payload.user_id,
+            rights: payload.rights,
+            custom_title: custom_title.map(str::to_string),
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(ChatAdmin {
+        user_id: payload.user_id,
+        role: "admin".to_string(),
+        custom_title: custom_title.map(str::to_string),
+        rights: payload.rights,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DemoteAdminModel {
+    user_id: i64,
+}
+
+/// Take the admin rights and the custom title away
+#[instrument(name = "Demote admin", skip(payload, pool, credentials))]
+pub async fn demote_admin(
+    path: web::Path<i64>,
+    payload: Json<DemoteAdminModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AdminError> {
+    let chat_id = path.into_inner();
+
+    check_owner(chat_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE chat_participants SET role = 'member', permission = 0, custom_title = NULL
+        WHERE chat_id = $1 AND user_id = $2 AND role = 'admin'
+        "#,
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update participant")?;
+    if res.rows_affected() == 0 {
+        return Err(AdminError::NotAdmin);
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::DemoteAdmin {
+            user_id: payload.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": payload.user_id,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TransferOwnershipModel {
+    user_id: i64,
+    /// The password of the owner, to confirm the transfer
+    password: String,
+}
+
+/// Hand the chat over to one of the admins
+///
+/// The previous owner stays as an admin with every right
+#[instrument(name = "Transfer ownership", skip(payload, pool, credentials))]
+pub async fn transfer_ownership(
+    path: web::Path<i64>,
+    payload: Json<TransferOwnershipModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AdminError> {
+    let chat_id = path.into_inner();
+
+    check_owner(chat_id, credentials.user_id, &pool).await?;
+
+    let hashed_password = sqlx::query_scalar!(
+        "SELECT password FROM users WHERE id = $1",
+        credentials.user_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to query user")?;
+    let password = normalize_password(&payload.password);
+    let verified =
+        spawn_blocking_with_tracing(move || verify_password(&password, &hashed_password))
+            .await
+            .context("Failed to spawn password verify task")?
+            .context("Failed to verify password")?;
+    if !verified {
+        return Err(AdminError::WrongPassword);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // step down first, a chat never has two owners
+    let res = sqlx::query!(
+        r#"
+        UPDATE chat_participants SET role = 'admin', permission = $3
+        WHERE chat_id = $1 AND user_id = $2 AND role = 'owner'
+        "#,
+        chat_id,
+        credentials.user_id,
+        AdminRights::from(AdminRight::ALL.to_vec()).0,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update previous owner")?;
+    if res.rows_affected() == 0 {
+        return Err(AdminError::NoPermission);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE chat_participants SET role = 'owner', permission = 0
+        WHERE chat_id = $1 AND user_id = $2 AND role = 'admin'
+        "#,
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update new owner")?;
+    if res.rows_affected() == 0 {
+        return Err(AdminError::NotAdmin);
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::TransferOwnership {
+            user_id: payload.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "owner_id": payload.user_id,
+    })))
+}
+
+/// Only the owner of groups and channels manages the admins
+async fn check_owner(chat_id: i64, user_id: i64, pool: &PgPool) -> Result<(), AdminError> {
+    let Some(participant) = load_participant(chat_id, user_id, pool)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AdminError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(AdminError::NotGroup);
+    }
+    if participant.role.as_deref() != Some("owner") {
+        return Err(AdminError::NoPermission);
+    }
+
+    Ok(())
+}
+
+/// The member to promote must not be the owner
+async fn check_target(chat_id: i64, user_id: i64, pool: &PgPool) -> Result<(), AdminError> {
+    let Some(participant) = load_participant(chat_id, user_id, pool)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AdminError::NotMember);
+    };
+    if participant.role.as_deref() == Some("owner") {
+        return Err(AdminError::Owner);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error("User is not a member")]
+    NotMember,
+    #[error("User is not an admin")]
+    NotAdmin,
+    #[error("Custom title too long")]
+    BadCustomTitle,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("Cannot change the rights of the owner")]
+    Owner,
+    #[error("Wrong password")]
+    WrongPassword,
+    #[error("No permission to manage the admins")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AdminError::NotMember => StatusCode::NOT_FOUND,
+            AdminError::NotAdmin
+            | AdminError::BadCustomTitle
+            | AdminError::NotGroup
+            | AdminError::Owner => StatusCode::BAD_REQUEST,
+            AdminError::WrongPassword | AdminError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AdminError::UnknownError(_) => "Internal Server Error",
+            AdminError::NotMember => "User is not a member",
+            AdminError::NotAdmin => "User is not an admin",
+            AdminError::BadCustomTitle => "The custom title must be at most 16 characters",
+            AdminError::NotGroup => "Chat is not a group",
+            AdminError::Owner => "Cannot change the rights of the owner",
+            AdminError::WrongPassword => "Wrong password",
+            AdminError::NoPermission => "No permission to manage the admins",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
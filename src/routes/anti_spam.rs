@@ -0,0 +1,298 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use sqlx::{PgConnection, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, load_participant},
+        messages::SendMessageError,
+    },
+};
+
+/// Longest slow mode interval in seconds, an hour
+const MAX_SLOW_MODE_INTERVAL: i32 = 60 * 60;
+/// Longest window of the flood limit in seconds, an hour
+const MAX_FLOOD_INTERVAL: i32 = 60 * 60;
+/// Longest restriction of a flooder in seconds, a week
+const MAX_FLOOD_RESTRICTION: i32 = 7 * 24 * 60 * 60;
+
+/// Members sending more than `max_messages` in `interval` seconds
+/// cannot send messages for `restrict_for` seconds
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct FloodLimit {
+    pub max_messages: i32,
+    pub interval: i32,
+    pub restrict_for: i32,
+}
+
+impl FloodLimit {
+    fn is_valid(&self) -> bool {
+        (1..=100).contains(&self.max_messages)
+            && (1..=MAX_FLOOD_INTERVAL).contains(&self.interval)
+            && (1..=MAX_FLOOD_RESTRICTION).contains(&self.restrict_for)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct AntiSpamSettings {
+    /// Seconds a member waits between two messages, None disables the slow mode
+    slow_mode: Option<i32>,
+    flood_limit: Option<FloodLimit>,
+}
+
+#[instrument(name = "Get anti-spam settings", skip(pool, credentials))]
+pub async fn get_anti_spam(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AntiSpamError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(AntiSpamError::NoPermission);
+    }
+
+    let chat = sqlx::query!(
+        r#"
+        SELECT slow_mode_interval, flood_max_messages, flood_interval, flood_restrict_for
+        FROM chats
+        WHERE id = $1
+        "#,
+        chat_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to query anti-spam settings")?;
+
+    let flood_limit = match (
+        chat.flood_max_messages,
+        chat.flood_interval,
+        chat.flood_restrict_for,
+    ) {
+        (Some(max_messages), Some(interval), Some(restrict_for)) => Some(FloodLimit {
+            max_messages,
+            interval,
+            restrict_for,
+        }),
+        _ => None,
+    };
+
+    Ok(HttpResponse::Ok().json(AntiSpamSettings {
+        slow_mode: chat.slow_mode_interval,
+        flood_limit,
+    }))
+}
+
+/// Replace the slow mode and the flood limit of the chat
+#[instrument(name = "Set anti-spam settings", skip(payload, pool, credentials))]
+pub async fn set_anti_spam(
+    path: web::Path<i64>,
+    payload: Json<AntiSpamSettings>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AntiSpamError> {
+    let chat_id = path.into_inner();
+    let settings = payload.into_inner();
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AntiSpamError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(AntiSpamError::NotGroup);
+    }
+    if !participant.has_right(AdminRight::BanUsers) {
+        return Err(AntiSpamError::NoPermission);
+    }
+
+    if let Some(interval) = settings.slow_mode
+        && !(1..=MAX_SLOW_MODE_INTERVAL).contains(&interval)
+    {
+        return Err(AntiSpamError::BadSlowMode);
+    }
+    if let Some(flood_limit) = &settings.flood_limit
+        && !flood_limit.is_valid()
+    {
+        return Err(AntiSpamError::BadFloodLimit);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE chats
+        SET slow_mode_interval = $2, flood_max_messages = $3, flood_interval = $4, flood_restrict_for = $5
+        WHERE id = $1
+        "#,
+        chat_id,
+        settings.slow_mode,
+        settings.flood_limit.map(|limit| limit.max_messages),
+        settings.flood_limit.map(|limit| limit.interval),
+        settings.flood_limit.map(|limit| limit.restrict_for),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update anti-spam settings")?;
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::ChangeAntiSpam {
+            slow_mode: settings.slow_mode,
+            flood_limit: settings.flood_limit,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Reject the message of a member who posts too fast, admins are exempt
+///
+/// A member exceeding the flood limit is restricted from sending messages.
+/// The membership stays locked until the transaction ends, so the messages sent at the same time are counted
+pub(crate) async fn check_send_rate(
+    chat_id: i64,
+    sender_id: i64,
+    connection: &mut PgConnection,
+) -> Result<(), SendMessageError> {
+    // the rate is queried by a later statement to see the messages committed while waiting for the lock
+    let locked = sqlx::query!(
+        r#"
+        SELECT 1 AS "locked"
+        FROM chat_participants
+        WHERE chat_id = $1 AND user_id = $2
+        FOR NO KEY UPDATE
+        "#,
+        chat_id,
+        sender_id,
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .context("Failed to lock participant")?;
+    if locked.is_none() {
+        return Err(SendMessageError::NoPermission);
+    }
+
+    let rate = sqlx::query!(
+        r#"
+        SELECT
+            c.type = 'private' OR COALESCE(cp.role IN ('owner', 'admin'), false) AS "exempt!",
+            c.slow_mode_interval IS NOT NULL AND EXISTS(
+                SELECT 1 FROM messages
+                WHERE
+                    sender_id = $2 AND chat_id = $1 AND kind <> 'service'
+                    AND created_at > now() - make_interval(secs => c.slow_mode_interval)
+            ) AS "too_soon!",
+            c.flood_max_messages,
+            c.flood_restrict_for,
+            (
+                SELECT COUNT(*) FROM messages
+                WHERE
+                    sender_id = $2 AND chat_id = $1 AND kind <> 'service'
+                    AND created_at > now() - make_interval(secs => c.flood_interval)
+            ) AS "recent_messages!"
+        FROM chats AS c
+        JOIN chat_participants AS cp ON cp.chat_id = c.id AND cp.user_id = $2
+        WHERE c.id = $1
+        "#,
+        chat_id,
+        sender_id,
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .context("Failed to query send rate")?;
+
+    if rate.exempt {
+        return Ok(());
+    }
+    if rate.too_soon {
+        return Err(SendMessageError::SlowMode);
+    }
+
+    if let (Some(max_messages), Some(restrict_for)) =
+        (rate.flood_max_messages, rate.flood_restrict_for)
+        && rate.recent_messages >= max_messages as i64
+    {
+        // the restrictions of the admins are left alone, each one expires on its own
+        sqlx::query!(
+            r#"
+            INSERT INTO chat_flood_restrictions (chat_id, user_id, until)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET until = GREATEST(chat_flood_restrictions.until, EXCLUDED.until), created_at = now()
+            "#,
+            chat_id,
+            sender_id,
+            restrict_for as f64,
+        )
+        .execute(&mut *connection)
+        .await
+        .context("Failed to restrict flooder")?;
+
+        return Err(SendMessageError::Flooding);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AntiSpamError {
+    #[error("Slow mode interval out of range")]
+    BadSlowMode,
+    #[error("Invalid flood limit")]
+    BadFloodLimit,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to change the anti-spam settings")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AntiSpamError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AntiSpamError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AntiSpamError::BadSlowMode | AntiSpamError::BadFloodLimit | AntiSpamError::NotGroup => {
+                StatusCode::BAD_REQUEST
+            }
+            AntiSpamError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AntiSpamError::UnknownError(_) => "Internal Server Error",
+            AntiSpamError::BadSlowMode => {
+                "The slow mode interval must be between 1 second and 1 hour"
+            }
+            AntiSpamError::BadFloodLimit => {
+                "A flood limit needs 1-100 messages in at most 1 hour and a restriction of at most 7 days"
+            }
+            AntiSpamError::NotGroup => "Chat is not a group",
+            AntiSpamError::NoPermission => "No permission to change the anti-spam settings",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,174 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        blocks::is_blocked_between,
+        chats::{AdminRight, load_other_participants, load_participant},
+    },
+};
+
+/// Period after which new messages of the chat are deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoDeletePeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl AutoDeletePeriod {
+    pub fn as_secs(&self) -> i32 {
+        match self {
+            AutoDeletePeriod::Day => 24 * 60 * 60,
+            AutoDeletePeriod::Week => 7 * 24 * 60 * 60,
+            AutoDeletePeriod::Month => 30 * 24 * 60 * 60,
+        }
+    }
+
+    fn from_secs(secs: i32) -> Option<Self> {
+        [Self::Day, Self::Week, Self::Month]
+            .into_iter()
+            .find(|period| period.as_secs() == secs)
+    }
+}
+
+#[instrument(name = "Get auto-delete period", skip(pool, credentials))]
+pub async fn get_auto_delete(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AutoDeleteError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(AutoDeleteError::NoPermission);
+    }
+
+    let period = sqlx::query_scalar!("SELECT auto_delete_after FROM chats WHERE id = $1", chat_id)
+        .fetch_one(pool.as_ref())
+        .await
+        .context("Failed to query auto-delete period")?
+        .and_then(AutoDeletePeriod::from_secs);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "period": period,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AutoDeleteModel {
+    /// None keeps new messages until they are deleted
+    period: Option<AutoDeletePeriod>,
+}
+
+/// Set the period after which new messages of the chat are deleted
+///
+/// Messages sent before the change keep their expiry
+#[instrument(name = "Set auto-delete period", skip(payload, pool, credentials))]
+pub async fn set_auto_delete(
+    path: web::Path<i64>,
+    payload: Json<AutoDeleteModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AutoDeleteError> {
+    let chat_id = path.into_inner();
+    let period = payload.into_inner().period;
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AutoDeleteError::NoPermission);
+    };
+
+    // admins can change it in groups, both sides can change it in private chats
+    if participant.chat_type == "private" {
+        let peer_id = load_other_participants(chat_id, credentials.user_id, pool.as_ref())
+            .await
+            .context("Failed to query peer user")?
+            .pop();
+
+        if let Some(peer_id) = peer_id
+            && is_blocked_between(credentials.user_id, peer_id, pool.as_ref())
+                .await
+                .context("Failed to query blocks")?
+        {
+            return Err(AutoDeleteError::NoPermission);
+        }
+    } else if !participant.has_right(AdminRight::ChangeInfo) {
+        return Err(AutoDeleteError::NoPermission);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        "UPDATE chats SET auto_delete_after = $2 WHERE id = $1",
+        chat_id,
+        period.map(|period| period.as_secs()),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update auto-delete period")?;
+
+    // private chats have no admin log
+    if participant.chat_type != "private" {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ChangeAutoDelete { period },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "period": period,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AutoDeleteError {
+    #[error("No permission to change the auto-delete period")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AutoDeleteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AutoDeleteError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AutoDeleteError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AutoDeleteError::UnknownError(_) => "Internal Server Error",
+            AutoDeleteError::NoPermission => "No permission to change the auto-delete period",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
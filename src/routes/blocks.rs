@@ -0,0 +1,181 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgExecutor, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::user::{UserProfile, hide_profile_photos, load_user_by_username},
+};
+
+#[derive(serde::Deserialize)]
+pub struct BlockModel {
+    username: String,
+}
+
+#[instrument(name = "Block user", skip(payload, pool, credentials))]
+pub async fn block_user(
+    payload: Json<BlockModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, BlockError> {
+    let Some(blocked_id) = load_user_by_username(&payload.username, &pool)
+        .await
+        .context("Failed to load blocked user")?
+    else {
+        return Err(BlockError::UserNotFound);
+    };
+
+    if blocked_id == credentials.user_id {
+        return Err(BlockError::BlockSelf);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO blocks (blocker_id, blocked_id) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        credentials.user_id,
+        blocked_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to insert block")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "user_id": blocked_id,
+    })))
+}
+
+#[instrument(name = "Unblock user", skip(payload, pool, credentials))]
+pub async fn unblock_user(
+    payload: Json<BlockModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, BlockError> {
+    let Some(blocked_id) = load_user_by_username(&payload.username, &pool)
+        .await
+        .context("Failed to load blocked user")?
+    else {
+        return Err(BlockError::UserNotFound);
+    };
+
+    let res = sqlx::query!(
+        "DELETE FROM blocks WHERE blocker_id = $1 AND blocked_id = $2",
+        credentials.user_id,
+        blocked_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete block")?;
+
+    if res.rows_affected() == 0 {
+        return Err(BlockError::NotBlocked);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": blocked_id,
+    })))
+}
+
+#[instrument(name = "List blocked users", skip(pool, credentials))]
+pub async fn list_blocked_users(
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, BlockError> {
+    let mut users = sqlx::query_as!(
+        UserProfile,
+        r#"
+        SELECT u.id, u.username, u.display_name, u.photo_media_id
+        FROM blocks AS b
+        JOIN users AS u ON b.blocked_id = u.id
+        WHERE b.blocker_id = $1
+        ORDER BY b.created_at
+        "#,
+        credentials.user_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query blocked users")?;
+    hide_profile_photos(&mut users, credentials.user_id, &pool)
+        .await
+        .context("Failed to check profile photo privacy")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": users,
+    })))
+}
+
+/// Check whether `blocker_id` has blocked `blocked_id`
+pub async fn has_blocked(
+    blocker_id: i64,
+    blocked_id: i64,
+    pool: &PgPool,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM blocks WHERE blocker_id = $1 AND blocked_id = $2) AS "exists!""#,
+        blocker_id,
+        blocked_id,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Check whether any of the two users has blocked the other one
+pub async fn is_blocked_between(
+    user_id: i64,
+    peer_id: i64,
+    executor: impl PgExecutor<'_>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM blocks
+            WHERE (blocker_id = $1 AND blocked_id = $2) OR (blocker_id = $2 AND blocked_id = $1)
+        ) AS "exists!"
+        "#,
+        user_id,
+        peer_id,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlockError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("User is not blocked")]
+    NotBlocked,
+    #[error("Cannot block yourself")]
+    BlockSelf,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for BlockError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BlockError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            BlockError::UserNotFound | BlockError::NotBlocked | BlockError::BlockSelf => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            BlockError::UnknownError(_) => "Internal Server Error",
+            BlockError::UserNotFound => "User not found",
+            BlockError::NotBlocked => "User is not blocked",
+            BlockError::BlockSelf => "Cannot block yourself",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
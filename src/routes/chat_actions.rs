@@ -0,0 +1,105 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    chat_actions::{ChatAction, ChatActions},
+    error::response_error,
+    realtime::Hub,
+    routes::{
+        blocks::is_blocked_between,
+        chats::{load_other_participants, load_participant},
+        moderation::{Permission, load_restrictions},
+    },
+};
+
+#[derive(serde::Deserialize)]
+pub struct ChatActionModel {
+    action: ChatAction,
+}
+
+#[instrument(
+    name = "Send chat action",
+    skip(payload, pool, credentials, hub, chat_actions)
+)]
+pub async fn send_chat_action(
+    path: web::Path<i64>,
+    payload: Json<ChatActionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+    chat_actions: web::Data<ChatActions>,
+) -> Result<HttpResponse, ChatActionError> {
+    let chat_id = path.into_inner();
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(ChatActionError::NoPermission);
+    };
+
+    let recipients = load_other_participants(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participants")?;
+
+    if participant.chat_type == "private"
+        && let Some(peer_id) = recipients.first()
+        && is_blocked_between(credentials.user_id, *peer_id, pool.as_ref())
+            .await
+            .context("Failed to query blocks")?
+    {
+        return Err(ChatActionError::NoPermission);
+    }
+
+    // members who cannot write should not look like they do
+    if participant.chat_type != "private"
+        && load_restrictions(chat_id, credentials.user_id, pool.as_ref())
+            .await
+            .context("Failed to load restrictions")?
+            .contains(Permission::SendMessages)
+    {
+        return Err(ChatActionError::NoPermission);
+    }
+
+    chat_actions.into_inner().start(
+        chat_id,
+        credentials.user_id,
+        payload.action,
+        recipients,
+        hub.into_inner(),
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatActionError {
+    #[error("No permission to send actions into the chat")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ChatActionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ChatActionError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ChatActionError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ChatActionError::UnknownError(_) => "Internal Server Error",
+            ChatActionError::NoPermission => "No permission to send actions into the chat",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
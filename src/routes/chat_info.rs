@@ -0,0 +1,356 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    media::clear_orphaned,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, load_participant},
+        messages::{ServiceAction, insert_service_message},
+    },
+};
+
+/// Characters of the title of a chat
+const MAX_TITLE_LENGTH: usize = 128;
+/// Characters of the description of a chat
+const MAX_DESCRIPTION_LENGTH: usize = 255;
+
+#[derive(serde::Serialize)]
+pub struct ChatInfo {
+    pub chat_id: i64,
+    #[serde(rename = "type")]
+    pub chat_type: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub photo_media_id: Option<i64>,
+    pub topics_enabled: bool,
+    pub member_count: i64,
+}
+
+#[instrument(name = "Get chat info", skip(pool, credentials))]
+pub async fn get_chat_info(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ChatInfoError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(ChatInfoError::NoPermission);
+    }
+
+    let chat = sqlx::query_as!(
+        ChatInfo,
+        r#"
+        SELECT
+            c.id AS chat_id,
+            c.type AS chat_type,
+            c.title,
+            c.description,
+            c.photo_media_id,
+            c.topics_enabled,
+            (SELECT COUNT(*) FROM chat_participants WHERE chat_id = c.id) AS "member_count!"
+        FROM chats AS c
+        WHERE c.id = $1
+        "#,
+        chat_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to query chat")?;
+
+    Ok(HttpResponse::Ok().json(chat))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetTitleModel {
+    title: String,
+}
+
+#[instrument(name = "Set chat title", skip(payload, pool, credentials))]
+pub async fn set_chat_title(
+    path: web::Path<i64>,
+    payload: Json<SetTitleModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ChatInfoError> {
+    let chat_id = path.into_inner();
+    let title = payload.title.trim();
+
+    check_change_info(chat_id, credentials.user_id, &pool).await?;
+
+    if !(1..=MAX_TITLE_LENGTH).contains(&title.chars().count()) {
+        return Err(ChatInfoError::BadTitle);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // setting the same title again records nothing
+    let res = sqlx::query!(
+        "UPDATE chats SET title = $2 WHERE id = $1 AND title IS DISTINCT FROM $2",
+        chat_id,
+        title,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update title")?;
+
+    let service_message_id = if res.rows_affected() > 0 {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ChangeTitle {
+                title: title.to_string(),
+            },
+            &mut *transaction,
+        )
+        .await?;
+        Some(
+            insert_service_message(
+                chat_id,
+                credentials.user_id,
+                ServiceAction::ChangeTitle {
+                    title: title.to_string(),
+                },
+                &mut *transaction,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "title": title,
+        "service_message_id": service_message_id,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetDescriptionModel {
+    /// None or blank removes the description
+    description: Option<String>,
+}
+
+#[instrument(name = "Set chat description", skip(payload, pool, credentials))]
+pub async fn set_chat_description(
+    path: web::Path<i64>,
+    payload: Json<SetDescriptionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ChatInfoError> {
+    let chat_id = path.into_inner();
+    let description = payload
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|description| !description.is_empty());
+
+    check_change_info(chat_id, credentials.user_id, &pool).await?;
+
+    if description.is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(ChatInfoError::BadDescription);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "UPDATE chats SET description = $2 WHERE id = $1 AND description IS DISTINCT FROM $2",
+        chat_id,
+        description,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update description")?;
+
+    if res.rows_affected() > 0 {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ChangeDescription {
+                description: description.map(str::to_string),
+            },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "description": description,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetPhotoModel {
+    /// An image uploaded by the admin, None removes the photo
+    media_id: Option<i64>,
+}
+
+#[instrument(name = "Set chat photo", skip(payload, pool, credentials))]
+pub async fn set_chat_photo(
+    path: web::Path<i64>,
+    payload: Json<SetPhotoModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ChatInfoError> {
+    let chat_id = path.into_inner();
+    let media_id = payload.media_id;
+
+    check_change_info(chat_id, credentials.user_id, &pool).await?;
+
+    if let Some(media_id) = media_id {
+        let Some(content_type) = sqlx::query_scalar!(
+            r#"
+            SELECT m.content_type
+            FROM media AS m
+            JOIN media_uploads AS mu ON mu.media_id = m.id
+            WHERE m.id = $1 AND mu.user_id = $2
+            "#,
+            media_id,
+            credentials.user_id,
+        )
+        .fetch_optional(pool.as_ref())
+        .await
+        .context("Failed to query media")?
+        else {
+            return Err(ChatInfoError::MediaNotFound);
+        };
+        if !content_type.starts_with("image/") {
+            return Err(ChatInfoError::NotImage);
+        }
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "UPDATE chats SET photo_media_id = $2 WHERE id = $1 AND photo_media_id IS DISTINCT FROM $2",
+        chat_id,
+        media_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update photo")?;
+    if let Some(media_id) = media_id {
+        clear_orphaned(&[media_id], &mut *transaction).await?;
+    }
+
+    let service_message_id = if res.rows_affected() > 0 {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ChangePhoto { media_id },
+            &mut *transaction,
+        )
+        .await?;
+        Some(
+            insert_service_message(
+                chat_id,
+                credentials.user_id,
+                ServiceAction::ChangePhoto { media_id },
+                &mut *transaction,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "photo_media_id": media_id,
+        "service_message_id": service_message_id,
+    })))
+}
+
+/// Only admins with the right to change the info edit groups and channels
+async fn check_change_info(chat_id: i64, user_id: i64, pool: &PgPool) -> Result<(), ChatInfoError> {
+    let Some(participant) = load_participant(chat_id, user_id, pool)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(ChatInfoError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(ChatInfoError::NotGroup);
+    }
+    if !participant.has_right(AdminRight::ChangeInfo) {
+        return Err(ChatInfoError::NoPermission);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatInfoError {
+    #[error("Title length not match the requirement")]
+    BadTitle,
+    #[error("Description too long")]
+    BadDescription,
+    #[error("Media not found")]
+    MediaNotFound,
+    #[error("Media is not an image")]
+    NotImage,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to change the chat info")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ChatInfoError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ChatInfoError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ChatInfoError::BadTitle
+            | ChatInfoError::BadDescription
+            | ChatInfoError::MediaNotFound
+            | ChatInfoError::NotImage
+            | ChatInfoError::NotGroup => StatusCode::BAD_REQUEST,
+            ChatInfoError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ChatInfoError::UnknownError(_) => "Internal Server Error",
+            ChatInfoError::BadTitle => "The title must be 1-128 characters",
+            ChatInfoError::BadDescription => "The description must be at most 255 characters",
+            ChatInfoError::MediaNotFound => "Media not found",
+            ChatInfoError::NotImage => "The chat photo must be an image",
+            ChatInfoError::NotGroup => "Chat is not a group",
+            ChatInfoError::NoPermission => "No permission to change the chat info",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -5,10 +5,21 @@ use actix_web::{
 };
 use anyhow::Context;
 use serde_json::json;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use tracing::instrument;
 
-use crate::{auth::BearerAuth, error::response_error, routes::user::load_user_by_username};
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        blocks::has_blocked,
+        messages::{ServiceAction, insert_service_message},
+        moderation::is_banned,
+        privacy::{PrivacyKey, is_allowed},
+        user::load_user_by_username,
+    },
+};
 
 #[derive(serde::Deserialize)]
 pub struct CreatePMModel {
@@ -29,6 +40,14 @@ pub async fn create_pm(
         return Err(CreatePMError::PeerNotFound);
     };
 
+    // the peer does not want to talk to us
+    if has_blocked(peer_id, credentials.user_id, &pool)
+        .await
+        .context("Failed to query blocks")?
+    {
+        return Err(CreatePMError::Blocked);
+    }
+
     // find the exist pm
     let chat_id: i64 = match sqlx::query!(
         r#"
@@ -58,7 +77,21 @@ LIMIT 1;
     .map(|chat| chat.chat_id)
     {
         Some(id) => id,
-        None => create_pm_chat(credentials.user_id, peer_id, &pool).await?,
+        None => {
+            // only new chats are restricted by the privacy settings
+            if !is_allowed(
+                peer_id,
+                credentials.user_id,
+                PrivacyKey::PrivateChats,
+                &pool,
+            )
+            .await
+            .context("Failed to check privacy rule")?
+            {
+                return Err(CreatePMError::PrivacyRestricted);
+            }
+            create_pm_chat(credentials.user_id, peer_id, &pool).await?
+        }
     };
 
     Ok(HttpResponse::Created().json(json!({
@@ -92,6 +125,10 @@ async fn create_pm_chat(user_id: i64, peer_id: i64, pool: &PgPool) -> Result<i64
 pub enum CreatePMError {
     #[error("Peer user not found")]
     PeerNotFound,
+    #[error("Blocked by the peer user")]
+    Blocked,
+    #[error("Restricted by the privacy settings of the peer user")]
+    PrivacyRestricted,
     #[error("Unknown error: {0}")]
     UnknownError(#[from] anyhow::Error),
 }
@@ -101,6 +138,7 @@ impl ResponseError for CreatePMError {
         match self {
             CreatePMError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             CreatePMError::PeerNotFound => StatusCode::BAD_REQUEST,
+            CreatePMError::Blocked | CreatePMError::PrivacyRestricted => StatusCode::FORBIDDEN,
         }
     }
 
@@ -108,7 +146,453 @@ impl ResponseError for CreatePMError {
         let msg = match self {
             CreatePMError::UnknownError(_) => "Internal Server Error",
             CreatePMError::PeerNotFound => "Peer user not found",
+            CreatePMError::Blocked | CreatePMError::PrivacyRestricted => {
+                "Cannot start a private chat with this user"
+            }
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[instrument(name = "Create group", skip(pool, credentials))]
+pub async fn create_group(
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, CreateGroupError> {
+    let chat_id = sqlx::query_scalar!(
+        r#"
+        WITH new_chat AS (
+            INSERT INTO chats (type) VALUES ('group')
+            RETURNING id
+        )
+        INSERT INTO chat_participants (chat_id, user_id, role)
+        SELECT id, $1, 'owner' FROM new_chat
+        RETURNING chat_id
+        "#,
+        credentials.user_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to insert chat entity")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "chat_id": chat_id,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CreateGroupError {
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for CreateGroupError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CreateGroupError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            CreateGroupError::UnknownError(_) => "Internal Server Error",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddMemberModel {
+    username: String,
+}
+
+#[instrument(name = "Add group member", skip(payload, pool, credentials))]
+pub async fn add_member(
+    path: web::Path<i64>,
+    payload: Json<AddMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AddMemberError> {
+    let chat_id = path.into_inner();
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AddMemberError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(AddMemberError::NotGroup);
+    }
+    if !participant.has_right(AdminRight::InviteUsers) {
+        return Err(AddMemberError::NoPermission);
+    }
+
+    let Some(user_id) = load_user_by_username(&payload.username, &pool)
+        .await
+        .context("Failed to load user")?
+    else {
+        return Err(AddMemberError::UserNotFound);
+    };
+
+    if has_blocked(user_id, credentials.user_id, &pool)
+        .await
+        .context("Failed to query blocks")?
+        || !is_allowed(
+            user_id,
+            credentials.user_id,
+            PrivacyKey::GroupInvites,
+            &pool,
+        )
+        .await
+        .context("Failed to check privacy rule")?
+    {
+        return Err(AddMemberError::PrivacyRestricted);
+    }
+
+    // the ban must be lifted explicitly
+    if is_banned(chat_id, user_id, pool.as_ref())
+        .await
+        .context("Failed to query bans")?
+    {
+        return Err(AddMemberError::Banned);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO chat_participants (chat_id, user_id, role) VALUES ($1, $2, 'member')
+        ON CONFLICT DO NOTHING
+        "#,
+        chat_id,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert participant")?;
+
+    if res.rows_affected() == 0 {
+        return Err(AddMemberError::AlreadyMember);
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::AddMember { user_id },
+        &mut *transaction,
+    )
+    .await?;
+    insert_service_message(
+        chat_id,
+        credentials.user_id,
+        ServiceAction::AddMember { user_id },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "user_id": user_id,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AddMemberError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("User is already a member")]
+    AlreadyMember,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to add members")]
+    NoPermission,
+    #[error("Restricted by the privacy settings of the user")]
+    PrivacyRestricted,
+    #[error("User is banned from the chat")]
+    Banned,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AddMemberError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AddMemberError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AddMemberError::UserNotFound
+            | AddMemberError::AlreadyMember
+            | AddMemberError::NotGroup => StatusCode::BAD_REQUEST,
+            AddMemberError::NoPermission
+            | AddMemberError::PrivacyRestricted
+            | AddMemberError::Banned => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AddMemberError::UnknownError(_) => "Internal Server Error",
+            AddMemberError::UserNotFound => "User not found",
+            AddMemberError::AlreadyMember => "User is already a member",
+            AddMemberError::NotGroup => "Chat is not a group",
+            AddMemberError::NoPermission => "No permission to add members",
+            AddMemberError::PrivacyRestricted => "Cannot add this user to groups",
+            AddMemberError::Banned => "User is banned from the chat, unban them first",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+/// Leave the group, the owner transfers the ownership first
+#[instrument(name = "Leave chat", skip(pool, credentials))]
+pub async fn leave_chat(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, LeaveChatError> {
+    let chat_id = path.into_inner();
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(LeaveChatError::NotMember);
+    };
+    if participant.chat_type == "private" {
+        return Err(LeaveChatError::NotGroup);
+    }
+    if participant.role.as_deref() == Some("owner") {
+        return Err(LeaveChatError::Owner);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2 AND role <> 'owner'",
+        chat_id,
+        credentials.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete participant")?;
+    if res.rows_affected() == 0 {
+        return Err(LeaveChatError::NotMember);
+    }
+
+    insert_service_message(
+        chat_id,
+        credentials.user_id,
+        ServiceAction::RemoveMember {
+            user_id: credentials.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LeaveChatError {
+    #[error("User is not a member")]
+    NotMember,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("The owner cannot leave the chat before transferring the ownership")]
+    Owner,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for LeaveChatError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LeaveChatError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            LeaveChatError::NotMember | LeaveChatError::NotGroup | LeaveChatError::Owner => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            LeaveChatError::UnknownError(_) => "Internal Server Error",
+            LeaveChatError::NotMember => "User is not a member",
+            LeaveChatError::NotGroup => "Chat is not a group",
+            LeaveChatError::Owner => "Transfer the ownership to another admin before leaving",
         };
         response_error(self.status_code(), msg)
     }
 }
+
+/// Rights granted to the admins, the owner has all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRight {
+    /// Change the title, the description, the photo and the chat settings
+    ChangeInfo,
+    /// Kick, ban and restrict members, set the anti-spam limits
+    BanUsers,
+    /// Add members, manage invite links and join requests
+    InviteUsers,
+    /// Pin and unpin messages
+    PinMessages,
+    /// Delete the messages of others
+    DeleteMessages,
+    /// Rename, close and reopen the topics of others, write into closed topics
+    ManageTopics,
+}
+
+impl AdminRight {
+    pub(crate) const ALL: [AdminRight; 6] = [
+        AdminRight::ChangeInfo,
+        AdminRight::BanUsers,
+        AdminRight::InviteUsers,
+        AdminRight::PinMessages,
+        AdminRight::DeleteMessages,
+        AdminRight::ManageTopics,
+    ];
+
+    fn bit(self) -> i32 {
+        1 << self as i32
+    }
+}
+
+/// Set of admin rights, stored as a bitmask and serialized as a list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(from = "Vec<AdminRight>", into = "Vec<AdminRight>")]
+pub struct AdminRights(pub(crate) i32);
+
+impl AdminRights {
+    pub fn contains(self, right: AdminRight) -> bool {
+        self.0 & right.bit() != 0
+    }
+}
+
+impl From<Vec<AdminRight>> for AdminRights {
+    fn from(rights: Vec<AdminRight>) -> Self {
+        AdminRights(rights.into_iter().fold(0, |bits, r| bits | r.bit()))
+    }
+}
+
+impl From<AdminRights> for Vec<AdminRight> {
+    fn from(rights: AdminRights) -> Self {
+        AdminRight::ALL
+            .into_iter()
+            .filter(|r| rights.contains(*r))
+            .collect()
+    }
+}
+
+pub struct Participant {
+    pub chat_type: String,
+    pub role: Option<String>,
+    /// Bitmask of the admin rights
+    pub permission: i32,
+}
+
+impl Participant {
+    pub fn is_admin(&self) -> bool {
+        matches!(self.role.as_deref(), Some("owner" | "admin"))
+    }
+
+    /// The owner has every right, the other admins the ones granted to them
+    pub fn has_right(&self, right: AdminRight) -> bool {
+        match self.role.as_deref() {
+            Some("owner") => true,
+            Some("admin") => AdminRights(self.permission).contains(right),
+            _ => false,
+        }
+    }
+}
+
+/// Check that the user is an admin of the group with the right
+pub(crate) async fn check_group_admin(
+    chat_id: i64,
+    user_id: i64,
+    right: AdminRight,
+    executor: impl PgExecutor<'_>,
+) -> Result<(), GroupAdminError> {
+    let Some(participant) = load_participant(chat_id, user_id, executor)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(GroupAdminError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(GroupAdminError::NotGroup);
+    }
+    if !participant.has_right(right) {
+        return Err(GroupAdminError::NoPermission);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GroupAdminError {
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("Missing the admin right")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+/// Load the membership of the user in the chat
+///
+/// Returns None if the chat does not exist or the user is not a participant
+pub async fn load_participant(
+    chat_id: i64,
+    user_id: i64,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<Participant>, sqlx::Error> {
+    sqlx::query_as!(
+        Participant,
+        r#"
+        SELECT c.type AS chat_type, cp.role, cp.permission
+        FROM chats AS c
+        JOIN chat_participants AS cp ON cp.chat_id = c.id
+        WHERE c.id = $1 AND cp.user_id = $2
+        "#,
+        chat_id,
+        user_id,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Load the ids of the participants of the chat except the user
+pub async fn load_other_participants(
+    chat_id: i64,
+    user_id: i64,
+    executor: impl PgExecutor<'_>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM chat_participants WHERE chat_id = $1 AND user_id <> $2",
+        chat_id,
+        user_id,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Load the ids of all the participants of the chat
+pub async fn load_participants(chat_id: i64, pool: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM chat_participants WHERE chat_id = $1",
+        chat_id,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,146 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::user::{UserProfile, hide_profile_photos, load_user_by_username},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ContactModel {
+    username: String,
+}
+
+#[instrument(name = "Add contact", skip(payload, pool, credentials))]
+pub async fn add_contact(
+    payload: Json<ContactModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ContactError> {
+    let Some(contact_id) = load_user_by_username(&payload.username, &pool)
+        .await
+        .context("Failed to load contact user")?
+    else {
+        return Err(ContactError::UserNotFound);
+    };
+
+    if contact_id == credentials.user_id {
+        return Err(ContactError::AddSelf);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO contacts (owner_id, contact_id) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        credentials.user_id,
+        contact_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to insert contact")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "user_id": contact_id,
+    })))
+}
+
+#[instrument(name = "Remove contact", skip(payload, pool, credentials))]
+pub async fn remove_contact(
+    payload: Json<ContactModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ContactError> {
+    let Some(contact_id) = load_user_by_username(&payload.username, &pool)
+        .await
+        .context("Failed to load contact user")?
+    else {
+        return Err(ContactError::UserNotFound);
+    };
+
+    let res = sqlx::query!(
+        "DELETE FROM contacts WHERE owner_id = $1 AND contact_id = $2",
+        credentials.user_id,
+        contact_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete contact")?;
+
+    if res.rows_affected() == 0 {
+        return Err(ContactError::ContactNotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": contact_id,
+    })))
+}
+
+#[instrument(name = "List contacts", skip(pool, credentials))]
+pub async fn list_contacts(
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ContactError> {
+    let mut contacts = sqlx::query_as!(
+        UserProfile,
+        r#"
+        SELECT u.id, u.username, u.display_name, u.photo_media_id
+        FROM contacts AS c
+        JOIN users AS u ON c.contact_id = u.id
+        WHERE c.owner_id = $1
+        ORDER BY c.added_at
+        "#,
+        credentials.user_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query contacts")?;
+    hide_profile_photos(&mut contacts, credentials.user_id, &pool)
+        .await
+        .context("Failed to check profile photo privacy")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "contacts": contacts,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContactError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Contact not found")]
+    ContactNotFound,
+    #[error("Cannot add yourself as a contact")]
+    AddSelf,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ContactError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ContactError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ContactError::UserNotFound | ContactError::ContactNotFound | ContactError::AddSelf => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ContactError::UnknownError(_) => "Internal Server Error",
+            ContactError::UserNotFound => "User not found",
+            ContactError::ContactNotFound => "Contact not found",
+            ContactError::AddSelf => "Cannot add yourself as a contact",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
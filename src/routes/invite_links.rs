@@ -0,0 +1,576 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, GroupAdminError, check_group_admin},
+        messages::{ServiceAction, insert_service_message},
+        moderation::is_banned,
+    },
+};
+
+/// Most users who can join through a single link
+const MAX_LINK_USES: i32 = 100_000;
+/// Links expire at most a year ahead
+const MAX_LINK_LIFETIME: i64 = 365 * 24 * 60 * 60;
+/// Characters of the message attached to a join request
+const MAX_JOIN_REQUEST_MESSAGE_LENGTH: usize = 256;
+
+#[derive(serde::Deserialize)]
+pub struct CreateInviteLinkModel {
+    /// Unix timestamp
+    expires_at: Option<i64>,
+    max_uses: Option<i32>,
+    /// Users who open the link only ask to join
+    #[serde(default)]
+    requires_approval: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct InviteLink {
+    pub link_id: i64,
+    pub chat_id: i64,
+    pub token: String,
+    pub created_by: i64,
+    /// Unix timestamp
+    pub expires_at: Option<i64>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub requires_approval: bool,
+    pub revoked: bool,
+    /// Unix timestamp
+    pub created_at: i64,
+}
+
+#[instrument(name = "Create invite link", skip(payload, pool, credentials))]
+pub async fn create_invite_link(
+    path: web::Path<i64>,
+    payload: Json<CreateInviteLinkModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, InviteLinkError> {
+    let chat_id = path.into_inner();
+    let payload = payload.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::InviteUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if let Some(expires_at) = payload.expires_at
+        && (expires_at <= now || expires_at > now + MAX_LINK_LIFETIME)
+    {
+        return Err(InviteLinkError::BadExpiry);
+    }
+    if let Some(max_uses) = payload.max_uses
+        && !(1..=MAX_LINK_USES).contains(&max_uses)
+    {
+        return Err(InviteLinkError::BadMaxUses);
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let link = sqlx::query!(
+        r#"
+        INSERT INTO invite_links (chat_id, token, created_by, expires_at, max_uses, requires_approval)
+        VALUES ($1, $2, $3, to_timestamp($4), $5, $6)
+        RETURNING id, created_at
+        "#,
+        chat_id,
+        token,
+        credentials.user_id,
+        payload.expires_at.map(|expires_at| expires_at as f64),
+        payload.max_uses,
+        payload.requires_approval,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to insert invite link")?;
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::CreateInviteLink { link_id: link.id },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Created().json(InviteLink {
+        link_id: link.id,
+        chat_id,
+        token,
+        created_by: credentials.user_id,
+        expires_at: payload.expires_at,
+        max_uses: payload.max_uses,
+        uses: 0,
+        requires_approval: payload.requires_approval,
+        revoked: false,
+        created_at: link.created_at.unix_timestamp(),
+    }))
+}
+
+/// Invite links of the chat, the newest first
+#[instrument(name = "List invite links", skip(pool, credentials))]
+pub async fn list_invite_links(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, InviteLinkError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::InviteUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let links: Vec<InviteLink> = sqlx::query!(
+        r#"
+        SELECT
+            id, token, created_by, expires_at, max_uses, uses, requires_approval,
+            revoked_at IS NOT NULL AS "revoked!",
+            created_at
+        FROM invite_links
+        WHERE chat_id = $1
+        ORDER BY id DESC
+        "#,
+        chat_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query invite links")?
+    .into_iter()
+    .map(|row| InviteLink {
+        link_id: row.id,
+        chat_id,
+        token: row.token,
+        created_by: row.created_by,
+        expires_at: row.expires_at.map(|time| time.unix_timestamp()),
+        max_uses: row.max_uses,
+        uses: row.uses,
+        requires_approval: row.requires_approval,
+        revoked: row.revoked,
+        created_at: row.created_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "links": links,
+    })))
+}
+
+/// Nobody can join through the link anymore, its statistics are kept
+#[instrument(name = "Revoke invite link", skip(pool, credentials))]
+pub async fn revoke_invite_link(
+    path: web::Path<(i64, i64)>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, InviteLinkError> {
+    let (chat_id, link_id) = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::InviteUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE invite_links SET revoked_at = now()
+        WHERE id = $1 AND chat_id = $2 AND revoked_at IS NULL
+        "#,
+        link_id,
+        chat_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to revoke invite link")?;
+    if res.rows_affected() == 0 {
+        return Err(InviteLinkError::LinkNotFound);
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::RevokeInviteLink { link_id },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "link_id": link_id,
+    })))
+}
+
+#[derive(serde::Serialize)]
+pub struct InviteLinkJoin {
+    pub user_id: i64,
+    /// Unix timestamp
+    pub joined_at: i64,
+}
+
+/// Users who joined through the link, the latest first
+#[instrument(name = "List invite link joins", skip(pool, credentials))]
+pub async fn list_invite_link_joins(
+    path: web::Path<(i64, i64)>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, InviteLinkError> {
+    let (chat_id, link_id) = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::InviteUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let Some(uses) = sqlx::query_scalar!(
+        "SELECT uses FROM invite_links WHERE id = $1 AND chat_id = $2",
+        link_id,
+        chat_id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to query invite link")?
+    else {
+        return Err(InviteLinkError::LinkNotFound);
+    };
+
+    let joins: Vec<InviteLinkJoin> = sqlx::query!(
+        r#"
+        SELECT user_id, joined_at
+        FROM invite_link_joins
+        WHERE link_id = $1
+        ORDER BY joined_at DESC, user_id
+        "#,
+        link_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query invite link joins")?
+    .into_iter()
+    .map(|row| InviteLinkJoin {
+        user_id: row.user_id,
+        joined_at: row.joined_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "link_id": link_id,
+        "uses": uses,
+        "joins": joins,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InviteLinkError {
+    #[error("Invite link not found")]
+    LinkNotFound,
+    #[error("Expiry time not in the next year")]
+    BadExpiry,
+    #[error("Max uses out of range")]
+    BadMaxUses,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to manage invite links")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl From<GroupAdminError> for InviteLinkError {
+    fn from(err: GroupAdminError) -> Self {
+        match err {
+            GroupAdminError::NotGroup => InviteLinkError::NotGroup,
+            GroupAdminError::NoPermission => InviteLinkError::NoPermission,
+            GroupAdminError::UnknownError(err) => InviteLinkError::UnknownError(err),
+        }
+    }
+}
+
+impl ResponseError for InviteLinkError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            InviteLinkError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            InviteLinkError::LinkNotFound => StatusCode::NOT_FOUND,
+            InviteLinkError::BadExpiry
+            | InviteLinkError::BadMaxUses
+            | InviteLinkError::NotGroup => StatusCode::BAD_REQUEST,
+            InviteLinkError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            InviteLinkError::UnknownError(_) => "Internal Server Error",
+            InviteLinkError::LinkNotFound => "Invite link not found",
+            InviteLinkError::BadExpiry => "The expiry time must be within the next year",
+            InviteLinkError::BadMaxUses => "The max uses must be between 1 and 100000",
+            InviteLinkError::NotGroup => "Chat is not a group",
+            InviteLinkError::NoPermission => "No permission to manage invite links",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct JoinChatModel {
+    token: String,
+    /// Shown to the admins when the link requires approval
+    message: Option<String>,
+}
+
+/// Join the chat through an invite link
+///
+/// A link which requires approval files a join request instead, the admins decide on it
+#[instrument(name = "Join chat by link", skip(payload, pool, credentials))]
+pub async fn join_chat(
+    payload: Json<JoinChatModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, JoinChatError> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // the link is locked, so concurrent joins cannot exceed the max uses
+    let Some(link) = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            chat_id,
+            requires_approval,
+            expires_at IS NOT NULL AND expires_at <= now() AS "expired!",
+            max_uses IS NOT NULL AND uses >= max_uses AS "used_up!"
+        FROM invite_links
+        WHERE token = $1 AND revoked_at IS NULL
+        FOR UPDATE
+        "#,
+        payload.token,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to query invite link")?
+    else {
+        return Err(JoinChatError::LinkNotFound);
+    };
+    if link.expired || link.used_up {
+        return Err(JoinChatError::LinkExpired);
+    }
+    if is_banned(link.chat_id, credentials.user_id, &mut *transaction)
+        .await
+        .context("Failed to query bans")?
+    {
+        return Err(JoinChatError::Banned);
+    }
+    if link.requires_approval {
+        return request_to_join(
+            link.chat_id,
+            link.id,
+            credentials.user_id,
+            payload.into_inner().message,
+            transaction,
+        )
+        .await;
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO chat_participants (chat_id, user_id, role) VALUES ($1, $2, 'member')
+        ON CONFLICT DO NOTHING
+        "#,
+        link.chat_id,
+        credentials.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert participant")?;
+    if res.rows_affected() == 0 {
+        return Err(JoinChatError::AlreadyMember);
+    }
+
+    record_link_join(link.id, credentials.user_id, &mut transaction).await?;
+    insert_service_message(
+        link.chat_id,
+        credentials.user_id,
+        ServiceAction::AddMember {
+            user_id: credentials.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": link.chat_id,
+    })))
+}
+
+async fn request_to_join(
+    chat_id: i64,
+    link_id: i64,
+    user_id: i64,
+    message: Option<String>,
+    mut transaction: Transaction<'_, Postgres>,
+) -> Result<HttpResponse, JoinChatError> {
+    if let Some(message) = &message
+        && message.chars().count() > MAX_JOIN_REQUEST_MESSAGE_LENGTH
+    {
+        return Err(JoinChatError::BadMessage);
+    }
+
+    let is_member = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM chat_participants WHERE chat_id = $1 AND user_id = $2
+        ) AS "exists!"
+        "#,
+        chat_id,
+        user_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to query participant")?;
+    if is_member {
+        return Err(JoinChatError::AlreadyMember);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO join_requests (chat_id, user_id, link_id, message) VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+        chat_id,
+        user_id,
+        link_id,
+        message,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert join request")?;
+    if res.rows_affected() == 0 {
+        return Err(JoinChatError::AlreadyRequested);
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "chat_id": chat_id,
+    })))
+}
+
+/// Count the use of the link by the user who just joined
+pub(crate) async fn record_link_join(
+    link_id: i64,
+    user_id: i64,
+    connection: &mut PgConnection,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE invite_links SET uses = uses + 1 WHERE id = $1",
+        link_id,
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to count invite link use")?;
+
+    // a user rejoining through the same link is counted again
+    sqlx::query!(
+        r#"
+        INSERT INTO invite_link_joins (link_id, user_id) VALUES ($1, $2)
+        ON CONFLICT (link_id, user_id) DO UPDATE SET joined_at = now()
+        "#,
+        link_id,
+        user_id,
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to insert invite link join")?;
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JoinChatError {
+    #[error("Invite link not found")]
+    LinkNotFound,
+    #[error("Invite link expired")]
+    LinkExpired,
+    #[error("Join request message too long")]
+    BadMessage,
+    #[error("User is already a member")]
+    AlreadyMember,
+    #[error("User already requested to join")]
+    AlreadyRequested,
+    #[error("User is banned from the chat")]
+    Banned,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for JoinChatError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            JoinChatError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            JoinChatError::LinkNotFound => StatusCode::NOT_FOUND,
+            JoinChatError::LinkExpired
+            | JoinChatError::BadMessage
+            | JoinChatError::AlreadyMember
+            | JoinChatError::AlreadyRequested => StatusCode::BAD_REQUEST,
+            JoinChatError::Banned => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            JoinChatError::UnknownError(_) => "Internal Server Error",
+            JoinChatError::LinkNotFound => "Invite link not found",
+            JoinChatError::LinkExpired => "The invite link expired or reached its max uses",
+            JoinChatError::BadMessage => "The message must be at most 256 characters",
+            JoinChatError::AlreadyMember => "User is already a member",
+            JoinChatError::AlreadyRequested => "Already requested to join the chat",
+            JoinChatError::Banned => "Banned from the chat",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
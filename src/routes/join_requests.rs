@@ -0,0 +1,260 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    realtime::{Event, Hub},
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, GroupAdminError, check_group_admin},
+        invite_links::record_link_join,
+        messages::{ServiceAction, insert_service_message},
+    },
+};
+
+/// Requests decided by a single call
+const MAX_DECISION_BATCH: usize = 100;
+
+#[derive(serde::Serialize)]
+pub struct JoinRequest {
+    pub user_id: i64,
+    pub link_id: Option<i64>,
+    pub message: Option<String>,
+    /// Unix timestamp
+    pub created_at: i64,
+}
+
+/// Pending join requests of the chat, the oldest first
+#[instrument(name = "List join requests", skip(pool, credentials))]
+pub async fn list_join_requests(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, JoinRequestError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::InviteUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let requests: Vec<JoinRequest> = sqlx::query!(
+        r#"
+        SELECT user_id, link_id, message, created_at
+        FROM join_requests
+        WHERE chat_id = $1
+        ORDER BY created_at, user_id
+        "#,
+        chat_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query join requests")?
+    .into_iter()
+    .map(|row| JoinRequest {
+        user_id: row.user_id,
+        link_id: row.link_id,
+        message: row.message,
+        created_at: row.created_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "requests": requests,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DecideJoinRequestsModel {
+    /// Applicants whose requests are decided
+    user_ids: Vec<i64>,
+}
+
+/// Add the applicants to the chat, even beyond the max uses of their links
+#[instrument(name = "Approve join requests", skip(payload, pool, credentials, hub))]
+pub async fn approve_join_requests(
+    path: web::Path<i64>,
+    payload: Json<DecideJoinRequestsModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, JoinRequestError> {
+    decide_join_requests(
+        path.into_inner(),
+        payload.into_inner().user_ids,
+        true,
+        credentials.user_id,
+        &pool,
+        &hub,
+    )
+    .await
+}
+
+#[instrument(name = "Decline join requests", skip(payload, pool, credentials, hub))]
+pub async fn decline_join_requests(
+    path: web::Path<i64>,
+    payload: Json<DecideJoinRequestsModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, JoinRequestError> {
+    decide_join_requests(
+        path.into_inner(),
+        payload.into_inner().user_ids,
+        false,
+        credentials.user_id,
+        &pool,
+        &hub,
+    )
+    .await
+}
+
+/// Users without a pending request are skipped, the decided ones are returned
+async fn decide_join_requests(
+    chat_id: i64,
+    mut user_ids: Vec<i64>,
+    approved: bool,
+    admin_id: i64,
+    pool: &PgPool,
+    hub: &Hub,
+) -> Result<HttpResponse, JoinRequestError> {
+    check_group_admin(chat_id, admin_id, AdminRight::InviteUsers, pool).await?;
+
+    user_ids.sort();
+    user_ids.dedup();
+    if !(1..=MAX_DECISION_BATCH).contains(&user_ids.len()) {
+        return Err(JoinRequestError::BadBatch);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let decided = sqlx::query!(
+        r#"
+        DELETE FROM join_requests
+        WHERE chat_id = $1 AND user_id = ANY($2)
+        RETURNING user_id, link_id
+        "#,
+        chat_id,
+        &user_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to delete join requests")?;
+    if decided.is_empty() {
+        return Err(JoinRequestError::RequestNotFound);
+    }
+
+    if approved {
+        for request in &decided {
+            let res = sqlx::query!(
+                r#"
+                INSERT INTO chat_participants (chat_id, user_id, role) VALUES ($1, $2, 'member')
+                ON CONFLICT DO NOTHING
+                "#,
+                chat_id,
+                request.user_id,
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to insert participant")?;
+
+            if res.rows_affected() == 0 {
+                continue;
+            }
+
+            if let Some(link_id) = request.link_id {
+                record_link_join(link_id, request.user_id, &mut transaction).await?;
+            }
+            insert_service_message(
+                chat_id,
+                admin_id,
+                ServiceAction::AddMember {
+                    user_id: request.user_id,
+                },
+                &mut *transaction,
+            )
+            .await?;
+        }
+    }
+
+    let decided: Vec<i64> = decided.into_iter().map(|request| request.user_id).collect();
+    let action = if approved {
+        AdminLogAction::ApproveJoinRequests {
+            user_ids: decided.clone(),
+        }
+    } else {
+        AdminLogAction::DeclineJoinRequests {
+            user_ids: decided.clone(),
+        }
+    };
+    record_admin_action(chat_id, admin_id, action, &mut *transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    hub.send_many(&decided, Event::JoinRequestDecided { chat_id, approved });
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "user_ids": decided,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JoinRequestError {
+    #[error("Join request not found")]
+    RequestNotFound,
+    #[error("Too many or no users")]
+    BadBatch,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to manage join requests")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl From<GroupAdminError> for JoinRequestError {
+    fn from(err: GroupAdminError) -> Self {
+        match err {
+            GroupAdminError::NotGroup => JoinRequestError::NotGroup,
+            GroupAdminError::NoPermission => JoinRequestError::NoPermission,
+            GroupAdminError::UnknownError(err) => JoinRequestError::UnknownError(err),
+        }
+    }
+}
+
+impl ResponseError for JoinRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            JoinRequestError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            JoinRequestError::RequestNotFound => StatusCode::NOT_FOUND,
+            JoinRequestError::BadBatch | JoinRequestError::NotGroup => StatusCode::BAD_REQUEST,
+            JoinRequestError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            JoinRequestError::UnknownError(_) => "Internal Server Error",
+            JoinRequestError::RequestNotFound => "Join request not found",
+            JoinRequestError::BadBatch => "Decide on 1-100 join requests at a time",
+            JoinRequestError::NotGroup => "Chat is not a group",
+            JoinRequestError::NoPermission => "No permission to manage join requests",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
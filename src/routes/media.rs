@@ -0,0 +1,625 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use bytes::Bytes;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    media::{
+        MediaStore, ProcessImageError, ProcessedImage, VoiceMetadata, clear_orphaned, media_key,
+        process_image, process_voice, thumbnail_key,
+    },
+    routes::privacy::{PrivacyKey, filter_visible_owners},
+    startup::{MaxFileSize, StorageQuota, ThumbnailSizes},
+};
+
+/// Upload a file as the raw request body
+///
+/// The content type is sniffed from the content, the header sent by the client is ignored
+#[instrument(
+    name = "Upload media",
+    skip(
+        payload,
+        pool,
+        credentials,
+        store,
+        max_file_size,
+        thumbnail_sizes,
+        quota
+    )
+)]
+pub async fn upload_media(
+    payload: web::Payload,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    store: web::Data<dyn MediaStore>,
+    max_file_size: web::Data<MaxFileSize>,
+    thumbnail_sizes: web::Data<ThumbnailSizes>,
+    quota: web::Data<StorageQuota>,
+) -> Result<HttpResponse, UploadMediaError> {
+    let data = payload
+        .to_bytes_limited(max_file_size.0)
+        .await
+        .map_err(|_| UploadMediaError::TooLarge)?
+        .map_err(|_| UploadMediaError::BadPayload)?;
+    if data.is_empty() {
+        return Err(UploadMediaError::Empty);
+    }
+
+    let media = save_media(
+        data,
+        credentials.user_id,
+        &pool,
+        store.as_ref(),
+        &thumbnail_sizes.0,
+        quota.0,
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(media))
+}
+
+#[derive(serde::Serialize)]
+pub struct MediaInfo {
+    pub media_id: i64,
+    /// SHA-256 of the uploaded content, images are stored without their metadata
+    pub sha256: String,
+    pub size: i64,
+    pub content_type: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    pub thumbnails: Vec<ThumbnailInfo>,
+    /// Set for Opus voice notes
+    pub duration_ms: Option<i32>,
+    /// Values in the range 0-31 for rendering a voice note
+    pub waveform: Option<Vec<u8>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ThumbnailInfo {
+    pub size: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Store the content and record the user as one of its uploaders
+///
+/// The content type is sniffed from the content, the same content is only stored once.
+/// Images are stored without EXIF, together with their thumbnails and blurhash.
+/// Opus voice notes get their duration and waveform
+pub(crate) async fn save_media(
+    data: Bytes,
+    user_id: i64,
+    pool: &PgPool,
+    store: &dyn MediaStore,
+    thumbnail_sizes: &[u32],
+    quota: i64,
+) -> Result<MediaInfo, SaveMediaError> {
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    let content_type = infer::get(&data)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("application/octet-stream");
+
+    let existing = sqlx::query!("SELECT id, size FROM media WHERE sha256 = $1", sha256)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to query media")?;
+
+    // fail early before storing anything, the quota is checked again when the media is referenced
+    let size = existing
+        .as_ref()
+        .map_or(data.len() as i64, |media| media.size);
+    let usage = load_storage_usage(user_id, existing.as_ref().map(|media| media.id), pool).await?;
+    if !usage.referenced && usage.used + size > quota {
+        return Err(SaveMediaError::QuotaExceeded);
+    }
+
+    let media_id = match existing {
+        Some(media) => media.id,
+        None => {
+            // decoding and resizing are CPU bound
+            let thumbnail_sizes = thumbnail_sizes.to_vec();
+            let (data, image, voice) = tokio::task::spawn_blocking(move || {
+                let image = process_image(&data, content_type, &thumbnail_sizes);
+                let voice = match content_type {
+                    "audio/opus" => process_voice(&data),
+                    _ => None,
+                };
+                (data, image, voice)
+            })
+            .await
+            .context("Media processing panicked")?;
+            let image = match image {
+                Ok(image) => image,
+                Err(ProcessImageError::Unsupported) => {
+                    return Err(SaveMediaError::UnsupportedImage);
+                }
+                Err(ProcessImageError::UnknownError(err)) => {
+                    return Err(err.context("Failed to process image").into());
+                }
+            };
+
+            let data = match &image {
+                Some(image) => image.data.clone(),
+                None => data,
+            };
+            let size = data.len() as i64;
+            store
+                .put(&media_key(&sha256), data, content_type)
+                .await
+                .context("Failed to store media")?;
+            for thumbnail in image.iter().flat_map(|image| &image.thumbnails) {
+                store
+                    .put(
+                        &thumbnail_key(&sha256, thumbnail.size),
+                        thumbnail.data.clone(),
+                        "image/jpeg",
+                    )
+                    .await
+                    .context("Failed to store thumbnail")?;
+            }
+
+            insert_media(
+                &sha256,
+                size,
+                content_type,
+                image.as_ref(),
+                voice.as_ref(),
+                pool,
+            )
+            .await?
+        }
+    };
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // serialize the uploads of the user, so concurrent uploads cannot exceed the quota together
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to lock user")?;
+
+    // a media rejected here stays unreferenced and is purged by the garbage collector
+    let usage = load_storage_usage(user_id, Some(media_id), &mut *transaction).await?;
+    let size = sqlx::query_scalar!("SELECT size FROM media WHERE id = $1", media_id)
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to query media size")?;
+    if !usage.referenced && usage.used + size > quota {
+        return Err(SaveMediaError::QuotaExceeded);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO media_uploads (media_id, user_id) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        media_id,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert media upload")?;
+    clear_orphaned(&[media_id], &mut *transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    let media = load_media_info(media_id, pool)
+        .await?
+        .context("Saved media not found")?;
+
+    Ok(media)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SaveMediaError {
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Image cannot be decoded")]
+    UnsupportedImage,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+pub(crate) struct StorageUsage {
+    /// Bytes of the distinct media referenced by the user
+    pub used: i64,
+    /// Whether the given media is already counted
+    pub referenced: bool,
+}
+
+/// A media counts once for a user, whether uploaded or attached to sent messages
+pub(crate) async fn load_storage_usage(
+    user_id: i64,
+    media_id: Option<i64>,
+    executor: impl PgExecutor<'_>,
+) -> anyhow::Result<StorageUsage> {
+    let usage = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(size), 0)::bigint AS "used!",
+            COALESCE(BOOL_OR(id = $2), false) AS "referenced!"
+        FROM media
+        WHERE id IN (
+            SELECT media_id FROM media_uploads WHERE user_id = $1
+            UNION
+            SELECT mm.media_id
+            FROM message_media AS mm
+            JOIN messages AS msg ON msg.id = mm.message_id
+            WHERE msg.sender_id = $1
+        )
+        "#,
+        user_id,
+        media_id,
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to query storage usage")?;
+
+    Ok(StorageUsage {
+        used: usage.used,
+        referenced: usage.referenced,
+    })
+}
+
+async fn insert_media(
+    sha256: &str,
+    size: i64,
+    content_type: &str,
+    image: Option<&ProcessedImage>,
+    voice: Option<&VoiceMetadata>,
+    pool: &PgPool,
+) -> anyhow::Result<i64> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let media_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO media (sha256, size, content_type, width, height, blurhash, duration_ms, waveform)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (sha256) DO UPDATE SET sha256 = EXCLUDED.sha256
+        RETURNING id
+        "#,
+        sha256,
+        size,
+        content_type,
+        image.map(|image| image.width as i32),
+        image.map(|image| image.height as i32),
+        image.map(|image| image.blurhash.as_str()),
+        voice.map(|voice| voice.duration_ms),
+        voice.map(|voice| voice.waveform.as_slice()),
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to insert media")?;
+
+    for thumbnail in image.iter().flat_map(|image| &image.thumbnails) {
+        sqlx::query!(
+            r#"
+            INSERT INTO media_thumbnails (media_id, size, width, height, byte_size)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            "#,
+            media_id,
+            thumbnail.size as i32,
+            thumbnail.width as i32,
+            thumbnail.height as i32,
+            thumbnail.data.len() as i64,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to insert thumbnail")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(media_id)
+}
+
+pub(crate) async fn load_media_info(
+    media_id: i64,
+    pool: &PgPool,
+) -> anyhow::Result<Option<MediaInfo>> {
+    let Some(media) = sqlx::query!(
+        r#"
+        SELECT sha256, size, content_type, width, height, blurhash, duration_ms, waveform
+        FROM media WHERE id = $1
+        "#,
+        media_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query media")?
+    else {
+        return Ok(None);
+    };
+
+    let thumbnails = sqlx::query_as!(
+        ThumbnailInfo,
+        "SELECT size, width, height FROM media_thumbnails WHERE media_id = $1 ORDER BY size",
+        media_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query thumbnails")?;
+
+    Ok(Some(MediaInfo {
+        media_id,
+        sha256: media.sha256,
+        size: media.size,
+        content_type: media.content_type,
+        width: media.width,
+        height: media.height,
+        blurhash: media.blurhash,
+        thumbnails,
+        duration_ms: media.duration_ms,
+        waveform: media.waveform,
+    }))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadMediaError {
+    #[error("File too large")]
+    TooLarge,
+    #[error("Empty file")]
+    Empty,
+    #[error("Failed to read the request body")]
+    BadPayload,
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Image cannot be decoded")]
+    UnsupportedImage,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl From<SaveMediaError> for UploadMediaError {
+    fn from(err: SaveMediaError) -> Self {
+        match err {
+            SaveMediaError::QuotaExceeded => UploadMediaError::QuotaExceeded,
+            SaveMediaError::UnsupportedImage => UploadMediaError::UnsupportedImage,
+            SaveMediaError::UnknownError(err) => UploadMediaError::UnknownError(err),
+        }
+    }
+}
+
+impl ResponseError for UploadMediaError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UploadMediaError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            UploadMediaError::TooLarge | UploadMediaError::QuotaExceeded => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            UploadMediaError::Empty
+            | UploadMediaError::BadPayload
+            | UploadMediaError::UnsupportedImage => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            UploadMediaError::UnknownError(_) => "Internal Server Error",
+            UploadMediaError::TooLarge => "File too large",
+            UploadMediaError::Empty => "Empty file",
+            UploadMediaError::BadPayload => "Failed to read the request body",
+            UploadMediaError::QuotaExceeded => "Storage quota exceeded",
+            UploadMediaError::UnsupportedImage => {
+                "Only JPEG, PNG and WebP images which can be decoded are accepted"
+            }
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[instrument(name = "Download media", skip(pool, credentials, store))]
+pub async fn download_media(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    store: web::Data<dyn MediaStore>,
+) -> Result<HttpResponse, DownloadMediaError> {
+    let media_id = path.into_inner();
+
+    if !can_access_media(media_id, credentials.user_id, &pool)
+        .await
+        .context("Failed to check media access")?
+    {
+        return Err(DownloadMediaError::NotFound);
+    }
+
+    let Some(media) = load_media_info(media_id, &pool).await? else {
+        return Err(DownloadMediaError::NotFound);
+    };
+
+    let data = store
+        .get(&media_key(&media.sha256))
+        .await
+        .context("Failed to load media")?
+        .context("Media missing in the store")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(media.content_type)
+        .body(data))
+}
+
+/// Dimensions, placeholder and available thumbnails of a media
+#[instrument(name = "Get media info", skip(pool, credentials))]
+pub async fn get_media_info(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, DownloadMediaError> {
+    let media_id = path.into_inner();
+
+    if !can_access_media(media_id, credentials.user_id, &pool)
+        .await
+        .context("Failed to check media access")?
+    {
+        return Err(DownloadMediaError::NotFound);
+    }
+
+    let Some(media) = load_media_info(media_id, &pool).await? else {
+        return Err(DownloadMediaError::NotFound);
+    };
+
+    Ok(HttpResponse::Ok().json(media))
+}
+
+#[instrument(name = "Download thumbnail", skip(pool, credentials, store))]
+pub async fn download_thumbnail(
+    path: web::Path<(i64, u32)>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    store: web::Data<dyn MediaStore>,
+) -> Result<HttpResponse, DownloadMediaError> {
+    let (media_id, size) = path.into_inner();
+
+    if !can_access_media(media_id, credentials.user_id, &pool)
+        .await
+        .context("Failed to check media access")?
+    {
+        return Err(DownloadMediaError::NotFound);
+    }
+
+    let Some(sha256) = sqlx::query_scalar!(
+        r#"
+        SELECT m.sha256
+        FROM media_thumbnails AS t
+        JOIN media AS m ON m.id = t.media_id
+        WHERE t.media_id = $1 AND t.size = $2
+        "#,
+        media_id,
+        size as i32,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to query thumbnail")?
+    else {
+        return Err(DownloadMediaError::NotFound);
+    };
+
+    let data = store
+        .get(&thumbnail_key(&sha256, size))
+        .await
+        .context("Failed to load thumbnail")?
+        .context("Thumbnail missing in the store")?;
+
+    Ok(HttpResponse::Ok().content_type("image/jpeg").body(data))
+}
+
+/// Remove the media from the uploads of the user
+///
+/// The space is freed once no sent message references the media either
+#[instrument(name = "Delete media", skip(pool, credentials))]
+pub async fn delete_media(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, DownloadMediaError> {
+    let media_id = path.into_inner();
+
+    let deleted = sqlx::query!(
+        "DELETE FROM media_uploads WHERE media_id = $1 AND user_id = $2",
+        media_id,
+        credentials.user_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete media upload")?;
+    if deleted.rows_affected() == 0 {
+        return Err(DownloadMediaError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(name = "Get storage usage", skip(pool, credentials, quota))]
+pub async fn get_storage_usage(
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    quota: web::Data<StorageQuota>,
+) -> Result<HttpResponse, DownloadMediaError> {
+    let usage = load_storage_usage(credentials.user_id, None, pool.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "used": usage.used,
+        "quota": quota.0,
+    })))
+}
+
+/// Media are accessible by their uploaders and the participants of the chats they are attached in,
+/// or which use them as the chat photo, profile photos by the users passing the privacy rule
+async fn can_access_media(media_id: i64, user_id: i64, pool: &PgPool) -> anyhow::Result<bool> {
+    let accessible = sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS(SELECT 1 FROM media_uploads WHERE media_id = $1 AND user_id = $2)
+            OR EXISTS(
+                SELECT 1
+                FROM message_media AS mm
+                JOIN messages AS msg ON msg.id = mm.message_id
+                JOIN chat_participants AS cp ON cp.chat_id = msg.chat_id
+                WHERE mm.media_id = $1 AND cp.user_id = $2
+            )
+            OR EXISTS(
+                SELECT 1
+                FROM chats AS c
+                JOIN chat_participants AS cp ON cp.chat_id = c.id
+                WHERE c.photo_media_id = $1 AND cp.user_id = $2
+            ) AS "accessible!"
+        "#,
+        media_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to query media access")?;
+    if accessible {
+        return Ok(true);
+    }
+
+    let owner_ids = sqlx::query_scalar!("SELECT id FROM users WHERE photo_media_id = $1", media_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to query profile photo owners")?;
+    let visible = filter_visible_owners(&owner_ids, user_id, PrivacyKey::ProfilePhoto, pool)
+        .await
+        .context("Failed to check profile photo privacy")?;
+
+    Ok(!visible.is_empty())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadMediaError {
+    #[error("Media not found")]
+    NotFound,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for DownloadMediaError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DownloadMediaError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DownloadMediaError::NotFound => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            DownloadMediaError::UnknownError(_) => "Internal Server Error",
+            DownloadMediaError::NotFound => "Media not found",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
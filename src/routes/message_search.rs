@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        chats::load_participant,
+        messages::{HistoryMessage, load_messages},
+    },
+};
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaType {
+    Image,
+    Video,
+    Audio,
+    /// Any attached media
+    Any,
+}
+
+impl MediaType {
+    /// LIKE pattern of the content types
+    fn pattern(&self) -> &'static str {
+        match self {
+            MediaType::Image => "image/%",
+            MediaType::Video => "video/%",
+            MediaType::Audio => "audio/%",
+            MediaType::Any => "%",
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SearchMessagesQuery {
+    q: String,
+    sender_id: Option<i64>,
+    /// Unix timestamp, inclusive
+    from: Option<i64>,
+    /// Unix timestamp, exclusive
+    to: Option<i64>,
+    media_type: Option<MediaType>,
+    /// Only messages older than this message are returned
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub message: HistoryMessage,
+    pub chat_id: i64,
+    /// HTML-escaped content around the matches, which are wrapped in `<b>` tags
+    pub snippet: String,
+}
+
+/// Search the messages of the chat, the newest first
+#[instrument(name = "Search chat messages", skip(query, pool, credentials))]
+pub async fn search_chat_messages(
+    path: web::Path<i64>,
+    query: web::Query<SearchMessagesQuery>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, SearchMessagesError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(SearchMessagesError::NoPermission);
+    }
+
+    let results = search_messages(Some(chat_id), credentials.user_id, &query, &pool).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "messages": results,
+    })))
+}
+
+/// Search the messages of every chat of the user, the newest first
+#[instrument(name = "Search messages", skip(query, pool, credentials))]
+pub async fn search_all_messages(
+    query: web::Query<SearchMessagesQuery>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, SearchMessagesError> {
+    let results = search_messages(None, credentials.user_id, &query, &pool).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "messages": results,
+    })))
+}
+
+async fn search_messages(
+    chat_id: Option<i64>,
+    user_id: i64,
+    query: &SearchMessagesQuery,
+    pool: &PgPool,
+) -> Result<Vec<SearchResult>, SearchMessagesError> {
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(SearchMessagesError::EmptyQuery);
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    // the content is escaped before highlighting, so the snippet is safe to render as HTML
+    let matches = sqlx::query!(
+        r#"
+        SELECT
+            m.id,
+            m.chat_id,
+            ts_headline(
+                'simple',
+                replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                websearch_to_tsquery('simple', $2),
+                'StartSel=<b>, StopSel=</b>, MaxWords=30, MinWords=10'
+            ) AS "snippet!"
+        FROM messages AS m
+        JOIN chat_participants AS cp ON cp.chat_id = m.chat_id AND cp.user_id = $1
+        WHERE
+            m.content_tsv @@ websearch_to_tsquery('simple', $2)
+            AND ($3::bigint IS NULL OR m.chat_id = $3)
+            AND ($4::bigint IS NULL OR m.sender_id = $4)
+            AND ($5::bigint IS NULL OR m.created_at >= to_timestamp($5))
+            AND ($6::bigint IS NULL OR m.created_at < to_timestamp($6))
+            AND ($7::text IS NULL OR EXISTS(
+                SELECT 1
+                FROM message_media AS mm
+                JOIN media AS md ON md.id = mm.media_id
+                WHERE mm.message_id = m.id AND md.content_type LIKE $7
+            ))
+            AND ($8::bigint IS NULL OR m.id < $8)
+            AND (m.expires_at IS NULL OR m.expires_at > now())
+        ORDER BY m.id DESC
+        LIMIT $9
+        "#,
+        user_id,
+        q,
+        chat_id,
+        query.sender_id,
+        query.from,
+        query.to,
+        query.media_type.map(|media_type| media_type.pattern()),
+        query.before_id,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search messages")?;
+
+    let message_ids: Vec<i64> = matches.iter().map(|m| m.id).collect();
+    // messages deleted in the meantime are missing, so they are matched by id
+    let mut messages: HashMap<i64, HistoryMessage> = load_messages(&message_ids, user_id, pool)
+        .await?
+        .into_iter()
+        .map(|message| (message.message_id, message))
+        .collect();
+
+    Ok(matches
+        .into_iter()
+        .filter_map(|m| {
+            Some(SearchResult {
+                message: messages.remove(&m.id)?,
+                chat_id: m.chat_id,
+                snippet: m.snippet,
+            })
+        })
+        .collect())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SearchMessagesError {
+    #[error("Search query must not be empty")]
+    EmptyQuery,
+    #[error("No permission to read the chat")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SearchMessagesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SearchMessagesError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SearchMessagesError::EmptyQuery => StatusCode::BAD_REQUEST,
+            SearchMessagesError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            SearchMessagesError::UnknownError(_) => "Internal Server Error",
+            SearchMessagesError::EmptyQuery => "Search query must not be empty",
+            SearchMessagesError::NoPermission => "No permission to read the chat",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
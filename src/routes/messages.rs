@@ -0,0 +1,687 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    media::{MediaStore, clear_orphaned, purge_unreferenced_media},
+    realtime::{Event, Hub},
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        anti_spam::check_send_rate,
+        blocks::is_blocked_between,
+        chats::{AdminRight, load_other_participants, load_participant, load_participants},
+        moderation::{Permission, load_restrictions},
+        polls::{NewPoll, Poll, insert_poll, load_polls},
+        reactions::{ReactionCount, load_reactions},
+        topics::resolve_topic,
+    },
+};
+
+/// Longest TTL of a message in seconds, 30 days
+const MAX_MESSAGE_TTL: i32 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Text,
+    /// The only attached media is an Opus voice note
+    Voice,
+    /// The content is the question of the attached poll
+    Poll,
+}
+
+impl MessageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageKind::Text => "text",
+            MessageKind::Voice => "voice",
+            MessageKind::Poll => "poll",
+        }
+    }
+}
+
+impl TryFrom<String> for MessageKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "text" => Ok(Self::Text),
+            "voice" => Ok(Self::Voice),
+            "poll" => Ok(Self::Poll),
+            other => Err(format!("{other} is not a message kind")),
+        }
+    }
+}
+
+/// Event recorded by a service message, the sender is the user who caused it
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServiceAction {
+    PinMessage {
+        message_id: i64,
+    },
+    /// The user was added by the sender, or joined if they are the sender
+    AddMember {
+        user_id: i64,
+    },
+    /// The user was removed by the sender, or left if they are the sender
+    RemoveMember {
+        user_id: i64,
+    },
+    ChangeTitle {
+        title: String,
+    },
+    /// None when the photo is removed
+    ChangePhoto {
+        media_id: Option<i64>,
+    },
+}
+
+#[derive(serde::Deserialize)]
+pub struct SendMessageModel {
+    pub chat_id: i64,
+    pub content: String,
+    /// Uploaded media attached to the message
+    #[serde(default)]
+    pub media_ids: Vec<i64>,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// Seconds the message lives, the auto-delete period of the chat applies if shorter
+    pub ttl: Option<i32>,
+    /// Required for polls, not allowed for other kinds
+    pub poll: Option<NewPoll>,
+    /// The General topic if absent in chats with topics
+    pub topic_id: Option<i64>,
+}
+
+#[instrument(name = "Send message", skip(payload, pool, credentials))]
+pub async fn send_message(
+    payload: Json<SendMessageModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, SendMessageError> {
+    let mut payload = payload.into_inner();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    check_message(&mut payload, credentials.user_id, &mut transaction).await?;
+    match check_send_rate(payload.chat_id, credentials.user_id, &mut transaction).await {
+        Ok(()) => {}
+        // the flood restriction stays although the message is rejected
+        Err(SendMessageError::Flooding) => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit transaction")?;
+            return Err(SendMessageError::Flooding);
+        }
+        Err(err) => return Err(err),
+    }
+
+    let message_id = insert_message(&payload, credentials.user_id, &mut transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "message_id": message_id,
+        "topic_id": payload.topic_id,
+    })))
+}
+
+/// Check whether the user may send the message in its current state of the chat
+///
+/// The attached media are deduplicated and the topic is resolved
+pub(crate) async fn check_message(
+    message: &mut SendMessageModel,
+    sender_id: i64,
+    connection: &mut PgConnection,
+) -> Result<(), SendMessageError> {
+    // a message with attachments may have no text
+    let content_length = message.content.chars().count();
+    let min_content_length = if message.media_ids.is_empty() { 1 } else { 0 };
+    if !(min_content_length..=4096).contains(&content_length) {
+        return Err(SendMessageError::BadContentLength);
+    }
+
+    if let Some(ttl) = message.ttl
+        && !(1..=MAX_MESSAGE_TTL).contains(&ttl)
+    {
+        return Err(SendMessageError::BadTtl);
+    }
+
+    // polls carry no media
+    let poll_valid = match (&message.poll, message.kind) {
+        (Some(poll), MessageKind::Poll) => message.media_ids.is_empty() && poll.is_valid(),
+        (None, MessageKind::Poll) | (Some(_), _) => false,
+        (None, _) => true,
+    };
+    if !poll_valid {
+        return Err(SendMessageError::BadPoll);
+    }
+
+    message.media_ids.sort();
+    message.media_ids.dedup();
+    if message.media_ids.len() > 10 {
+        return Err(SendMessageError::TooManyMedia);
+    }
+
+    // the sender must be a participant of the chat
+    let Some(participant) = load_participant(message.chat_id, sender_id, &mut *connection)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(SendMessageError::NoPermission);
+    };
+
+    // nobody can write into a private chat once any side blocked the other
+    if participant.chat_type == "private" {
+        let peer_id = load_other_participants(message.chat_id, sender_id, &mut *connection)
+            .await
+            .context("Failed to query peer user")?
+            .pop();
+
+        if let Some(peer_id) = peer_id
+            && is_blocked_between(sender_id, peer_id, &mut *connection)
+                .await
+                .context("Failed to query blocks")?
+        {
+            return Err(SendMessageError::Blocked);
+        }
+    } else {
+        let restricted = load_restrictions(message.chat_id, sender_id, &mut *connection)
+            .await
+            .context("Failed to load restrictions")?;
+        if restricted.contains(Permission::SendMessages)
+            || (!message.media_ids.is_empty() && restricted.contains(Permission::SendMedia))
+            || (message.kind == MessageKind::Poll && restricted.contains(Permission::SendPolls))
+        {
+            return Err(SendMessageError::Restricted);
+        }
+    }
+
+    message.topic_id =
+        resolve_topic(message.chat_id, message.topic_id, &participant, connection).await?;
+
+    // only the media uploaded by the sender can be attached
+    let owned_media = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM media_uploads WHERE user_id = $1 AND media_id = ANY($2)"#,
+        sender_id,
+        &message.media_ids,
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .context("Failed to query media uploads")?;
+    if owned_media != message.media_ids.len() as i64 {
+        return Err(SendMessageError::MediaNotFound);
+    }
+
+    // the duration is only detected for well-formed Opus recordings
+    if message.kind == MessageKind::Voice {
+        let is_voice = match message.media_ids.as_slice() {
+            [media_id] => sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM media
+                    WHERE id = $1 AND content_type = 'audio/opus' AND duration_ms IS NOT NULL
+                ) AS "exists!"
+                "#,
+                media_id,
+            )
+            .fetch_one(&mut *connection)
+            .await
+            .context("Failed to query voice media")?,
+            _ => false,
+        };
+        if !is_voice {
+            return Err(SendMessageError::NotVoice);
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn insert_message(
+    message: &SendMessageModel,
+    sender_id: i64,
+    connection: &mut PgConnection,
+) -> anyhow::Result<i64> {
+    let message_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO messages (chat_id, sender_id, content, kind, expires_at, topic_id)
+        VALUES (
+            $1, $2, $3, $4,
+            LEAST(
+                now() + make_interval(secs => $5),
+                now() + make_interval(secs => (SELECT auto_delete_after FROM chats WHERE id = $1))
+            ),
+            $6
+        )
+        RETURNING id
+        "#,
+        message.chat_id,
+        sender_id,
+        message.content,
+        message.kind.as_str(),
+        message.ttl.map(f64::from),
+        message.topic_id,
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .context("Failed to insert message")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO message_media (message_id, media_id)
+        SELECT $1, media_id FROM UNNEST($2::bigint[]) AS media_id
+        "#,
+        message_id,
+        &message.media_ids,
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to insert message media")?;
+    clear_orphaned(&message.media_ids, &mut *connection).await?;
+
+    if let Some(poll) = &message.poll {
+        insert_poll(message_id, poll, connection).await?;
+    }
+
+    Ok(message_id)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendMessageError {
+    #[error("Content length not match the requirement")]
+    BadContentLength,
+    #[error("TTL out of range")]
+    BadTtl,
+    #[error("Invalid poll")]
+    BadPoll,
+    #[error("No permission to send messages into the chat")]
+    NoPermission,
+    #[error("Blocked by the peer user")]
+    Blocked,
+    #[error("Restricted by the admins")]
+    Restricted,
+    #[error("Slow mode is enabled")]
+    SlowMode,
+    #[error("Restricted by the flood limit")]
+    Flooding,
+    #[error("Media not found")]
+    MediaNotFound,
+    #[error("Too many media attached")]
+    TooManyMedia,
+    #[error("Voice message without a voice note")]
+    NotVoice,
+    #[error("Topic not found")]
+    TopicNotFound,
+    #[error("Topic is closed")]
+    TopicClosed,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SendMessageError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SendMessageError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SendMessageError::BadContentLength
+            | SendMessageError::BadTtl
+            | SendMessageError::BadPoll
+            | SendMessageError::MediaNotFound
+            | SendMessageError::TooManyMedia
+            | SendMessageError::NotVoice
+            | SendMessageError::TopicNotFound => StatusCode::BAD_REQUEST,
+            SendMessageError::NoPermission
+            | SendMessageError::Blocked
+            | SendMessageError::Restricted
+            | SendMessageError::Flooding
+            | SendMessageError::TopicClosed => StatusCode::FORBIDDEN,
+            SendMessageError::SlowMode => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            SendMessageError::UnknownError(_) => "Internal Server Error",
+            SendMessageError::BadContentLength => {
+                "Content length not match the requirement: only length in the range 1-4096 is acceptable"
+            }
+            SendMessageError::BadTtl => "The TTL must be between 1 second and 30 days",
+            SendMessageError::BadPoll => {
+                "A poll needs 2-10 options of 1-100 characters, a quiz a single correct option"
+            }
+            SendMessageError::NoPermission => "No permission to send messages into the chat",
+            SendMessageError::Blocked => "Cannot send messages to this user",
+            SendMessageError::Restricted => "Restricted from sending this message by the admins",
+            SendMessageError::SlowMode => {
+                "Slow mode is enabled, wait before sending another message"
+            }
+            SendMessageError::Flooding => {
+                "Too many messages sent, restricted from sending messages for a while"
+            }
+            SendMessageError::MediaNotFound => "Media not found",
+            SendMessageError::TooManyMedia => "At most 10 media can be attached to a message",
+            SendMessageError::NotVoice => {
+                "A voice message needs exactly one Opus voice note attached"
+            }
+            SendMessageError::TopicNotFound => "Topic not found",
+            SendMessageError::TopicClosed => "The topic is closed",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct HistoryQuery {
+    /// Only messages older than this message are returned
+    before_id: Option<i64>,
+    /// Only messages of this topic
+    topic_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct HistoryMessage {
+    pub message_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub kind: String,
+    /// Set for service messages
+    pub service: Option<ServiceAction>,
+    /// Unix timestamp
+    pub created_at: i64,
+    /// Unix timestamp, the message is deleted at this time
+    pub expires_at: Option<i64>,
+    /// Set in chats with topics
+    pub topic_id: Option<i64>,
+    pub media_ids: Vec<i64>,
+    pub reactions: Vec<ReactionCount>,
+    pub poll: Option<Poll>,
+}
+
+/// Messages of the chat, the newest first
+#[instrument(name = "Get chat history", skip(query, pool, credentials))]
+pub async fn get_chat_history(
+    path: web::Path<i64>,
+    query: web::Query<HistoryQuery>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, HistoryError> {
+    let chat_id = path.into_inner();
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(HistoryError::NoPermission);
+    }
+
+    let message_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM messages
+        WHERE
+            chat_id = $1
+            AND ($2::bigint IS NULL OR id < $2)
+            AND ($4::bigint IS NULL OR topic_id = $4)
+            -- expired messages are hidden until the sweeper deletes them
+            AND (expires_at IS NULL OR expires_at > now())
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+        chat_id,
+        query.before_id,
+        limit,
+        query.topic_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query messages")?;
+
+    let messages = load_messages(&message_ids, credentials.user_id, &pool).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "messages": messages,
+    })))
+}
+
+/// Load the messages in the order of the given ids, with the reactions and votes seen by the user
+pub(crate) async fn load_messages(
+    message_ids: &[i64],
+    user_id: i64,
+    pool: &PgPool,
+) -> anyhow::Result<Vec<HistoryMessage>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            m.id,
+            m.sender_id,
+            m.content,
+            m.kind,
+            m.service AS "service: sqlx::types::Json<ServiceAction>",
+            m.created_at,
+            m.expires_at,
+            m.topic_id,
+            ARRAY(
+                SELECT media_id FROM message_media WHERE message_id = m.id ORDER BY media_id
+            ) AS "media_ids!"
+        FROM UNNEST($1::bigint[]) WITH ORDINALITY AS ids(id, position)
+        JOIN messages AS m ON m.id = ids.id
+        ORDER BY ids.position
+        "#,
+        message_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query messages")?;
+
+    let mut reactions = load_reactions(message_ids, user_id, pool).await?;
+    let mut polls = load_polls(message_ids, user_id, pool).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| HistoryMessage {
+            message_id: row.id,
+            sender_id: row.sender_id,
+            content: row.content,
+            kind: row.kind,
+            service: row.service.map(|service| service.0),
+            created_at: row.created_at.unix_timestamp(),
+            expires_at: row.expires_at.map(|time| time.unix_timestamp()),
+            topic_id: row.topic_id,
+            media_ids: row.media_ids,
+            reactions: reactions.remove(&row.id).unwrap_or_default(),
+            poll: polls.remove(&row.id),
+        })
+        .collect())
+}
+
+/// Record an event of the chat in its history
+pub(crate) async fn insert_service_message(
+    chat_id: i64,
+    user_id: i64,
+    action: ServiceAction,
+    executor: impl PgExecutor<'_>,
+) -> anyhow::Result<i64> {
+    let message_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO messages (chat_id, sender_id, content, kind, service, topic_id)
+        VALUES (
+            $1, $2, '', 'service', $3,
+            -- the General topic in chats with topics
+            (
+                SELECT t.id FROM forum_topics AS t
+                JOIN chats AS c ON c.id = t.chat_id
+                WHERE t.chat_id = $1 AND t.is_general AND c.topics_enabled
+            )
+        )
+        RETURNING id
+        "#,
+        chat_id,
+        user_id,
+        sqlx::types::Json(action) as _,
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to insert service message")?;
+
+    Ok(message_id)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("No permission to read the chat")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for HistoryError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            HistoryError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            HistoryError::UnknownError(_) => "Internal Server Error",
+            HistoryError::NoPermission => "No permission to read the chat",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+/// Delete the message, the admins with the right can delete the messages of the others
+///
+/// The deletions by the admins are recorded in the admin log
+#[instrument(name = "Delete message", skip(pool, credentials, hub, store))]
+pub async fn delete_message(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+    store: web::Data<dyn MediaStore>,
+) -> Result<HttpResponse, DeleteMessageError> {
+    let message_id = path.into_inner();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let Some(message) = sqlx::query!(
+        "SELECT chat_id, sender_id FROM messages WHERE id = $1 FOR UPDATE",
+        message_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to query message")?
+    else {
+        return Err(DeleteMessageError::NotFound);
+    };
+
+    // the message is hidden from the others
+    let Some(participant) =
+        load_participant(message.chat_id, credentials.user_id, &mut *transaction)
+            .await
+            .context("Failed to load participant")?
+    else {
+        return Err(DeleteMessageError::NotFound);
+    };
+    let by_admin = message.sender_id != credentials.user_id;
+    if by_admin
+        && (participant.chat_type == "private"
+            || !participant.has_right(AdminRight::DeleteMessages))
+    {
+        return Err(DeleteMessageError::NoPermission);
+    }
+
+    let media_ids = sqlx::query_scalar!(
+        "SELECT media_id FROM message_media WHERE message_id = $1",
+        message_id,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query attached media")?;
+
+    sqlx::query!("DELETE FROM messages WHERE id = $1", message_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete message")?;
+
+    if by_admin {
+        record_admin_action(
+            message.chat_id,
+            credentials.user_id,
+            AdminLogAction::DeleteMessage {
+                message_id,
+                sender_id: message.sender_id,
+            },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    let participants = load_participants(message.chat_id, &pool)
+        .await
+        .context("Failed to load participants")?;
+    hub.send_many(
+        &participants,
+        Event::MessagesDeleted {
+            chat_id: message.chat_id,
+            message_ids: vec![message_id],
+        },
+    );
+
+    // media still referenced by an upload or another message are kept
+    purge_unreferenced_media(&pool, store.as_ref(), &media_ids).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteMessageError {
+    #[error("Message not found")]
+    NotFound,
+    #[error("No permission to delete the message")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for DeleteMessageError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DeleteMessageError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DeleteMessageError::NotFound => StatusCode::NOT_FOUND,
+            DeleteMessageError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            DeleteMessageError::UnknownError(_) => "Internal Server Error",
+            DeleteMessageError::NotFound => "Message not found",
+            DeleteMessageError::NoPermission => "No permission to delete the message",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,704 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    realtime::{Event, Hub},
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, GroupAdminError, check_group_admin},
+        messages::{ServiceAction, insert_service_message},
+    },
+};
+
+/// Bans and restrictions with an end last at most a year
+const MAX_MODERATION_PERIOD: i64 = 365 * 24 * 60 * 60;
+
+/// Permissions of the members which can be restricted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    SendMessages,
+    SendMedia,
+    SendPolls,
+    AddReactions,
+}
+
+impl Permission {
+    const ALL: [Permission; 4] = [
+        Permission::SendMessages,
+        Permission::SendMedia,
+        Permission::SendPolls,
+        Permission::AddReactions,
+    ];
+
+    pub(crate) fn bit(self) -> i32 {
+        1 << self as i32
+    }
+}
+
+/// Set of permissions, stored as a bitmask and serialized as a list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(from = "Vec<Permission>", into = "Vec<Permission>")]
+pub struct Permissions(i32);
+
+impl Permissions {
+    pub fn contains(self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<Vec<Permission>> for Permissions {
+    fn from(permissions: Vec<Permission>) -> Self {
+        Permissions(permissions.into_iter().fold(0, |bits, p| bits | p.bit()))
+    }
+}
+
+impl From<Permissions> for Vec<Permission> {
+    fn from(permissions: Permissions) -> Self {
+        Permission::ALL
+            .into_iter()
+            .filter(|p| permissions.contains(*p))
+            .collect()
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct KickMemberModel {
+    user_id: i64,
+}
+
+/// Remove the member from the chat, they can join again
+#[instrument(name = "Kick member", skip(payload, pool, credentials, hub))]
+pub async fn kick_member(
+    path: web::Path<i64>,
+    payload: Json<KickMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+    check_target(chat_id, payload.user_id, &mut transaction).await?;
+
+    // admins are never removed, even if promoted meanwhile
+    let res = sqlx::query!(
+        "DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2 AND role = 'member'",
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete participant")?;
+    if res.rows_affected() == 0 {
+        return Err(ModerationError::NotMember);
+    }
+
+    insert_service_message(
+        chat_id,
+        credentials.user_id,
+        ServiceAction::RemoveMember {
+            user_id: payload.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::KickMember {
+            user_id: payload.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    hub.send_many(
+        &[payload.user_id],
+        Event::RemovedFromChat {
+            chat_id,
+            banned: false,
+        },
+    );
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": payload.user_id,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct BanMemberModel {
+    user_id: i64,
+    /// Unix timestamp, banned forever if absent
+    until: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ChatBan {
+    pub user_id: i64,
+    /// None once the admin is deleted
+    pub banned_by: Option<i64>,
+    /// Unix timestamp
+    pub until: Option<i64>,
+    /// Unix timestamp
+    pub created_at: i64,
+}
+
+/// Remove the user from the chat and keep them from joining again
+///
+/// Users who are not members can be banned beforehand
+#[instrument(name = "Ban member", skip(payload, pool, credentials, hub))]
+pub async fn ban_member(
+    path: web::Path<i64>,
+    payload: Json<BanMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+    check_until(payload.until)?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    match check_target(chat_id, payload.user_id, &mut transaction).await {
+        Ok(()) => {}
+        Err(ModerationError::NotMember) => {
+            let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS "exists!""#,
+                payload.user_id,
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .context("Failed to query user")?;
+            if !exists {
+                return Err(ModerationError::UserNotFound);
+            }
+        }
+        Err(e) => return Err(e),
+    }
+
+    let ban = sqlx::query!(
+        r#"
+        INSERT INTO chat_bans (chat_id, user_id, banned_by, until)
+        VALUES ($1, $2, $3, to_timestamp($4))
+        ON CONFLICT (chat_id, user_id) DO UPDATE
+        SET banned_by = EXCLUDED.banned_by, until = EXCLUDED.until, created_at = now()
+        RETURNING created_at
+        "#,
+        chat_id,
+        payload.user_id,
+        credentials.user_id,
+        payload.until.map(|until| until as f64),
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to insert ban")?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2 AND role = 'member'",
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete participant")?
+    .rows_affected()
+        > 0;
+    if removed {
+        insert_service_message(
+            chat_id,
+            credentials.user_id,
+            ServiceAction::RemoveMember {
+                user_id: payload.user_id,
+            },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    // a pending request would let the admins approve the banned user
+    sqlx::query!(
+        "DELETE FROM join_requests WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete join request")?;
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::BanMember {
+            user_id: payload.user_id,
+            until: payload.until,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    if removed {
+        hub.send_many(
+            &[payload.user_id],
+            Event::RemovedFromChat {
+                chat_id,
+                banned: true,
+            },
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(ChatBan {
+        user_id: payload.user_id,
+        banned_by: Some(credentials.user_id),
+        until: payload.until,
+        created_at: ban.created_at.unix_timestamp(),
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnbanMemberModel {
+    user_id: i64,
+}
+
+/// Let the user join again, they are not added back
+#[instrument(name = "Unban member", skip(payload, pool, credentials))]
+pub async fn unban_member(
+    path: web::Path<i64>,
+    payload: Json<UnbanMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM chat_bans
+        WHERE chat_id = $1 AND user_id = $2 AND (until IS NULL OR until > now())
+        "#,
+        chat_id,
+        payload.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete ban")?;
+    if res.rows_affected() == 0 {
+        return Err(ModerationError::NotBanned);
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::UnbanMember {
+            user_id: payload.user_id,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": payload.user_id,
+    })))
+}
+
+/// Active bans of the chat, the newest first
+#[instrument(name = "List bans", skip(pool, credentials))]
+pub async fn list_bans(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    let bans: Vec<ChatBan> = sqlx::query!(
+        r#"
+        SELECT user_id, banned_by, until, created_at
+        FROM chat_bans
+        WHERE chat_id = $1 AND (until IS NULL OR until > now())
+        ORDER BY created_at DESC, user_id
+        "#,
+        chat_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query bans")?
+    .into_iter()
+    .map(|row| ChatBan {
+        user_id: row.user_id,
+        banned_by: row.banned_by,
+        until: row.until.map(|until| until.unix_timestamp()),
+        created_at: row.created_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "bans": bans,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RestrictMemberModel {
+    user_id: i64,
+    /// Permissions taken away, an empty list lifts the restriction and the one of the flood limit
+    permissions: Permissions,
+    /// Unix timestamp, restricted until lifted if absent
+    until: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ChatRestriction {
+    pub user_id: i64,
+    /// None when restricted by the flood limit or once the admin is deleted
+    pub restricted_by: Option<i64>,
+    pub permissions: Permissions,
+    /// Unix timestamp
+    pub until: Option<i64>,
+}
+
+/// Take permissions away from the member, replacing their previous restriction
+#[instrument(name = "Restrict member", skip(payload, pool, credentials))]
+pub async fn restrict_member(
+    path: web::Path<i64>,
+    payload: Json<RestrictMemberModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+    check_until(payload.until)?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+    check_target(chat_id, payload.user_id, &mut transaction).await?;
+
+    if payload.permissions.is_empty() {
+        sqlx::query!(
+            "DELETE FROM chat_restrictions WHERE chat_id = $1 AND user_id = $2",
+            chat_id,
+            payload.user_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete restriction")?;
+        sqlx::query!(
+            "DELETE FROM chat_flood_restrictions WHERE chat_id = $1 AND user_id = $2",
+            chat_id,
+            payload.user_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete flood restriction")?;
+    } else {
+        sqlx::query!(
+            r#"
+            INSERT INTO chat_restrictions (chat_id, user_id, restricted_by, permissions, until)
+            VALUES ($1, $2, $3, $4, to_timestamp($5))
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET
+                restricted_by = EXCLUDED.restricted_by,
+                permissions = EXCLUDED.permissions,
+                until = EXCLUDED.until,
+                created_at = now()
+            "#,
+            chat_id,
+            payload.user_id,
+            credentials.user_id,
+            payload.permissions.0,
+            payload.until.map(|until| until as f64),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to upsert restriction")?;
+    }
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::RestrictMember {
+            user_id: payload.user_id,
+            permissions: payload.permissions,
+            until: payload.until,
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(ChatRestriction {
+        user_id: payload.user_id,
+        restricted_by: Some(credentials.user_id),
+        permissions: payload.permissions,
+        until: payload.until,
+    }))
+}
+
+/// Active restrictions of the chat
+#[instrument(name = "List restrictions", skip(pool, credentials))]
+pub async fn list_restrictions(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ModerationError> {
+    let chat_id = path.into_inner();
+
+    check_group_admin(
+        chat_id,
+        credentials.user_id,
+        AdminRight::BanUsers,
+        pool.as_ref(),
+    )
+    .await?;
+
+    // a member can be restricted by the admins and the flood limit at once
+    let restrictions: Vec<ChatRestriction> = sqlx::query!(
+        r#"
+        SELECT
+            user_id AS "user_id!",
+            restricted_by,
+            permissions AS "permissions!",
+            until
+        FROM (
+            SELECT user_id, restricted_by, permissions, until, created_at
+            FROM chat_restrictions
+            WHERE chat_id = $1 AND (until IS NULL OR until > now())
+            UNION ALL
+            SELECT user_id, NULL, $2, until, created_at
+            FROM chat_flood_restrictions
+            WHERE chat_id = $1 AND until > now()
+        ) AS restrictions
+        ORDER BY created_at DESC, user_id
+        "#,
+        chat_id,
+        Permission::SendMessages.bit(),
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query restrictions")?
+    .into_iter()
+    .map(|row| ChatRestriction {
+        user_id: row.user_id,
+        restricted_by: row.restricted_by,
+        permissions: Permissions(row.permissions),
+        until: row.until.map(|until| until.unix_timestamp()),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "restrictions": restrictions,
+    })))
+}
+
+/// Check whether the user is banned from the chat
+pub(crate) async fn is_banned(
+    chat_id: i64,
+    user_id: i64,
+    executor: impl PgExecutor<'_>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM chat_bans
+            WHERE chat_id = $1 AND user_id = $2 AND (until IS NULL OR until > now())
+        ) AS "exists!"
+        "#,
+        chat_id,
+        user_id,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Load the permissions currently taken away from the member
+pub(crate) async fn load_restrictions(
+    chat_id: i64,
+    user_id: i64,
+    executor: impl PgExecutor<'_>,
+) -> Result<Permissions, sqlx::Error> {
+    let permissions = sqlx::query_scalar!(
+        r#"
+        SELECT
+            COALESCE((
+                SELECT permissions FROM chat_restrictions
+                WHERE chat_id = $1 AND user_id = $2 AND (until IS NULL OR until > now())
+            ), 0)
+            | CASE WHEN EXISTS(
+                SELECT 1 FROM chat_flood_restrictions
+                WHERE chat_id = $1 AND user_id = $2 AND until > now()
+            ) THEN $3 ELSE 0 END AS "permissions!"
+        "#,
+        chat_id,
+        user_id,
+        Permission::SendMessages.bit(),
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(Permissions(permissions))
+}
+
+fn check_until(until: Option<i64>) -> Result<(), ModerationError> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    match until {
+        Some(until) if until <= now || until > now + MAX_MODERATION_PERIOD => {
+            Err(ModerationError::BadUntil)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Admins and the owner cannot be moderated
+///
+/// The membership stays locked until the transaction ends, so the member cannot be promoted meanwhile
+async fn check_target(
+    chat_id: i64,
+    user_id: i64,
+    connection: &mut PgConnection,
+) -> Result<(), ModerationError> {
+    let Some(role) = sqlx::query_scalar!(
+        "SELECT role FROM chat_participants WHERE chat_id = $1 AND user_id = $2 FOR UPDATE",
+        chat_id,
+        user_id,
+    )
+    .fetch_optional(connection)
+    .await
+    .context("Failed to query participant")?
+    else {
+        return Err(ModerationError::NotMember);
+    };
+    if matches!(role.as_deref(), Some("owner" | "admin")) {
+        return Err(ModerationError::CannotModerate);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ModerationError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("User is not a member")]
+    NotMember,
+    #[error("User is not banned")]
+    NotBanned,
+    #[error("Until time not in the next year")]
+    BadUntil,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("Cannot moderate admins")]
+    CannotModerate,
+    #[error("No permission to moderate members")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl From<GroupAdminError> for ModerationError {
+    fn from(err: GroupAdminError) -> Self {
+        match err {
+            GroupAdminError::NotGroup => ModerationError::NotGroup,
+            GroupAdminError::NoPermission => ModerationError::NoPermission,
+            GroupAdminError::UnknownError(err) => ModerationError::UnknownError(err),
+        }
+    }
+}
+
+impl ResponseError for ModerationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ModerationError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ModerationError::UserNotFound
+            | ModerationError::NotMember
+            | ModerationError::NotBanned => StatusCode::NOT_FOUND,
+            ModerationError::BadUntil | ModerationError::NotGroup => StatusCode::BAD_REQUEST,
+            ModerationError::CannotModerate | ModerationError::NoPermission => {
+                StatusCode::FORBIDDEN
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ModerationError::UnknownError(_) => "Internal Server Error",
+            ModerationError::UserNotFound => "User not found",
+            ModerationError::NotMember => "User is not a member",
+            ModerationError::NotBanned => "User is not banned",
+            ModerationError::BadUntil => "The until time must be within the next year",
+            ModerationError::NotGroup => "Chat is not a group",
+            ModerationError::CannotModerate => "Admins cannot be kicked, banned or restricted",
+            ModerationError::NoPermission => "No permission to moderate members",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        blocks::is_blocked_between,
+        chats::{AdminRight, Participant, load_other_participants, load_participant},
+        messages::{HistoryMessage, ServiceAction, insert_service_message, load_messages},
+    },
+};
+
+#[derive(serde::Serialize)]
+pub struct PinnedMessage {
+    #[serde(flatten)]
+    pub message: HistoryMessage,
+    pub pinned_by: i64,
+    /// Unix timestamp
+    pub pinned_at: i64,
+}
+
+/// Pin the message and record the pin in the chat history
+#[instrument(name = "Pin message", skip(pool, credentials))]
+pub async fn pin_message(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, PinError> {
+    let message_id = path.into_inner();
+
+    let (chat_id, is_private) =
+        check_pin_permission(message_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO pinned_messages (chat_id, message_id, pinned_by) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        chat_id,
+        message_id,
+        credentials.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert pinned message")?;
+    if res.rows_affected() == 0 {
+        return Err(PinError::AlreadyPinned);
+    }
+
+    let service_message_id = insert_service_message(
+        chat_id,
+        credentials.user_id,
+        ServiceAction::PinMessage { message_id },
+        &mut *transaction,
+    )
+    .await?;
+    if !is_private {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::PinMessage { message_id },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "message_id": message_id,
+        "service_message_id": service_message_id,
+    })))
+}
+
+#[instrument(name = "Unpin message", skip(pool, credentials))]
+pub async fn unpin_message(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, PinError> {
+    let message_id = path.into_inner();
+
+    let (chat_id, is_private) =
+        check_pin_permission(message_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "DELETE FROM pinned_messages WHERE message_id = $1",
+        message_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete pinned message")?;
+    if res.rows_affected() == 0 {
+        return Err(PinError::NotPinned);
+    }
+
+    if !is_private {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::UnpinMessage { message_id },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message_id": message_id,
+    })))
+}
+
+/// Pinned messages of the chat, the latest pin first
+#[instrument(name = "List pinned messages", skip(pool, credentials))]
+pub async fn list_pinned_messages(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, PinError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(PinError::NoPermission);
+    }
+
+    let pins = sqlx::query!(
+        r#"
+        SELECT p.message_id, p.pinned_by, p.pinned_at
+        FROM pinned_messages AS p
+        JOIN messages AS m ON m.id = p.message_id
+        WHERE p.chat_id = $1 AND (m.expires_at IS NULL OR m.expires_at > now())
+        ORDER BY p.pinned_at DESC, p.message_id DESC
+        "#,
+        chat_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query pinned messages")?;
+
+    let message_ids: Vec<i64> = pins.iter().map(|pin| pin.message_id).collect();
+    // messages deleted in the meantime are missing, so they are matched by id
+    let mut messages: HashMap<i64, HistoryMessage> =
+        load_messages(&message_ids, credentials.user_id, &pool)
+            .await?
+            .into_iter()
+            .map(|message| (message.message_id, message))
+            .collect();
+
+    let pinned: Vec<PinnedMessage> = pins
+        .into_iter()
+        .filter_map(|pin| {
+            Some(PinnedMessage {
+                message: messages.remove(&pin.message_id)?,
+                pinned_by: pin.pinned_by,
+                pinned_at: pin.pinned_at.unix_timestamp(),
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "messages": pinned,
+    })))
+}
+
+/// Admins can pin in groups, both sides can pin in private chats
+///
+/// Returns the chat of the message and whether it is a private chat
+async fn check_pin_permission(
+    message_id: i64,
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<(i64, bool), PinError> {
+    let Some(message) = sqlx::query!(
+        r#"
+        SELECT m.chat_id, c.type AS chat_type, cp.role, cp.permission
+        FROM messages AS m
+        JOIN chats AS c ON c.id = m.chat_id
+        JOIN chat_participants AS cp ON cp.chat_id = m.chat_id AND cp.user_id = $2
+        WHERE m.id = $1
+        "#,
+        message_id,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query message")?
+    else {
+        return Err(PinError::MessageNotFound);
+    };
+
+    let participant = Participant {
+        chat_type: message.chat_type,
+        role: message.role,
+        permission: message.permission,
+    };
+    if participant.chat_type == "private" {
+        let peer_id = load_other_participants(message.chat_id, user_id, pool)
+            .await
+            .context("Failed to query peer user")?
+            .pop();
+
+        if let Some(peer_id) = peer_id
+            && is_blocked_between(user_id, peer_id, pool)
+                .await
+                .context("Failed to query blocks")?
+        {
+            return Err(PinError::NoPermission);
+        }
+    } else if !participant.has_right(AdminRight::PinMessages) {
+        return Err(PinError::NoPermission);
+    }
+
+    Ok((message.chat_id, participant.chat_type == "private"))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PinError {
+    #[error("Message not found")]
+    MessageNotFound,
+    #[error("Message is already pinned")]
+    AlreadyPinned,
+    #[error("Message is not pinned")]
+    NotPinned,
+    #[error("No permission to pin messages")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PinError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PinError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PinError::MessageNotFound => StatusCode::NOT_FOUND,
+            PinError::AlreadyPinned | PinError::NotPinned => StatusCode::BAD_REQUEST,
+            PinError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            PinError::UnknownError(_) => "Internal Server Error",
+            PinError::MessageNotFound => "Message not found",
+            PinError::AlreadyPinned => "Message is already pinned",
+            PinError::NotPinned => "Message is not pinned",
+            PinError::NoPermission => "No permission to pin messages in the chat",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    realtime::{Event, Hub},
+    routes::chats::load_participants,
+};
+
+/// Options of a poll
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 10;
+/// Characters of a single option
+const MAX_OPTION_LENGTH: usize = 100;
+/// Characters of the explanation of a quiz
+const MAX_EXPLANATION_LENGTH: usize = 200;
+
+/// Poll attached to a new message, the question is the content of the message
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NewPoll {
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multiple_choice: bool,
+    /// Whether the voters are hidden from the participants
+    #[serde(default = "anonymous_by_default")]
+    pub anonymous: bool,
+    pub quiz: Option<Quiz>,
+}
+
+fn anonymous_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Quiz {
+    /// Index into the options
+    pub correct_option: i16,
+    /// Shown once the user answered
+    pub explanation: Option<String>,
+}
+
+impl NewPoll {
+    pub fn is_valid(&self) -> bool {
+        let options_valid = (MIN_POLL_OPTIONS..=MAX_POLL_OPTIONS).contains(&self.options.len())
+            && self
+                .options
+                .iter()
+                .all(|option| (1..=MAX_OPTION_LENGTH).contains(&option.trim().chars().count()));
+
+        // a quiz has exactly one correct answer
+        let quiz_valid = match &self.quiz {
+            Some(quiz) => {
+                !self.multiple_choice
+                    && (0..self.options.len() as i16).contains(&quiz.correct_option)
+                    && quiz.explanation.as_ref().is_none_or(|explanation| {
+                        explanation.chars().count() <= MAX_EXPLANATION_LENGTH
+                    })
+            }
+            None => true,
+        };
+
+        options_valid && quiz_valid
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Poll {
+    pub options: Vec<PollOption>,
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub quiz: bool,
+    /// Revealed once the user voted or the poll is closed
+    pub correct_option: Option<i16>,
+    pub explanation: Option<String>,
+    pub total_voters: i64,
+    pub closed: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PollOption {
+    pub text: String,
+    pub votes: i64,
+    /// Whether the requesting user voted for the option
+    pub chosen: bool,
+    /// None for anonymous polls
+    pub voters: Option<Vec<i64>>,
+}
+
+pub(crate) async fn insert_poll(
+    message_id: i64,
+    poll: &NewPoll,
+    connection: &mut PgConnection,
+) -> anyhow::Result<()> {
+    let options: Vec<String> = poll
+        .options
+        .iter()
+        .map(|option| option.trim().to_string())
+        .collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO polls (message_id, options, multiple_choice, anonymous, correct_option, explanation)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        message_id,
+        &options,
+        poll.multiple_choice,
+        poll.anonymous,
+        poll.quiz.as_ref().map(|quiz| quiz.correct_option),
+        poll.quiz.as_ref().and_then(|quiz| quiz.explanation.as_deref()),
+    )
+    .execute(connection)
+    .await
+    .context("Failed to insert poll")?;
+
+    Ok(())
+}
+
+/// Load the polls of the messages with the votes seen by the user
+pub(crate) async fn load_polls(
+    message_ids: &[i64],
+    user_id: i64,
+    pool: &PgPool,
+) -> anyhow::Result<HashMap<i64, Poll>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            message_id,
+            options,
+            multiple_choice,
+            anonymous,
+            correct_option,
+            explanation,
+            closed_at IS NOT NULL AS "closed!"
+        FROM polls
+        WHERE message_id = ANY($1)
+        "#,
+        message_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query polls")?;
+    if rows.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let votes = sqlx::query!(
+        r#"
+        SELECT message_id, user_id, option
+        FROM poll_votes
+        WHERE message_id = ANY($1)
+        ORDER BY voted_at, user_id
+        "#,
+        message_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query poll votes")?;
+
+    let mut polls = HashMap::new();
+    for row in rows {
+        let mut options: Vec<PollOption> = row
+            .options
+            .into_iter()
+            .map(|text| PollOption {
+                text,
+                votes: 0,
+                chosen: false,
+                voters: (!row.anonymous).then(Vec::new),
+            })
+            .collect();
+
+        let mut voters = Vec::new();
+        for vote in votes
+            .iter()
+            .filter(|vote| vote.message_id == row.message_id)
+        {
+            let Some(option) = options.get_mut(vote.option as usize) else {
+                continue;
+            };
+            option.votes += 1;
+            option.chosen |= vote.user_id == user_id;
+            if let Some(option_voters) = &mut option.voters {
+                option_voters.push(vote.user_id);
+            }
+            if !voters.contains(&vote.user_id) {
+                voters.push(vote.user_id);
+            }
+        }
+
+        // the answer of a quiz is hidden until the user answered
+        let revealed = row.closed || voters.contains(&user_id);
+
+        polls.insert(
+            row.message_id,
+            Poll {
+                options,
+                multiple_choice: row.multiple_choice,
+                anonymous: row.anonymous,
+                quiz: row.correct_option.is_some(),
+                correct_option: row.correct_option.filter(|_| revealed),
+                explanation: row.explanation.filter(|_| revealed),
+                total_voters: voters.len() as i64,
+                closed: row.closed,
+            },
+        );
+    }
+
+    Ok(polls)
+}
+
+#[derive(serde::Deserialize)]
+pub struct VoteModel {
+    /// Indexes into the options, exactly one unless the poll is multiple choice
+    options: Vec<i16>,
+}
+
+#[instrument(name = "Vote in poll", skip(payload, pool, credentials, hub))]
+pub async fn vote_poll(
+    path: web::Path<i64>,
+    payload: Json<VoteModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, PollError> {
+    let message_id = path.into_inner();
+    let mut options = payload.into_inner().options;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let poll = lock_poll(message_id, credentials.user_id, &mut transaction).await?;
+    if poll.closed {
+        return Err(PollError::Closed);
+    }
+
+    options.sort();
+    options.dedup();
+    let options_valid = !options.is_empty()
+        && (poll.multiple_choice || options.len() == 1)
+        && options
+            .iter()
+            .all(|option| (0..poll.option_count).contains(option));
+    if !options_valid {
+        return Err(PollError::BadOptions);
+    }
+
+    // a vote must be retracted before voting again
+    let voted = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM poll_votes WHERE message_id = $1 AND user_id = $2) AS "exists!"
+        "#,
+        message_id,
+        credentials.user_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to query poll votes")?;
+    if voted {
+        return Err(PollError::AlreadyVoted);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO poll_votes (message_id, user_id, option)
+        SELECT $1, $2, option FROM UNNEST($3::smallint[]) AS option
+        "#,
+        message_id,
+        credentials.user_id,
+        &options,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert poll votes")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    respond_with_poll(poll.chat_id, message_id, credentials.user_id, &pool, &hub).await
+}
+
+/// Take back the vote, answers of a quiz are final
+#[instrument(name = "Retract poll vote", skip(pool, credentials, hub))]
+pub async fn retract_vote(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, PollError> {
+    let message_id = path.into_inner();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let poll = lock_poll(message_id, credentials.user_id, &mut transaction).await?;
+    if poll.closed {
+        return Err(PollError::Closed);
+    }
+    if poll.quiz {
+        return Err(PollError::QuizAnswered);
+    }
+
+    let res = sqlx::query!(
+        "DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2",
+        message_id,
+        credentials.user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete poll votes")?;
+    if res.rows_affected() == 0 {
+        return Err(PollError::NotVoted);
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    respond_with_poll(poll.chat_id, message_id, credentials.user_id, &pool, &hub).await
+}
+
+/// Stop the voting, only the creator of the poll can close it
+#[instrument(name = "Close poll", skip(pool, credentials, hub))]
+pub async fn close_poll(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, PollError> {
+    let message_id = path.into_inner();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let poll = lock_poll(message_id, credentials.user_id, &mut transaction).await?;
+    if poll.sender_id != credentials.user_id {
+        return Err(PollError::NoPermission);
+    }
+    if poll.closed {
+        return Err(PollError::Closed);
+    }
+
+    sqlx::query!(
+        "UPDATE polls SET closed_at = now() WHERE message_id = $1",
+        message_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to close poll")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    respond_with_poll(poll.chat_id, message_id, credentials.user_id, &pool, &hub).await
+}
+
+struct LockedPoll {
+    chat_id: i64,
+    sender_id: i64,
+    option_count: i16,
+    multiple_choice: bool,
+    quiz: bool,
+    closed: bool,
+}
+
+/// Lock the poll for the transaction, so the votes of a user are changed one request at a time
+///
+/// Only participants of the chat can see the poll
+async fn lock_poll(
+    message_id: i64,
+    user_id: i64,
+    connection: &mut PgConnection,
+) -> Result<LockedPoll, PollError> {
+    let poll = sqlx::query_as!(
+        LockedPoll,
+        r#"
+        SELECT
+            m.chat_id,
+            m.sender_id,
+            cardinality(p.options)::smallint AS "option_count!",
+            p.multiple_choice,
+            p.correct_option IS NOT NULL AS "quiz!",
+            p.closed_at IS NOT NULL AS "closed!"
+        FROM polls AS p
+        JOIN messages AS m ON m.id = p.message_id
+        JOIN chat_participants AS cp ON cp.chat_id = m.chat_id AND cp.user_id = $2
+        WHERE p.message_id = $1
+        FOR UPDATE OF p
+        "#,
+        message_id,
+        user_id,
+    )
+    .fetch_optional(connection)
+    .await
+    .context("Failed to query poll")?;
+
+    poll.ok_or(PollError::PollNotFound)
+}
+
+/// Push the new tally to every participant and return the poll as seen by the user
+async fn respond_with_poll(
+    chat_id: i64,
+    message_id: i64,
+    user_id: i64,
+    pool: &PgPool,
+    hub: &Hub,
+) -> Result<HttpResponse, PollError> {
+    let poll = load_polls(&[message_id], user_id, pool)
+        .await?
+        .remove(&message_id)
+        .context("Poll disappeared")?;
+
+    let recipients = load_participants(chat_id, pool)
+        .await
+        .context("Failed to load participants")?;
+    hub.send_many(
+        &recipients,
+        Event::PollUpdate {
+            chat_id,
+            message_id,
+            votes: poll.options.iter().map(|option| option.votes).collect(),
+            total_voters: poll.total_voters,
+            closed: poll.closed,
+        },
+    );
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message_id": message_id,
+        "poll": poll,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PollError {
+    #[error("Poll not found")]
+    PollNotFound,
+    #[error("Poll is closed")]
+    Closed,
+    #[error("Invalid options")]
+    BadOptions,
+    #[error("Already voted in the poll")]
+    AlreadyVoted,
+    #[error("Not voted in the poll")]
+    NotVoted,
+    #[error("Quiz answers cannot be retracted")]
+    QuizAnswered,
+    #[error("Only the creator can close the poll")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PollError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PollError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PollError::PollNotFound => StatusCode::NOT_FOUND,
+            PollError::Closed
+            | PollError::BadOptions
+            | PollError::AlreadyVoted
+            | PollError::NotVoted
+            | PollError::QuizAnswered => StatusCode::BAD_REQUEST,
+            PollError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            PollError::UnknownError(_) => "Internal Server Error",
+            PollError::PollNotFound => "Poll not found",
+            PollError::Closed => "The poll is closed",
+            PollError::BadOptions => {
+                "Choose one of the options, or several in a multiple choice poll"
+            }
+            PollError::AlreadyVoted => "Already voted, retract the vote to change it",
+            PollError::NotVoted => "Not voted in the poll",
+            PollError::QuizAnswered => "Quiz answers cannot be retracted",
+            PollError::NoPermission => "Only the creator can close the poll",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
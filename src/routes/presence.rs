@@ -0,0 +1,88 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    presence::Presence,
+    routes::{
+        blocks::is_blocked_between,
+        privacy::{PrivacyKey, is_allowed},
+    },
+};
+
+#[instrument(name = "Get presence", skip(pool, credentials, presence))]
+pub async fn get_presence(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    presence: web::Data<Presence>,
+) -> Result<HttpResponse, GetPresenceError> {
+    let user_id = path.into_inner();
+
+    let Some(user) = sqlx::query!("SELECT last_seen_at FROM users WHERE id = $1", user_id)
+        .fetch_optional(pool.as_ref())
+        .await
+        .context("Failed to query user")?
+    else {
+        return Err(GetPresenceError::UserNotFound);
+    };
+
+    let visible = user_id == credentials.user_id
+        || (!is_blocked_between(user_id, credentials.user_id, pool.as_ref())
+            .await
+            .context("Failed to query blocks")?
+            && is_allowed(user_id, credentials.user_id, PrivacyKey::LastSeen, &pool)
+                .await
+                .context("Failed to check privacy rule")?);
+    if !visible {
+        return Ok(HttpResponse::Ok().json(json!({
+            "user_id": user_id,
+            "status": "hidden",
+            "last_seen_at": null,
+        })));
+    }
+
+    let status = if presence.is_online(user_id) {
+        "online"
+    } else {
+        "offline"
+    };
+    let last_seen_at = presence
+        .last_seen(user_id)
+        .or(user.last_seen_at.map(|time| time.unix_timestamp()));
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": user_id,
+        "status": status,
+        "last_seen_at": last_seen_at,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GetPresenceError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for GetPresenceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            GetPresenceError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            GetPresenceError::UserNotFound => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            GetPresenceError::UnknownError(_) => "Internal Server Error",
+            GetPresenceError::UserNotFound => "User not found",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,321 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::{BearerAuth, normalize_username},
+    error::response_error,
+    routes::user::{UserProfile, hide_profile_photos},
+};
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyKey {
+    LastSeen,
+    ProfilePhoto,
+    GroupInvites,
+    PrivateChats,
+}
+
+impl PrivacyKey {
+    pub const ALL: [PrivacyKey; 4] = [
+        PrivacyKey::LastSeen,
+        PrivacyKey::ProfilePhoto,
+        PrivacyKey::GroupInvites,
+        PrivacyKey::PrivateChats,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrivacyKey::LastSeen => "last_seen",
+            PrivacyKey::ProfilePhoto => "profile_photo",
+            PrivacyKey::GroupInvites => "group_invites",
+            PrivacyKey::PrivateChats => "private_chats",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Everybody,
+    Contacts,
+    Nobody,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Everybody => "everybody",
+            Visibility::Contacts => "contacts",
+            Visibility::Nobody => "nobody",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetPrivacyModel {
+    key: PrivacyKey,
+    visibility: Visibility,
+    /// Usernames always allowed regardless of the visibility
+    #[serde(default)]
+    allow: Vec<String>,
+    /// Usernames always denied regardless of the visibility
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+#[instrument(name = "Set privacy rule", skip(payload, pool, credentials))]
+pub async fn set_privacy(
+    payload: Json<SetPrivacyModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, PrivacyError> {
+    let payload = payload.into_inner();
+
+    let allow = load_user_ids(&payload.allow, &pool).await?;
+    let deny = load_user_ids(&payload.deny, &pool).await?;
+    if allow.iter().any(|id| deny.contains(id)) {
+        return Err(PrivacyError::ConflictingExceptions);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO privacy_rules (user_id, key, visibility) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, key) DO UPDATE SET visibility = EXCLUDED.visibility
+        "#,
+        credentials.user_id,
+        payload.key.as_str(),
+        payload.visibility.as_str(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to upsert privacy rule")?;
+
+    // the exceptions are replaced as a whole
+    sqlx::query!(
+        "DELETE FROM privacy_exceptions WHERE user_id = $1 AND key = $2",
+        credentials.user_id,
+        payload.key.as_str(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete privacy exceptions")?;
+
+    for (targets, is_allowed) in [(&allow, true), (&deny, false)] {
+        sqlx::query!(
+            r#"
+            INSERT INTO privacy_exceptions (user_id, key, target_id, allow)
+            SELECT $1, $2, target_id, $4 FROM UNNEST($3::bigint[]) AS target_id
+            ON CONFLICT DO NOTHING
+            "#,
+            credentials.user_id,
+            payload.key.as_str(),
+            targets,
+            is_allowed,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to insert privacy exceptions")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[instrument(name = "List privacy rules", skip(pool, credentials))]
+pub async fn list_privacy(
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, PrivacyError> {
+    let mut rules = Vec::with_capacity(PrivacyKey::ALL.len());
+    for key in PrivacyKey::ALL {
+        let visibility = sqlx::query_scalar!(
+            "SELECT visibility FROM privacy_rules WHERE user_id = $1 AND key = $2",
+            credentials.user_id,
+            key.as_str(),
+        )
+        .fetch_optional(pool.as_ref())
+        .await
+        .context("Failed to query privacy rule")?
+        .unwrap_or_else(|| Visibility::Everybody.as_str().to_string());
+
+        let exceptions = sqlx::query!(
+            r#"
+            SELECT e.allow, u.id, u.username, u.display_name, u.photo_media_id
+            FROM privacy_exceptions AS e
+            JOIN users AS u ON e.target_id = u.id
+            WHERE e.user_id = $1 AND e.key = $2
+            ORDER BY u.id
+            "#,
+            credentials.user_id,
+            key.as_str(),
+        )
+        .fetch_all(pool.as_ref())
+        .await
+        .context("Failed to query privacy exceptions")?;
+
+        let mut allow = Vec::new();
+        let mut deny = Vec::new();
+        for exception in exceptions {
+            let profile = UserProfile {
+                id: exception.id,
+                username: exception.username,
+                display_name: exception.display_name,
+                photo_media_id: exception.photo_media_id,
+            };
+            if exception.allow {
+                allow.push(profile);
+            } else {
+                deny.push(profile);
+            }
+        }
+
+        hide_profile_photos(&mut allow, credentials.user_id, &pool)
+            .await
+            .context("Failed to check profile photo privacy")?;
+        hide_profile_photos(&mut deny, credentials.user_id, &pool)
+            .await
+            .context("Failed to check profile photo privacy")?;
+
+        rules.push(json!({
+            "key": key,
+            "visibility": visibility,
+            "allow": allow,
+            "deny": deny,
+        }));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "rules": rules,
+    })))
+}
+
+/// Check whether `viewer_id` passes the privacy rule `key` of `owner_id`
+pub async fn is_allowed(
+    owner_id: i64,
+    viewer_id: i64,
+    key: PrivacyKey,
+    pool: &PgPool,
+) -> Result<bool, sqlx::Error> {
+    let allowed = filter_allowed(owner_id, &[viewer_id], key, pool).await?;
+    Ok(!allowed.is_empty())
+}
+
+/// Keep the viewers passing the privacy rule `key` of `owner_id`
+pub async fn filter_allowed(
+    owner_id: i64,
+    viewer_ids: &[i64],
+    key: PrivacyKey,
+    pool: &PgPool,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT viewer.id AS "id!"
+        FROM UNNEST($3::bigint[]) AS viewer(id)
+        WHERE viewer.id = $1 OR COALESCE(
+            (SELECT allow FROM privacy_exceptions WHERE user_id = $1 AND key = $2 AND target_id = viewer.id),
+            CASE COALESCE((SELECT visibility FROM privacy_rules WHERE user_id = $1 AND key = $2), 'everybody')
+                WHEN 'everybody' THEN true
+                WHEN 'contacts' THEN EXISTS(SELECT 1 FROM contacts WHERE owner_id = $1 AND contact_id = viewer.id)
+                ELSE false
+            END
+        )
+        "#,
+        owner_id,
+        key.as_str(),
+        viewer_ids,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Keep the owners whose privacy rule `key` is passed by `viewer_id`
+pub async fn filter_visible_owners(
+    owner_ids: &[i64],
+    viewer_id: i64,
+    key: PrivacyKey,
+    pool: &PgPool,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT owner.id AS "id!"
+        FROM UNNEST($1::bigint[]) AS owner(id)
+        WHERE owner.id = $3 OR COALESCE(
+            (SELECT allow FROM privacy_exceptions WHERE user_id = owner.id AND key = $2 AND target_id = $3),
+            CASE COALESCE((SELECT visibility FROM privacy_rules WHERE user_id = owner.id AND key = $2), 'everybody')
+                WHEN 'everybody' THEN true
+                WHEN 'contacts' THEN EXISTS(SELECT 1 FROM contacts WHERE owner_id = owner.id AND contact_id = $3)
+                ELSE false
+            END
+        )
+        "#,
+        owner_ids,
+        key.as_str(),
+        viewer_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn load_user_ids(usernames: &[String], pool: &PgPool) -> Result<Vec<i64>, PrivacyError> {
+    let mut usernames = usernames
+        .iter()
+        .map(|username| normalize_username(username))
+        .collect::<Vec<_>>();
+    usernames.sort();
+    usernames.dedup();
+
+    let ids = sqlx::query_scalar!("SELECT id FROM users WHERE username = ANY($1)", &usernames,)
+        .fetch_all(pool)
+        .await
+        .context("Failed to load exception users")?;
+
+    if ids.len() != usernames.len() {
+        return Err(PrivacyError::UserNotFound);
+    }
+
+    Ok(ids)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PrivacyError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("A user is both allowed and denied")]
+    ConflictingExceptions,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PrivacyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PrivacyError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PrivacyError::UserNotFound | PrivacyError::ConflictingExceptions => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            PrivacyError::UnknownError(_) => "Internal Server Error",
+            PrivacyError::UserNotFound => "User not found",
+            PrivacyError::ConflictingExceptions => "A user is both allowed and denied",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    realtime::{Event, Hub},
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        blocks::is_blocked_between,
+        chats::{AdminRight, load_other_participants, load_participant, load_participants},
+        moderation::{Permission, load_restrictions},
+    },
+};
+
+/// Most emoji a chat can allow
+const MAX_ALLOWED_REACTIONS: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct ReactionModel {
+    emoji: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the requesting user reacted with the emoji
+    pub chosen: bool,
+}
+
+#[instrument(name = "Add reaction", skip(payload, pool, credentials, hub))]
+pub async fn add_reaction(
+    path: web::Path<i64>,
+    payload: Json<ReactionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, ReactionError> {
+    let message_id = path.into_inner();
+
+    if !is_valid_emoji(&payload.emoji) {
+        return Err(ReactionError::InvalidEmoji);
+    }
+
+    let Some(message) = sqlx::query!(
+        r#"
+        SELECT m.chat_id, c.type AS chat_type, c.allowed_reactions
+        FROM messages AS m
+        JOIN chats AS c ON c.id = m.chat_id
+        JOIN chat_participants AS cp ON cp.chat_id = m.chat_id AND cp.user_id = $2
+        WHERE m.id = $1
+        "#,
+        message_id,
+        credentials.user_id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to query message")?
+    else {
+        return Err(ReactionError::MessageNotFound);
+    };
+
+    if let Some(allowed) = &message.allowed_reactions
+        && !allowed.contains(&payload.emoji)
+    {
+        return Err(ReactionError::NotAllowed);
+    }
+
+    // nobody can react in a private chat once any side blocked the other
+    if message.chat_type == "private" {
+        let peer_id = load_other_participants(message.chat_id, credentials.user_id, pool.as_ref())
+            .await
+            .context("Failed to query peer user")?
+            .pop();
+
+        if let Some(peer_id) = peer_id
+            && is_blocked_between(credentials.user_id, peer_id, pool.as_ref())
+                .await
+                .context("Failed to query blocks")?
+        {
+            return Err(ReactionError::Blocked);
+        }
+    } else if load_restrictions(message.chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load restrictions")?
+        .contains(Permission::AddReactions)
+    {
+        return Err(ReactionError::Restricted);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO message_reactions (message_id, user_id, emoji) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        message_id,
+        credentials.user_id,
+        payload.emoji,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to insert reaction")?;
+
+    if res.rows_affected() > 0 {
+        broadcast_reaction(
+            message.chat_id,
+            message_id,
+            credentials.user_id,
+            &payload.emoji,
+            true,
+            &pool,
+            &hub,
+        )
+        .await?;
+    }
+
+    let reactions = load_reactions(&[message_id], credentials.user_id, &pool)
+        .await?
+        .remove(&message_id)
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Created().json(json!({
+        "message_id": message_id,
+        "reactions": reactions,
+    })))
+}
+
+#[instrument(name = "Remove reaction", skip(payload, pool, credentials, hub))]
+pub async fn remove_reaction(
+    path: web::Path<i64>,
+    payload: Json<ReactionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+) -> Result<HttpResponse, ReactionError> {
+    let message_id = path.into_inner();
+
+    let Some(chat_id) = sqlx::query_scalar!(
+        r#"
+        DELETE FROM message_reactions AS r
+        USING messages AS m
+        WHERE r.message_id = $1 AND r.user_id = $2 AND r.emoji = $3 AND m.id = r.message_id
+        RETURNING m.chat_id
+        "#,
+        message_id,
+        credentials.user_id,
+        payload.emoji,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to delete reaction")?
+    else {
+        return Err(ReactionError::ReactionNotFound);
+    };
+
+    broadcast_reaction(
+        chat_id,
+        message_id,
+        credentials.user_id,
+        &payload.emoji,
+        false,
+        &pool,
+        &hub,
+    )
+    .await?;
+
+    let reactions = load_reactions(&[message_id], credentials.user_id, &pool)
+        .await?
+        .remove(&message_id)
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message_id": message_id,
+        "reactions": reactions,
+    })))
+}
+
+/// Push the change to every participant, including the other connections of the user
+async fn broadcast_reaction(
+    chat_id: i64,
+    message_id: i64,
+    user_id: i64,
+    emoji: &str,
+    added: bool,
+    pool: &PgPool,
+    hub: &Hub,
+) -> anyhow::Result<()> {
+    let recipients = load_participants(chat_id, pool)
+        .await
+        .context("Failed to load participants")?;
+
+    hub.send_many(
+        &recipients,
+        Event::Reaction {
+            chat_id,
+            message_id,
+            user_id,
+            emoji: emoji.to_string(),
+            added,
+        },
+    );
+
+    Ok(())
+}
+
+/// Load the reaction counts of the messages, the most used emoji first
+pub(crate) async fn load_reactions(
+    message_ids: &[i64],
+    user_id: i64,
+    pool: &PgPool,
+) -> anyhow::Result<HashMap<i64, Vec<ReactionCount>>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            message_id,
+            emoji,
+            COUNT(*) AS "count!",
+            BOOL_OR(user_id = $2) AS "chosen!"
+        FROM message_reactions
+        WHERE message_id = ANY($1)
+        GROUP BY message_id, emoji
+        ORDER BY message_id, COUNT(*) DESC, MIN(created_at)
+        "#,
+        message_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query reactions")?;
+
+    let mut reactions: HashMap<i64, Vec<ReactionCount>> = HashMap::new();
+    for row in rows {
+        reactions
+            .entry(row.message_id)
+            .or_default()
+            .push(ReactionCount {
+                emoji: row.emoji,
+                count: row.count,
+                chosen: row.chosen,
+            });
+    }
+
+    Ok(reactions)
+}
+
+/// Accept a short sequence of non-ASCII symbols, such as an emoji with modifiers or joiners,
+/// or a keycap
+fn is_valid_emoji(emoji: &str) -> bool {
+    let length = emoji.chars().count();
+    is_keycap(emoji)
+        || ((1..=16).contains(&length)
+            && emoji
+                .chars()
+                .all(|c| !c.is_ascii() && !c.is_alphanumeric() && !c.is_whitespace()))
+}
+
+/// Keycap emoji start with an ASCII digit, `#` or `*`, such as 1️⃣
+fn is_keycap(emoji: &str) -> bool {
+    let mut chars = emoji.chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next(), chars.next()),
+        (
+            Some('0'..='9' | '#' | '*'),
+            Some('\u{FE0F}'),
+            Some('\u{20E3}'),
+            None
+        )
+    )
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReactionError {
+    #[error("Message not found")]
+    MessageNotFound,
+    #[error("Reaction not found")]
+    ReactionNotFound,
+    #[error("Invalid emoji")]
+    InvalidEmoji,
+    #[error("Reaction not allowed in the chat")]
+    NotAllowed,
+    #[error("Blocked by the peer user")]
+    Blocked,
+    #[error("Restricted by the admins")]
+    Restricted,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ReactionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ReactionError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ReactionError::MessageNotFound => StatusCode::NOT_FOUND,
+            ReactionError::ReactionNotFound
+            | ReactionError::InvalidEmoji
+            | ReactionError::NotAllowed => StatusCode::BAD_REQUEST,
+            ReactionError::Blocked | ReactionError::Restricted => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ReactionError::UnknownError(_) => "Internal Server Error",
+            ReactionError::MessageNotFound => "Message not found",
+            ReactionError::ReactionNotFound => "Reaction not found",
+            ReactionError::InvalidEmoji => "Invalid emoji",
+            ReactionError::NotAllowed => "This reaction is not allowed in the chat",
+            ReactionError::Blocked => "Cannot react to messages of this user",
+            ReactionError::Restricted => "Restricted from adding reactions by the admins",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
+
+#[instrument(name = "Get allowed reactions", skip(pool, credentials))]
+pub async fn get_allowed_reactions(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AllowedReactionsError> {
+    let chat_id = path.into_inner();
+
+    if load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+        .is_none()
+    {
+        return Err(AllowedReactionsError::NoPermission);
+    }
+
+    let allowed =
+        sqlx::query_scalar!("SELECT allowed_reactions FROM chats WHERE id = $1", chat_id,)
+            .fetch_one(pool.as_ref())
+            .await
+            .context("Failed to query allowed reactions")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "allowed": allowed,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AllowedReactionsModel {
+    /// None allows every emoji
+    allowed: Option<Vec<String>>,
+}
+
+#[instrument(name = "Set allowed reactions", skip(payload, pool, credentials))]
+pub async fn set_allowed_reactions(
+    path: web::Path<i64>,
+    payload: Json<AllowedReactionsModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, AllowedReactionsError> {
+    let chat_id = path.into_inner();
+
+    let Some(participant) = load_participant(chat_id, credentials.user_id, pool.as_ref())
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(AllowedReactionsError::NoPermission);
+    };
+    if participant.chat_type == "private" {
+        return Err(AllowedReactionsError::NotGroup);
+    }
+    if !participant.has_right(AdminRight::ChangeInfo) {
+        return Err(AllowedReactionsError::NoPermission);
+    }
+
+    let mut allowed = payload.into_inner().allowed;
+    if let Some(allowed) = &mut allowed {
+        if !allowed.iter().all(|emoji| is_valid_emoji(emoji)) {
+            return Err(AllowedReactionsError::InvalidEmoji);
+        }
+
+        // keep the order chosen by the admin
+        let mut seen = HashSet::new();
+        allowed.retain(|emoji| seen.insert(emoji.clone()));
+        if allowed.len() > MAX_ALLOWED_REACTIONS {
+            return Err(AllowedReactionsError::TooMany);
+        }
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        "UPDATE chats SET allowed_reactions = $2 WHERE id = $1",
+        chat_id,
+        allowed.as_deref(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update allowed reactions")?;
+
+    record_admin_action(
+        chat_id,
+        credentials.user_id,
+        AdminLogAction::ChangeAllowedReactions {
+            allowed: allowed.clone(),
+        },
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "allowed": allowed,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AllowedReactionsError {
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to change the allowed reactions")]
+    NoPermission,
+    #[error("Invalid emoji")]
+    InvalidEmoji,
+    #[error("Too many allowed reactions")]
+    TooMany,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AllowedReactionsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AllowedReactionsError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AllowedReactionsError::NotGroup
+            | AllowedReactionsError::InvalidEmoji
+            | AllowedReactionsError::TooMany => StatusCode::BAD_REQUEST,
+            AllowedReactionsError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            AllowedReactionsError::UnknownError(_) => "Internal Server Error",
+            AllowedReactionsError::NotGroup => "Chat is not a group",
+            AllowedReactionsError::NoPermission => "No permission to change the allowed reactions",
+            AllowedReactionsError::InvalidEmoji => "Invalid emoji",
+            AllowedReactionsError::TooMany => "At most 100 reactions can be allowed",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
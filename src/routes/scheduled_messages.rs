@@ -0,0 +1,302 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        messages::{MessageKind, SendMessageError, SendMessageModel, check_message},
+        polls::NewPoll,
+    },
+};
+
+/// Messages can be scheduled at most a year ahead
+const MAX_SCHEDULE_AHEAD: i64 = 365 * 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleMessageModel {
+    #[serde(flatten)]
+    message: SendMessageModel,
+    /// Unix timestamp
+    send_at: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct ScheduledMessage {
+    pub scheduled_id: i64,
+    pub chat_id: i64,
+    pub content: String,
+    pub kind: String,
+    pub media_ids: Vec<i64>,
+    pub ttl: Option<i32>,
+    pub poll: Option<NewPoll>,
+    pub topic_id: Option<i64>,
+    /// Unix timestamp
+    pub send_at: i64,
+}
+
+/// Schedule a message to be sent into the chat at a future time
+///
+/// The message is checked now and again when it is due, it is dropped if the sender cannot send it anymore
+#[instrument(name = "Schedule message", skip(payload, pool, credentials))]
+pub async fn schedule_message(
+    payload: Json<ScheduleMessageModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ScheduledMessageError> {
+    let ScheduleMessageModel {
+        mut message,
+        send_at,
+    } = payload.into_inner();
+
+    check_send_at(send_at)?;
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire connection")?;
+    check_message(&mut message, credentials.user_id, &mut connection).await?;
+
+    let scheduled_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO scheduled_messages
+            (chat_id, sender_id, content, kind, media_ids, ttl, poll, topic_id, send_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, to_timestamp($9))
+        RETURNING id
+        "#,
+        message.chat_id,
+        credentials.user_id,
+        message.content,
+        message.kind.as_str(),
+        &message.media_ids,
+        message.ttl,
+        message.poll.clone().map(sqlx::types::Json) as _,
+        message.topic_id,
+        send_at as f64,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to insert scheduled message")?;
+
+    Ok(HttpResponse::Created().json(ScheduledMessage {
+        scheduled_id,
+        chat_id: message.chat_id,
+        content: message.content,
+        kind: message.kind.as_str().to_string(),
+        media_ids: message.media_ids,
+        ttl: message.ttl,
+        poll: message.poll,
+        topic_id: message.topic_id,
+        send_at,
+    }))
+}
+
+/// Messages the user scheduled in the chat, the earliest first
+#[instrument(name = "List scheduled messages", skip(pool, credentials))]
+pub async fn list_scheduled_messages(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ScheduledMessageError> {
+    let chat_id = path.into_inner();
+
+    let messages: Vec<ScheduledMessage> = sqlx::query!(
+        r#"
+        SELECT
+            id, chat_id, content, kind, media_ids, ttl,
+            poll AS "poll: sqlx::types::Json<NewPoll>",
+            topic_id,
+            send_at
+        FROM scheduled_messages
+        WHERE chat_id = $1 AND sender_id = $2
+        ORDER BY send_at, id
+        "#,
+        chat_id,
+        credentials.user_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query scheduled messages")?
+    .into_iter()
+    .map(|row| ScheduledMessage {
+        scheduled_id: row.id,
+        chat_id: row.chat_id,
+        content: row.content,
+        kind: row.kind,
+        media_ids: row.media_ids,
+        ttl: row.ttl,
+        poll: row.poll.map(|poll| poll.0),
+        topic_id: row.topic_id,
+        send_at: row.send_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "messages": messages,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EditScheduledMessageModel {
+    content: Option<String>,
+    /// Unix timestamp
+    send_at: Option<i64>,
+}
+
+/// Change the content or the time of a scheduled message
+#[instrument(name = "Edit scheduled message", skip(payload, pool, credentials))]
+pub async fn edit_scheduled_message(
+    path: web::Path<i64>,
+    payload: Json<EditScheduledMessageModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ScheduledMessageError> {
+    let scheduled_id = path.into_inner();
+    let payload = payload.into_inner();
+
+    let Some(scheduled) = sqlx::query!(
+        r#"
+        SELECT
+            chat_id, content, kind, media_ids, ttl,
+            poll AS "poll: sqlx::types::Json<NewPoll>",
+            topic_id,
+            send_at
+        FROM scheduled_messages
+        WHERE id = $1 AND sender_id = $2
+        "#,
+        scheduled_id,
+        credentials.user_id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to query scheduled message")?
+    else {
+        return Err(ScheduledMessageError::NotFound);
+    };
+
+    let send_at = match payload.send_at {
+        Some(send_at) => {
+            check_send_at(send_at)?;
+            send_at
+        }
+        None => scheduled.send_at.unix_timestamp(),
+    };
+    let mut message = SendMessageModel {
+        chat_id: scheduled.chat_id,
+        content: payload.content.unwrap_or(scheduled.content),
+        media_ids: scheduled.media_ids,
+        kind: MessageKind::try_from(scheduled.kind).map_err(anyhow::Error::msg)?,
+        ttl: scheduled.ttl,
+        poll: scheduled.poll.map(|poll| poll.0),
+        topic_id: scheduled.topic_id,
+    };
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire connection")?;
+    check_message(&mut message, credentials.user_id, &mut connection).await?;
+
+    // the message may have been posted in the meantime
+    let updated = sqlx::query!(
+        r#"
+        UPDATE scheduled_messages SET content = $3, send_at = to_timestamp($4)
+        WHERE id = $1 AND sender_id = $2
+        "#,
+        scheduled_id,
+        credentials.user_id,
+        message.content,
+        send_at as f64,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update scheduled message")?;
+    if updated.rows_affected() == 0 {
+        return Err(ScheduledMessageError::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(ScheduledMessage {
+        scheduled_id,
+        chat_id: message.chat_id,
+        content: message.content,
+        kind: message.kind.as_str().to_string(),
+        media_ids: message.media_ids,
+        ttl: message.ttl,
+        poll: message.poll,
+        topic_id: message.topic_id,
+        send_at,
+    }))
+}
+
+#[instrument(name = "Cancel scheduled message", skip(pool, credentials))]
+pub async fn cancel_scheduled_message(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, ScheduledMessageError> {
+    let scheduled_id = path.into_inner();
+
+    let deleted = sqlx::query!(
+        "DELETE FROM scheduled_messages WHERE id = $1 AND sender_id = $2",
+        scheduled_id,
+        credentials.user_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete scheduled message")?;
+    if deleted.rows_affected() == 0 {
+        return Err(ScheduledMessageError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn check_send_at(send_at: i64) -> Result<(), ScheduledMessageError> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if send_at <= now || send_at > now + MAX_SCHEDULE_AHEAD {
+        return Err(ScheduledMessageError::BadSendTime);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduledMessageError {
+    #[error("Scheduled message not found")]
+    NotFound,
+    #[error("Send time not in the next year")]
+    BadSendTime,
+    #[error(transparent)]
+    InvalidMessage(#[from] SendMessageError),
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ScheduledMessageError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ScheduledMessageError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ScheduledMessageError::NotFound => StatusCode::NOT_FOUND,
+            ScheduledMessageError::BadSendTime => StatusCode::BAD_REQUEST,
+            ScheduledMessageError::InvalidMessage(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            ScheduledMessageError::UnknownError(_) => "Internal Server Error",
+            ScheduledMessageError::NotFound => "Scheduled message not found",
+            ScheduledMessageError::BadSendTime => {
+                "The send time must be in the future, at most a year ahead"
+            }
+            ScheduledMessageError::InvalidMessage(err) => return err.error_response(),
+        };
+        response_error(self.status_code(), msg)
+    }
+}
@@ -0,0 +1,604 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    routes::{
+        admin_log::{AdminLogAction, record_admin_action},
+        chats::{AdminRight, Participant, load_participant},
+        messages::SendMessageError,
+    },
+};
+
+/// Characters of the title of a topic
+const MAX_TOPIC_TITLE_LENGTH: usize = 128;
+/// Title of the topic created for the messages sent before the topics were enabled
+const GENERAL_TOPIC_TITLE: &str = "General";
+
+#[derive(serde::Deserialize)]
+pub struct ToggleTopicsModel {
+    enabled: bool,
+}
+
+/// Enable or disable the topics of the group
+///
+/// Enabling the topics moves the messages without a topic into the General topic
+#[instrument(name = "Toggle topics", skip(payload, pool, credentials))]
+pub async fn toggle_topics(
+    path: web::Path<i64>,
+    payload: Json<ToggleTopicsModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let chat_id = path.into_inner();
+
+    let participant = load_group_participant(chat_id, credentials.user_id, &pool).await?;
+    if !participant.has_right(AdminRight::ChangeInfo) {
+        return Err(TopicError::NoPermission);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "UPDATE chats SET topics_enabled = $2 WHERE id = $1 AND topics_enabled <> $2",
+        chat_id,
+        payload.enabled,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update chat")?;
+
+    if res.rows_affected() > 0 {
+        if payload.enabled {
+            sqlx::query!(
+                r#"
+                INSERT INTO forum_topics (chat_id, title, is_general) VALUES ($1, $2, true)
+                ON CONFLICT (chat_id) WHERE is_general DO NOTHING
+                "#,
+                chat_id,
+                GENERAL_TOPIC_TITLE,
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to insert General topic")?;
+
+            // including the messages sent while the topics were disabled
+            sqlx::query!(
+                r#"
+                UPDATE messages
+                SET topic_id = (SELECT id FROM forum_topics WHERE chat_id = $1 AND is_general)
+                WHERE chat_id = $1 AND topic_id IS NULL
+                "#,
+                chat_id,
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to move messages into General topic")?;
+        }
+
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ToggleTopics {
+                enabled: payload.enabled,
+            },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "chat_id": chat_id,
+        "topics_enabled": payload.enabled,
+    })))
+}
+
+#[derive(serde::Serialize)]
+pub struct Topic {
+    pub topic_id: i64,
+    pub title: String,
+    /// None for the General topic
+    pub created_by: Option<i64>,
+    pub is_general: bool,
+    pub closed: bool,
+    /// Messages of the others newer than the last one read by the user
+    pub unread_count: i64,
+    pub last_message_id: Option<i64>,
+    /// Unix timestamp
+    pub created_at: i64,
+}
+
+/// Topics of the group, the General topic first
+#[instrument(name = "List topics", skip(pool, credentials))]
+pub async fn list_topics(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let chat_id = path.into_inner();
+
+    load_forum_participant(chat_id, credentials.user_id, &pool).await?;
+
+    let topics: Vec<Topic> = sqlx::query!(
+        r#"
+        SELECT
+            t.id,
+            t.title,
+            t.created_by,
+            t.is_general,
+            t.closed_at IS NOT NULL AS "closed!",
+            t.created_at,
+            (
+                SELECT COUNT(*) FROM messages AS m
+                WHERE
+                    m.topic_id = t.id
+                    AND m.sender_id <> $2
+                    AND m.id > COALESCE(r.last_read_message_id, 0)
+                    AND (m.expires_at IS NULL OR m.expires_at > now())
+            ) AS "unread_count!",
+            (
+                SELECT MAX(m.id) FROM messages AS m
+                WHERE m.topic_id = t.id AND (m.expires_at IS NULL OR m.expires_at > now())
+            ) AS last_message_id
+        FROM forum_topics AS t
+        LEFT JOIN forum_topic_reads AS r ON r.topic_id = t.id AND r.user_id = $2
+        WHERE t.chat_id = $1
+        ORDER BY t.is_general DESC, t.id
+        "#,
+        chat_id,
+        credentials.user_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query topics")?
+    .into_iter()
+    .map(|row| Topic {
+        topic_id: row.id,
+        title: row.title,
+        created_by: row.created_by,
+        is_general: row.is_general,
+        closed: row.closed,
+        unread_count: row.unread_count,
+        last_message_id: row.last_message_id,
+        created_at: row.created_at.unix_timestamp(),
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "topics": topics,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TopicTitleModel {
+    title: String,
+}
+
+/// Any member can start a topic
+#[instrument(name = "Create topic", skip(payload, pool, credentials))]
+pub async fn create_topic(
+    path: web::Path<i64>,
+    payload: Json<TopicTitleModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let chat_id = path.into_inner();
+    let title = check_title(&payload.title)?;
+
+    load_forum_participant(chat_id, credentials.user_id, &pool).await?;
+
+    let topic = sqlx::query!(
+        r#"
+        INSERT INTO forum_topics (chat_id, title, created_by) VALUES ($1, $2, $3)
+        RETURNING id, created_at
+        "#,
+        chat_id,
+        title,
+        credentials.user_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to insert topic")?;
+
+    Ok(HttpResponse::Created().json(Topic {
+        topic_id: topic.id,
+        title: title.to_string(),
+        created_by: Some(credentials.user_id),
+        is_general: false,
+        closed: false,
+        unread_count: 0,
+        last_message_id: None,
+        created_at: topic.created_at.unix_timestamp(),
+    }))
+}
+
+#[instrument(name = "Rename topic", skip(payload, pool, credentials))]
+pub async fn rename_topic(
+    path: web::Path<(i64, i64)>,
+    payload: Json<TopicTitleModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let (chat_id, topic_id) = path.into_inner();
+    let title = check_title(&payload.title)?;
+
+    let by_admin = check_topic_manager(chat_id, topic_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        "UPDATE forum_topics SET title = $2 WHERE id = $1",
+        topic_id,
+        title,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update topic")?;
+
+    if by_admin {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::RenameTopic {
+                topic_id,
+                title: title.to_string(),
+            },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "topic_id": topic_id,
+        "title": title,
+    })))
+}
+
+/// Only the admins can write into the topic once closed
+#[instrument(name = "Close topic", skip(pool, credentials))]
+pub async fn close_topic(
+    path: web::Path<(i64, i64)>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let (chat_id, topic_id) = path.into_inner();
+
+    let by_admin = check_topic_manager(chat_id, topic_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // the General topic takes the messages sent without a topic
+    let res = sqlx::query!(
+        r#"
+        UPDATE forum_topics SET closed_at = now()
+        WHERE id = $1 AND NOT is_general AND closed_at IS NULL
+        "#,
+        topic_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to close topic")?;
+    if res.rows_affected() == 0 {
+        return Err(TopicError::CannotClose);
+    }
+
+    if by_admin {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::CloseTopic { topic_id },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "topic_id": topic_id,
+        "closed": true,
+    })))
+}
+
+#[instrument(name = "Reopen topic", skip(pool, credentials))]
+pub async fn reopen_topic(
+    path: web::Path<(i64, i64)>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let (chat_id, topic_id) = path.into_inner();
+
+    let by_admin = check_topic_manager(chat_id, topic_id, credentials.user_id, &pool).await?;
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let res = sqlx::query!(
+        "UPDATE forum_topics SET closed_at = NULL WHERE id = $1 AND closed_at IS NOT NULL",
+        topic_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reopen topic")?;
+    if res.rows_affected() == 0 {
+        return Err(TopicError::NotClosed);
+    }
+
+    if by_admin {
+        record_admin_action(
+            chat_id,
+            credentials.user_id,
+            AdminLogAction::ReopenTopic { topic_id },
+            &mut *transaction,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "topic_id": topic_id,
+        "closed": false,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReadTopicModel {
+    /// The newest message of the topic read by the user
+    message_id: i64,
+}
+
+/// Move the read position of the user in the topic forward
+#[instrument(name = "Read topic", skip(payload, pool, credentials))]
+pub async fn read_topic(
+    path: web::Path<(i64, i64)>,
+    payload: Json<ReadTopicModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, TopicError> {
+    let (chat_id, topic_id) = path.into_inner();
+
+    load_forum_participant(chat_id, credentials.user_id, &pool).await?;
+
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM messages AS m
+            JOIN forum_topics AS t ON t.id = m.topic_id
+            WHERE m.id = $1 AND t.id = $2 AND t.chat_id = $3
+        ) AS "exists!"
+        "#,
+        payload.message_id,
+        topic_id,
+        chat_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to query message")?;
+    if !exists {
+        return Err(TopicError::MessageNotFound);
+    }
+
+    // reading an older message keeps the position
+    let last_read_message_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO forum_topic_reads (topic_id, user_id, last_read_message_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (topic_id, user_id) DO UPDATE
+        SET last_read_message_id = GREATEST(
+            forum_topic_reads.last_read_message_id,
+            EXCLUDED.last_read_message_id
+        )
+        RETURNING last_read_message_id
+        "#,
+        topic_id,
+        credentials.user_id,
+        payload.message_id,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to upsert topic read")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "topic_id": topic_id,
+        "last_read_message_id": last_read_message_id,
+    })))
+}
+
+/// Resolve the topic a new message goes into
+///
+/// Messages without a topic go into the General topic, chats without topics take none
+pub(crate) async fn resolve_topic(
+    chat_id: i64,
+    topic_id: Option<i64>,
+    participant: &Participant,
+    connection: &mut PgConnection,
+) -> Result<Option<i64>, SendMessageError> {
+    let topics_enabled =
+        sqlx::query_scalar!("SELECT topics_enabled FROM chats WHERE id = $1", chat_id,)
+            .fetch_one(&mut *connection)
+            .await
+            .context("Failed to query chat")?;
+    if !topics_enabled {
+        return match topic_id {
+            Some(_) => Err(SendMessageError::TopicNotFound),
+            None => Ok(None),
+        };
+    }
+
+    let Some(topic) = sqlx::query!(
+        r#"
+        SELECT id, closed_at IS NOT NULL AS "closed!"
+        FROM forum_topics
+        WHERE chat_id = $1 AND (id = $2 OR ($2::bigint IS NULL AND is_general))
+        "#,
+        chat_id,
+        topic_id,
+    )
+    .fetch_optional(connection)
+    .await
+    .context("Failed to query topic")?
+    else {
+        return Err(SendMessageError::TopicNotFound);
+    };
+    if topic.closed && !participant.has_right(AdminRight::ManageTopics) {
+        return Err(SendMessageError::TopicClosed);
+    }
+
+    Ok(Some(topic.id))
+}
+
+fn check_title(title: &str) -> Result<&str, TopicError> {
+    let title = title.trim();
+    if !(1..=MAX_TOPIC_TITLE_LENGTH).contains(&title.chars().count()) {
+        return Err(TopicError::BadTitle);
+    }
+
+    Ok(title)
+}
+
+/// Topics only exist in groups
+async fn load_group_participant(
+    chat_id: i64,
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<Participant, TopicError> {
+    let Some(participant) = load_participant(chat_id, user_id, pool)
+        .await
+        .context("Failed to load participant")?
+    else {
+        return Err(TopicError::NoPermission);
+    };
+    if participant.chat_type != "group" {
+        return Err(TopicError::NotGroup);
+    }
+
+    Ok(participant)
+}
+
+/// The participant of a group with the topics enabled
+async fn load_forum_participant(
+    chat_id: i64,
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<Participant, TopicError> {
+    let participant = load_group_participant(chat_id, user_id, pool).await?;
+
+    let topics_enabled =
+        sqlx::query_scalar!("SELECT topics_enabled FROM chats WHERE id = $1", chat_id,)
+            .fetch_one(pool)
+            .await
+            .context("Failed to query chat")?;
+    if !topics_enabled {
+        return Err(TopicError::TopicsDisabled);
+    }
+
+    Ok(participant)
+}
+
+/// The creator of the topic and the admins with the right to manage topics edit it
+///
+/// Returns whether the user edits the topic of another as an admin
+async fn check_topic_manager(
+    chat_id: i64,
+    topic_id: i64,
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<bool, TopicError> {
+    let participant = load_forum_participant(chat_id, user_id, pool).await?;
+
+    let Some(created_by) = sqlx::query_scalar!(
+        "SELECT created_by FROM forum_topics WHERE id = $1 AND chat_id = $2",
+        topic_id,
+        chat_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query topic")?
+    else {
+        return Err(TopicError::TopicNotFound);
+    };
+    if created_by == Some(user_id) {
+        return Ok(false);
+    }
+    if !participant.has_right(AdminRight::ManageTopics) {
+        return Err(TopicError::NoPermission);
+    }
+
+    Ok(true)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TopicError {
+    #[error("Title length not match the requirement")]
+    BadTitle,
+    #[error("Topic not found")]
+    TopicNotFound,
+    #[error("Message not found in the topic")]
+    MessageNotFound,
+    #[error("Topic cannot be closed")]
+    CannotClose,
+    #[error("Topic is not closed")]
+    NotClosed,
+    #[error("Topics are disabled")]
+    TopicsDisabled,
+    #[error("Chat is not a group")]
+    NotGroup,
+    #[error("No permission to manage the topic")]
+    NoPermission,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for TopicError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TopicError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            TopicError::TopicNotFound | TopicError::MessageNotFound => StatusCode::NOT_FOUND,
+            TopicError::BadTitle
+            | TopicError::CannotClose
+            | TopicError::NotClosed
+            | TopicError::TopicsDisabled
+            | TopicError::NotGroup => StatusCode::BAD_REQUEST,
+            TopicError::NoPermission => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            TopicError::UnknownError(_) => "Internal Server Error",
+            TopicError::BadTitle => "The title must be 1-128 characters",
+            TopicError::TopicNotFound => "Topic not found",
+            TopicError::MessageNotFound => "Message not found in the topic",
+            TopicError::CannotClose => "The topic is already closed or is the General topic",
+            TopicError::NotClosed => "The topic is not closed",
+            TopicError::TopicsDisabled => "Topics are disabled in this chat",
+            TopicError::NotGroup => "Chat is not a group",
+            TopicError::NoPermission => "No permission to manage the topic",
+        };
+        response_error(self.status_code(), msg)
+    }
+}
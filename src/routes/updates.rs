@@ -0,0 +1,63 @@
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_ws::Message;
+use tracing::{event, instrument};
+
+use crate::{auth::BearerAuth, presence::Presence, realtime::Hub};
+
+/// Open the real-time channel of the user
+///
+/// Events are pushed as JSON text frames, frames sent by the client are ignored
+#[instrument(
+    name = "Open updates channel",
+    skip(req, body, credentials, hub, presence)
+)]
+pub async fn updates(
+    req: HttpRequest,
+    body: web::Payload,
+    credentials: BearerAuth,
+    hub: web::Data<Hub>,
+    presence: web::Data<Presence>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+
+    let user_id = credentials.user_id;
+    let (connection_id, mut events) = hub.connect(user_id);
+    presence.connect(user_id);
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    let text = match serde_json::to_string(&event) {
+                        Ok(text) => text,
+                        Err(err) => {
+                            event!(tracing::Level::ERROR, "Failed to serialize event: {err}");
+                            continue;
+                        }
+                    };
+                    if session.text(text).await.is_err() {
+                        break;
+                    }
+                }
+                msg = msg_stream.recv() => match msg {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+
+        hub.disconnect(user_id, connection_id);
+        presence.disconnect(user_id);
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,376 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::StatusCode,
+    web::{self, Json},
+};
+use anyhow::Context;
+use bytes::BytesMut;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::BearerAuth,
+    error::response_error,
+    media::{MediaStore, chunk_count, upload_chunk_key},
+    routes::media::{SaveMediaError, load_storage_usage, save_media},
+    startup::{MaxFileSize, StorageQuota, ThumbnailSizes, UploadChunkSize},
+};
+
+#[derive(serde::Deserialize)]
+pub struct CreateUploadSessionModel {
+    total_size: i64,
+}
+
+/// Start a resumable upload, the file is then sent in numbered chunks
+#[instrument(
+    name = "Create upload session",
+    skip(payload, pool, credentials, max_file_size, chunk_size, quota)
+)]
+pub async fn create_upload_session(
+    payload: Json<CreateUploadSessionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    max_file_size: web::Data<MaxFileSize>,
+    chunk_size: web::Data<UploadChunkSize>,
+    quota: web::Data<StorageQuota>,
+) -> Result<HttpResponse, UploadSessionError> {
+    if payload.total_size <= 0 {
+        return Err(UploadSessionError::Empty);
+    }
+    if payload.total_size > max_file_size.0 as i64 {
+        return Err(UploadSessionError::TooLarge);
+    }
+
+    // the file may still be a duplicate, the quota is checked again when finalizing
+    let usage = load_storage_usage(credentials.user_id, None, pool.as_ref()).await?;
+    if usage.used + payload.total_size > quota.0 {
+        return Err(UploadSessionError::QuotaExceeded);
+    }
+    let chunk_size = chunk_size.0 as i32;
+
+    let session_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO upload_sessions (user_id, total_size, chunk_size) VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+        credentials.user_id,
+        payload.total_size,
+        chunk_size,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to insert upload session")?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "session_id": session_id,
+        "chunk_size": chunk_size,
+        "chunk_count": chunk_count(payload.total_size, chunk_size),
+    })))
+}
+
+/// Report the chunks which are not uploaded yet
+#[instrument(name = "Get upload session", skip(pool, credentials))]
+pub async fn get_upload_session(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, UploadSessionError> {
+    let session_id = path.into_inner();
+
+    let Some(session) = sqlx::query!(
+        "SELECT total_size, chunk_size FROM upload_sessions WHERE id = $1 AND user_id = $2",
+        session_id,
+        credentials.user_id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to query upload session")?
+    else {
+        return Err(UploadSessionError::NotFound);
+    };
+
+    let uploaded = sqlx::query_scalar!(
+        "SELECT chunk_index FROM upload_chunks WHERE session_id = $1",
+        session_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to query upload chunks")?;
+
+    let chunk_count = chunk_count(session.total_size, session.chunk_size);
+    let missing: Vec<i32> = (0..chunk_count)
+        .filter(|index| !uploaded.contains(index))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "total_size": session.total_size,
+        "chunk_size": session.chunk_size,
+        "chunk_count": chunk_count,
+        "missing": missing,
+    })))
+}
+
+/// Upload one chunk as the raw request body
+///
+/// Every chunk has the chunk size of the session except the last one, uploading a chunk again replaces it
+#[instrument(name = "Upload chunk", skip(payload, pool, credentials, store))]
+pub async fn upload_chunk(
+    path: web::Path<(i64, i32)>,
+    payload: web::Payload,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    store: web::Data<dyn MediaStore>,
+) -> Result<HttpResponse, UploadSessionError> {
+    let (session_id, chunk_index) = path.into_inner();
+
+    // uploading a chunk keeps the session alive
+    let Some(session) = sqlx::query!(
+        r#"
+        UPDATE upload_sessions SET updated_at = now()
+        WHERE id = $1 AND user_id = $2
+        RETURNING total_size, chunk_size
+        "#,
+        session_id,
+        credentials.user_id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to update upload session")?
+    else {
+        return Err(UploadSessionError::NotFound);
+    };
+
+    let chunk_count = chunk_count(session.total_size, session.chunk_size);
+    if !(0..chunk_count).contains(&chunk_index) {
+        return Err(UploadSessionError::BadChunkIndex);
+    }
+    let expected_size = if chunk_index == chunk_count - 1 {
+        session.total_size - session.chunk_size as i64 * chunk_index as i64
+    } else {
+        session.chunk_size as i64
+    } as usize;
+
+    let data = payload
+        .to_bytes_limited(expected_size)
+        .await
+        .map_err(|_| UploadSessionError::BadChunkSize)?
+        .map_err(|_| UploadSessionError::BadPayload)?;
+    if data.len() != expected_size {
+        return Err(UploadSessionError::BadChunkSize);
+    }
+
+    store
+        .put(
+            &upload_chunk_key(session_id, chunk_index),
+            data,
+            "application/octet-stream",
+        )
+        .await
+        .context("Failed to store upload chunk")?;
+
+    // the session may have been garbage-collected in the meantime
+    let recorded = sqlx::query!(
+        r#"
+        INSERT INTO upload_chunks (session_id, chunk_index)
+        SELECT id, $2 FROM upload_sessions WHERE id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        session_id,
+        chunk_index,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to insert upload chunk")?;
+    if recorded.rows_affected() == 0 {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM upload_sessions WHERE id = $1) AS "exists!""#,
+            session_id,
+        )
+        .fetch_one(pool.as_ref())
+        .await
+        .context("Failed to query upload session")?;
+        if !exists {
+            return Err(UploadSessionError::NotFound);
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct FinalizeUploadSessionModel {
+    /// Hex SHA-256 of the whole file
+    sha256: String,
+}
+
+/// Assemble the chunks into a media
+///
+/// The session is kept when the checksum does not match, so the broken chunks can be uploaded again
+#[instrument(
+    name = "Finalize upload session",
+    skip(payload, pool, credentials, store, thumbnail_sizes, quota)
+)]
+pub async fn finalize_upload_session(
+    path: web::Path<i64>,
+    payload: Json<FinalizeUploadSessionModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    store: web::Data<dyn MediaStore>,
+    thumbnail_sizes: web::Data<ThumbnailSizes>,
+    quota: web::Data<StorageQuota>,
+) -> Result<HttpResponse, UploadSessionError> {
+    let session_id = path.into_inner();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // lock the session against concurrent finalization and the garbage collector
+    let Some(session) = sqlx::query!(
+        r#"
+        SELECT total_size, chunk_size FROM upload_sessions
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#,
+        session_id,
+        credentials.user_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to query upload session")?
+    else {
+        return Err(UploadSessionError::NotFound);
+    };
+
+    let chunk_count = chunk_count(session.total_size, session.chunk_size);
+    let uploaded = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM upload_chunks WHERE session_id = $1"#,
+        session_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to query upload chunks")?;
+    if uploaded != chunk_count as i64 {
+        return Err(UploadSessionError::Incomplete);
+    }
+
+    let mut data = BytesMut::with_capacity(session.total_size as usize);
+    for chunk_index in 0..chunk_count {
+        let chunk = store
+            .get(&upload_chunk_key(session_id, chunk_index))
+            .await
+            .context("Failed to load upload chunk")?
+            .context("Upload chunk missing in the store")?;
+        data.extend_from_slice(&chunk);
+    }
+    let data = data.freeze();
+
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    if !sha256.eq_ignore_ascii_case(payload.sha256.trim()) {
+        return Err(UploadSessionError::ChecksumMismatch);
+    }
+
+    let media = save_media(
+        data,
+        credentials.user_id,
+        &pool,
+        store.as_ref(),
+        &thumbnail_sizes.0,
+        quota.0,
+    )
+    .await?;
+
+    sqlx::query!("DELETE FROM upload_sessions WHERE id = $1", session_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete upload session")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    for chunk_index in 0..chunk_count {
+        store
+            .delete(&upload_chunk_key(session_id, chunk_index))
+            .await
+            .context("Failed to delete upload chunk")?;
+    }
+
+    Ok(HttpResponse::Created().json(media))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadSessionError {
+    #[error("Upload session not found")]
+    NotFound,
+    #[error("File too large")]
+    TooLarge,
+    #[error("Empty file")]
+    Empty,
+    #[error("Chunk index out of range")]
+    BadChunkIndex,
+    #[error("Chunk size not match the session")]
+    BadChunkSize,
+    #[error("Failed to read the request body")]
+    BadPayload,
+    #[error("Some chunks are not uploaded yet")]
+    Incomplete,
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Image cannot be decoded")]
+    UnsupportedImage,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl From<SaveMediaError> for UploadSessionError {
+    fn from(err: SaveMediaError) -> Self {
+        match err {
+            SaveMediaError::QuotaExceeded => UploadSessionError::QuotaExceeded,
+            SaveMediaError::UnsupportedImage => UploadSessionError::UnsupportedImage,
+            SaveMediaError::UnknownError(err) => UploadSessionError::UnknownError(err),
+        }
+    }
+}
+
+impl ResponseError for UploadSessionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UploadSessionError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            UploadSessionError::NotFound => StatusCode::NOT_FOUND,
+            UploadSessionError::TooLarge | UploadSessionError::QuotaExceeded => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            UploadSessionError::Empty
+            | UploadSessionError::BadChunkIndex
+            | UploadSessionError::BadChunkSize
+            | UploadSessionError::BadPayload
+            | UploadSessionError::Incomplete
+            | UploadSessionError::ChecksumMismatch
+            | UploadSessionError::UnsupportedImage => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let msg = match self {
+            UploadSessionError::UnknownError(_) => "Internal Server Error",
+            UploadSessionError::NotFound => "Upload session not found",
+            UploadSessionError::TooLarge => "File too large",
+            UploadSessionError::Empty => "Empty file",
+            UploadSessionError::BadChunkIndex => "Chunk index out of range",
+            UploadSessionError::BadChunkSize => "Chunk size not match the session",
+            UploadSessionError::BadPayload => "Failed to read the request body",
+            UploadSessionError::Incomplete => "Some chunks are not uploaded yet",
+            UploadSessionError::ChecksumMismatch => "Checksum mismatch",
+            UploadSessionError::QuotaExceeded => "Storage quota exceeded",
+            UploadSessionError::UnsupportedImage => {
+                "Only JPEG, PNG and WebP images which can be decoded are accepted"
+            }
+        };
+        response_error(self.status_code(), msg)
+    }
+}
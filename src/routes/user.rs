@@ -7,11 +7,17 @@ use anyhow::Context;
 use serde_json::{Value, json};
 use sqlx::PgPool;
 use tracing::{Level, instrument};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::{
-    auth::{Credentials, CredentialsVerifyError, generate_token, hash_password, verify_password},
+    auth::{
+        BearerAuth, Credentials, CredentialsVerifyError, generate_token, hash_password,
+        normalize_username, username_skeleton, verify_password,
+    },
     error::response_error,
-    startup::{TokenExpireInterval, TokenSecret},
+    media::clear_orphaned,
+    routes::privacy::{PrivacyKey, filter_visible_owners},
+    startup::{SearchRateLimiter, TokenExpireInterval, TokenSecret},
     telemetry::spawn_blocking_with_tracing,
 };
 
@@ -47,17 +53,22 @@ pub async fn register(
 
 #[instrument(name = "Insert credentials into database", skip(credentials, pool))]
 async fn register_user(credentials: Credentials, pool: &PgPool) -> Result<i64, RegisterError> {
-    // find the user with the same username exists
-    if sqlx::query!(
-        "SELECT (username) FROM users WHERE username LIKE $1 LIMIT 1",
-        credentials.username
+    let skeleton = username_skeleton(&credentials.username);
+
+    // find the user with the same (or a look-alike) username exists
+    if let Some(user) = sqlx::query!(
+        "SELECT username FROM users WHERE username = $1 OR username_skeleton = $2 LIMIT 1",
+        credentials.username,
+        skeleton,
     )
     .fetch_optional(pool)
     .await
     .context("Failed to find exist user")?
-    .is_some()
     {
-        return Err(RegisterError::UsernameExists);
+        if user.username == credentials.username {
+            return Err(RegisterError::UsernameExists);
+        }
+        return Err(RegisterError::UsernameConfusable);
     }
 
     tracing::event!(Level::INFO, "Register new user: {}", credentials.username);
@@ -68,21 +79,42 @@ async fn register_user(credentials: Credentials, pool: &PgPool) -> Result<i64, R
         .context("Failed to hash password")?;
 
     let res = sqlx::query!(
-        "INSERT INTO users (username, password) VALUES ($1, $2) RETURNING (id);",
+        "INSERT INTO users (username, password, username_skeleton) VALUES ($1, $2, $3) RETURNING (id);",
         credentials.username,
         hashed_password,
+        skeleton,
     )
     .fetch_one(pool)
-    .await
-    .context("Failed to insert new user")?;
+    .await;
 
-    Ok(res.id)
+    match res {
+        Ok(res) => Ok(res.id),
+        // the same (or a look-alike) username was registered concurrently
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            let taken = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM users WHERE username = $1) AS "exists!""#,
+                credentials.username,
+            )
+            .fetch_one(pool)
+            .await
+            .context("Failed to find exist user")?;
+            if taken {
+                return Err(RegisterError::UsernameExists);
+            }
+            Err(RegisterError::UsernameConfusable)
+        }
+        Err(err) => Err(anyhow::Error::new(err)
+            .context("Failed to insert new user")
+            .into()),
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum RegisterError {
     #[error("Username was taken")]
     UsernameExists,
+    #[error("Username is too similar to an existing username")]
+    UsernameConfusable,
     #[error("Credentials error")]
     CredentialsError(#[from] CredentialsVerifyError),
     #[error("Unknown error: {0}")]
@@ -93,10 +125,14 @@ impl ResponseError for RegisterError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             RegisterError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            RegisterError::UsernameExists => StatusCode::BAD_REQUEST,
+            RegisterError::UsernameExists | RegisterError::UsernameConfusable => {
+                StatusCode::BAD_REQUEST
+            }
             RegisterError::CredentialsError(credentials_error) => match credentials_error {
                 CredentialsVerifyError::BadPasswordLength
-                | CredentialsVerifyError::InvalidCharacter => StatusCode::BAD_REQUEST,
+                | CredentialsVerifyError::BadUsernameLength
+                | CredentialsVerifyError::InvalidCharacter
+                | CredentialsVerifyError::MixedScript => StatusCode::BAD_REQUEST,
             },
         }
     }
@@ -105,13 +141,20 @@ impl ResponseError for RegisterError {
         let error_msg = match self {
             RegisterError::UnknownError(_) => "Unknown error",
             RegisterError::UsernameExists => "Username was taken",
+            RegisterError::UsernameConfusable => "Username is too similar to an existing username",
             RegisterError::CredentialsError(credentials_error) => match credentials_error {
                 CredentialsVerifyError::BadPasswordLength => {
                     "Password length not match the requirement: only length in the range 8-256 is acceptable"
                 }
+                CredentialsVerifyError::BadUsernameLength => {
+                    "Username length not match the requirement: only length in the range 1-64 is acceptable"
+                }
                 CredentialsVerifyError::InvalidCharacter => {
                     "Invalid characters found in username or password"
                 }
+                CredentialsVerifyError::MixedScript => {
+                    "Username mixes characters from multiple scripts"
+                }
             },
         };
 
@@ -137,10 +180,7 @@ pub async fn login(
 ) -> Result<Json<Value>, LoginError> {
     let payload = payload.into_inner();
     // convert payload into credentials
-    let credentials = Credentials {
-        username: payload.username,
-        password: payload.password,
-    };
+    let credentials = Credentials::normalized(payload.username, payload.password);
 
     // process auth flow for user
     let user_id: i64 = authorize_user(&credentials, &pool).await?;
@@ -215,10 +255,293 @@ pub async fn load_user_by_username(
     username: &str,
     pool: &PgPool,
 ) -> Result<Option<i64>, sqlx::Error> {
-    let user_id = sqlx::query!("SELECT id FROM users WHERE username LIKE $1", username)
+    let username = normalize_username(username);
+    let user_id = sqlx::query!("SELECT id FROM users WHERE username = $1", username)
         .fetch_optional(pool)
         .await?
         .map(|user| user.id);
 
     Ok(user_id)
 }
+
+#[derive(Debug, serde::Serialize)]
+pub struct UserProfile {
+    pub id: i64,
+    pub username: String,
+    pub display_name: Option<String>,
+    /// None when hidden by the profile_photo privacy rule
+    pub photo_media_id: Option<i64>,
+}
+
+/// Remove the photos the viewer is not allowed to see by the profile_photo privacy rule
+pub(crate) async fn hide_profile_photos(
+    profiles: &mut [UserProfile],
+    viewer_id: i64,
+    pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    let owner_ids: Vec<i64> = profiles
+        .iter()
+        .filter(|profile| profile.photo_media_id.is_some())
+        .map(|profile| profile.id)
+        .collect();
+    if owner_ids.is_empty() {
+        return Ok(());
+    }
+
+    let visible =
+        filter_visible_owners(&owner_ids, viewer_id, PrivacyKey::ProfilePhoto, pool).await?;
+    for profile in profiles {
+        if !visible.contains(&profile.id) {
+            profile.photo_media_id = None;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateProfileModel {
+    display_name: Option<String>,
+    searchable: Option<bool>,
+}
+
+#[instrument(name = "Update profile", skip(payload, pool, credentials))]
+pub async fn update_profile(
+    payload: Json<UpdateProfileModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, UpdateProfileError> {
+    let payload = payload.into_inner();
+
+    let display_name = payload
+        .display_name
+        .map(|name| name.nfkc().collect::<String>().trim().to_string());
+    if let Some(name) = &display_name
+        && (name.chars().count() > 64 || name.chars().any(char::is_control))
+    {
+        return Err(UpdateProfileError::BadDisplayName);
+    }
+
+    // an empty display name removes it
+    sqlx::query!(
+        r#"
+        UPDATE users SET
+            display_name = CASE WHEN $2::text IS NULL THEN display_name ELSE NULLIF($2, '') END,
+            searchable = COALESCE($3, searchable)
+        WHERE id = $1
+        "#,
+        credentials.user_id,
+        display_name,
+        payload.searchable,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update profile")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetProfilePhotoModel {
+    /// An image uploaded by the user, None removes the photo
+    media_id: Option<i64>,
+}
+
+#[instrument(name = "Set profile photo", skip(payload, pool, credentials))]
+pub async fn set_profile_photo(
+    payload: Json<SetProfilePhotoModel>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+) -> Result<HttpResponse, UpdateProfileError> {
+    let media_id = payload.media_id;
+
+    if let Some(media_id) = media_id {
+        let Some(content_type) = sqlx::query_scalar!(
+            r#"
+            SELECT m.content_type
+            FROM media AS m
+            JOIN media_uploads AS mu ON mu.media_id = m.id
+            WHERE m.id = $1 AND mu.user_id = $2
+            "#,
+            media_id,
+            credentials.user_id,
+        )
+        .fetch_optional(pool.as_ref())
+        .await
+        .context("Failed to query media")?
+        else {
+            return Err(UpdateProfileError::MediaNotFound);
+        };
+        if !content_type.starts_with("image/") {
+            return Err(UpdateProfileError::NotImage);
+        }
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        "UPDATE users SET photo_media_id = $2 WHERE id = $1",
+        credentials.user_id,
+        media_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update profile photo")?;
+    if let Some(media_id) = media_id {
+        clear_orphaned(&[media_id], &mut *transaction).await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "photo_media_id": media_id,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpdateProfileError {
+    #[error("Bad display name")]
+    BadDisplayName,
+    #[error("Media not found")]
+    MediaNotFound,
+    #[error("Media is not an image")]
+    NotImage,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UpdateProfileError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UpdateProfileError::BadDisplayName
+            | UpdateProfileError::MediaNotFound
+            | UpdateProfileError::NotImage => StatusCode::BAD_REQUEST,
+            UpdateProfileError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let error_msg = match self {
+            UpdateProfileError::BadDisplayName => {
+                "Display name must be at most 64 characters without control characters"
+            }
+            UpdateProfileError::MediaNotFound => "Media not found",
+            UpdateProfileError::NotImage => "The profile photo must be an image",
+            UpdateProfileError::UnknownError(_) => "Internal Server Error",
+        };
+
+        response_error(self.status_code(), error_msg)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchUsersQuery {
+    q: String,
+}
+
+#[instrument(name = "Search users", skip(query, pool, credentials, rate_limiter))]
+pub async fn search_users(
+    query: web::Query<SearchUsersQuery>,
+    pool: web::Data<PgPool>,
+    credentials: BearerAuth,
+    rate_limiter: web::Data<SearchRateLimiter>,
+) -> Result<HttpResponse, SearchUsersError> {
+    if !rate_limiter.0.check(credentials.user_id) {
+        return Err(SearchUsersError::TooManyRequests);
+    }
+
+    let q = normalize_username(query.q.trim());
+    if q.is_empty() {
+        return Err(SearchUsersError::EmptyQuery);
+    }
+    // nothing longer than a username is worth matching
+    if q.chars().count() > 64 {
+        return Err(SearchUsersError::QueryTooLong);
+    }
+
+    // escape LIKE wildcards in the query, so it is matched as a literal prefix
+    let prefix = format!(
+        "{}%",
+        q.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    let mut users = sqlx::query_as!(
+        UserProfile,
+        r#"
+        SELECT id, username, display_name, photo_media_id
+        FROM users
+        WHERE
+            searchable
+            AND id <> $3
+            AND NOT EXISTS(
+                SELECT 1 FROM blocks
+                WHERE (blocker_id = $3 AND blocked_id = users.id)
+                    OR (blocker_id = users.id AND blocked_id = $3)
+            )
+            AND (
+                username ILIKE $2
+                OR display_name ILIKE $2
+                OR username % $1
+                OR display_name % $1
+            )
+        ORDER BY
+            (username ILIKE $2 OR display_name ILIKE $2) DESC,
+            GREATEST(similarity(username, $1), similarity(COALESCE(display_name, ''), $1)) DESC,
+            id
+        LIMIT 20
+        "#,
+        q,
+        prefix,
+        credentials.user_id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to search users")?;
+    hide_profile_photos(&mut users, credentials.user_id, &pool)
+        .await
+        .context("Failed to check profile photo privacy")?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": users,
+    })))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SearchUsersError {
+    #[error("Empty search query")]
+    EmptyQuery,
+    #[error("Search query is too long")]
+    QueryTooLong,
+    #[error("Too many requests")]
+    TooManyRequests,
+    #[error("Unknown error: {0}")]
+    UnknownError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SearchUsersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SearchUsersError::EmptyQuery | SearchUsersError::QueryTooLong => {
+                StatusCode::BAD_REQUEST
+            }
+            SearchUsersError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            SearchUsersError::UnknownError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let error_msg = match self {
+            SearchUsersError::EmptyQuery => "Search query must not be empty",
+            SearchUsersError::QueryTooLong => "Search query must not be longer than 64 characters",
+            SearchUsersError::TooManyRequests => "Too many requests",
+            SearchUsersError::UnknownError(_) => "Internal Server Error",
+        };
+
+        response_error(self.status_code(), error_msg)
+    }
+}
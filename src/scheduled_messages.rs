@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{Connection, PgConnection, PgPool};
+use tracing::event;
+
+use crate::routes::{
+    anti_spam::check_send_rate,
+    messages::{MessageKind, SendMessageError, SendMessageModel, check_message, insert_message},
+    polls::NewPoll,
+};
+
+/// Scheduled messages posted by a single transaction
+const POST_BATCH_SIZE: i64 = 100;
+
+/// Post the scheduled messages once they are due
+pub async fn run_scheduled_message_worker(pool: PgPool, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(err) = post_due_messages(&pool).await {
+            event!(
+                tracing::Level::ERROR,
+                "Failed to post scheduled messages: {err:?}"
+            );
+        }
+    }
+}
+
+async fn post_due_messages(pool: &PgPool) -> anyhow::Result<()> {
+    while post_batch(pool).await? == POST_BATCH_SIZE {}
+
+    Ok(())
+}
+
+/// Returns the number of handled scheduled messages
+async fn post_batch(pool: &PgPool) -> anyhow::Result<i64> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    // locked rows are skipped and deleted in the same transaction as the message is inserted,
+    // so every message is posted exactly once when several instances run at the same time
+    let due = sqlx::query!(
+        r#"
+        SELECT
+            id, chat_id, sender_id, content, kind, media_ids, ttl,
+            poll AS "poll: sqlx::types::Json<NewPoll>",
+            topic_id
+        FROM scheduled_messages
+        WHERE send_at <= now()
+        ORDER BY send_at, id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        POST_BATCH_SIZE,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to query due scheduled messages")?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+
+    for scheduled in &due {
+        let kind = match MessageKind::try_from(scheduled.kind.clone()) {
+            Ok(kind) => kind,
+            Err(err) => {
+                event!(
+                    tracing::Level::ERROR,
+                    "Failed to post scheduled message {}: {err}",
+                    scheduled.id
+                );
+                continue;
+            }
+        };
+        let mut message = SendMessageModel {
+            chat_id: scheduled.chat_id,
+            content: scheduled.content.clone(),
+            media_ids: scheduled.media_ids.clone(),
+            kind,
+            ttl: scheduled.ttl,
+            poll: scheduled.poll.clone().map(|poll| poll.0),
+            topic_id: scheduled.topic_id,
+        };
+
+        // a failing message is rolled back to its savepoint and deleted with the others,
+        // so it cannot hold back the rest of the batch
+        let mut savepoint = Connection::begin(&mut *transaction)
+            .await
+            .context("Failed to create savepoint")?;
+        match post_message(&mut message, scheduled.sender_id, &mut savepoint).await {
+            Ok(()) => {
+                savepoint
+                    .commit()
+                    .await
+                    .context("Failed to release savepoint")?;
+            }
+            Err(SendMessageError::UnknownError(err)) => {
+                savepoint
+                    .rollback()
+                    .await
+                    .context("Failed to roll back to savepoint")?;
+                event!(
+                    tracing::Level::ERROR,
+                    "Failed to post scheduled message {}: {err:?}",
+                    scheduled.id
+                );
+            }
+            // the flood restriction stays although the message is dropped
+            Err(SendMessageError::Flooding) => {
+                savepoint
+                    .commit()
+                    .await
+                    .context("Failed to release savepoint")?;
+                event!(
+                    tracing::Level::INFO,
+                    "Dropped scheduled message {}: {}",
+                    scheduled.id,
+                    SendMessageError::Flooding
+                );
+            }
+            // the sender may have left the chat or lost the attached media in the meantime
+            Err(err) => {
+                savepoint
+                    .rollback()
+                    .await
+                    .context("Failed to roll back to savepoint")?;
+                event!(
+                    tracing::Level::INFO,
+                    "Dropped scheduled message {}: {err}",
+                    scheduled.id
+                );
+            }
+        }
+    }
+
+    let ids: Vec<i64> = due.iter().map(|scheduled| scheduled.id).collect();
+    sqlx::query!("DELETE FROM scheduled_messages WHERE id = ANY($1)", &ids)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete scheduled messages")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(due.len() as i64)
+}
+
+async fn post_message(
+    message: &mut SendMessageModel,
+    sender_id: i64,
+    connection: &mut PgConnection,
+) -> Result<(), SendMessageError> {
+    check_message(message, sender_id, connection).await?;
+    // messages scheduled at the same time count towards the limits like the ones sent right away
+    check_send_rate(message.chat_id, sender_id, connection).await?;
+    insert_message(message, sender_id, connection).await?;
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::net::TcpListener;
+use std::{net::TcpListener, sync::Arc, time::Duration};
 
 use actix_web::{App, HttpServer, dev::Server, web};
 use bytes::Bytes;
@@ -6,8 +6,33 @@ use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
 use crate::{
+    chat_actions::ChatActions,
     configuration::Settings,
-    routes::{create_pm, login, register},
+    media::{build_media_store, run_media_gc, run_upload_session_gc},
+    message_expiry::run_message_sweeper,
+    presence::{Presence, run_presence_worker},
+    rate_limit::RateLimiter,
+    realtime::Hub,
+    routes::{
+        add_contact, add_member, add_reaction, approve_join_requests, ban_member, block_user,
+        cancel_scheduled_message, close_poll, close_topic, create_group, create_invite_link,
+        create_pm, create_topic, create_upload_session, decline_join_requests, delete_media,
+        delete_message, demote_admin, download_media, download_thumbnail, edit_scheduled_message,
+        finalize_upload_session, get_admin_log, get_allowed_reactions, get_anti_spam,
+        get_auto_delete, get_chat_history, get_chat_info, get_media_info, get_presence,
+        get_storage_usage, get_upload_session, join_chat, kick_member, leave_chat, list_admins,
+        list_bans, list_blocked_users, list_contacts, list_invite_link_joins, list_invite_links,
+        list_join_requests, list_pinned_messages, list_privacy, list_restrictions,
+        list_scheduled_messages, list_topics, login, pin_message, promote_member, read_topic,
+        register, remove_contact, remove_reaction, rename_topic, reopen_topic, restrict_member,
+        retract_vote, revoke_invite_link, schedule_message, search_all_messages,
+        search_chat_messages, search_users, send_chat_action, send_message, set_allowed_reactions,
+        set_anti_spam, set_auto_delete, set_chat_description, set_chat_photo, set_chat_title,
+        set_privacy, set_profile_photo, toggle_topics, transfer_ownership, unban_member,
+        unblock_user, unpin_message, update_profile, updates, upload_chunk, upload_media,
+        vote_poll,
+    },
+    scheduled_messages::run_scheduled_message_worker,
 };
 
 pub struct Application {
@@ -25,13 +50,7 @@ impl Application {
         let port = listener.local_addr()?.port();
 
         // build the server
-        let server = run(
-            listener,
-            settings.database.db_url(),
-            settings.security.token_expire_interval,
-            Bytes::from(settings.security.token_secret),
-        )
-        .await?;
+        let server = run(listener, settings).await?;
 
         Ok(Self { server, port })
     }
@@ -47,18 +66,82 @@ impl Application {
 
 pub struct TokenExpireInterval(pub usize);
 pub struct TokenSecret(pub Bytes);
+pub struct SearchRateLimiter(pub RateLimiter);
+pub struct MaxFileSize(pub usize);
+pub struct UploadChunkSize(pub usize);
+pub struct ThumbnailSizes(pub Vec<u32>);
+pub struct StorageQuota(pub i64);
+
+async fn run(lst: TcpListener, settings: Settings) -> anyhow::Result<Server> {
+    let Settings {
+        database,
+        security,
+        search,
+        presence: presence_config,
+        media: media_config,
+        messages: messages_config,
+        ..
+    } = settings;
 
-async fn run(
-    lst: TcpListener,
-    db_url: String,
-    token_expire_interval: usize,
-    token_secret: Bytes,
-) -> anyhow::Result<Server> {
     // connect to postgres
-    let pool = web::Data::new(PgPool::connect(&db_url).await?);
+    let pool = web::Data::new(PgPool::connect(&database.db_url()).await?);
+
+    // real-time channel and presence tracking
+    let hub = Arc::new(Hub::default());
+    let (presence, presence_changes) = Presence::new(
+        Duration::from_secs(presence_config.online_timeout),
+        Duration::from_secs(presence_config.last_seen_granularity),
+    );
+    let presence = Arc::new(presence);
+    tokio::spawn(run_presence_worker(
+        presence.clone(),
+        presence_changes,
+        hub.clone(),
+        pool.as_ref().clone(),
+    ));
+    let presence = web::Data::from(presence);
+    let chat_actions = web::Data::new(ChatActions::default());
+
+    // media storage, the collection of abandoned upload sessions and unreferenced media
+    let media_store = build_media_store(&media_config.storage)?;
+    tokio::spawn(run_media_gc(
+        pool.as_ref().clone(),
+        media_store.clone(),
+        Duration::from_secs(media_config.retention.grace_period),
+        Duration::from_secs(media_config.retention.gc_interval),
+    ));
+    tokio::spawn(run_upload_session_gc(
+        pool.as_ref().clone(),
+        media_store.clone(),
+        Duration::from_secs(media_config.upload_session.expire),
+        Duration::from_secs(media_config.upload_session.gc_interval),
+    ));
+
+    // scheduled messages are posted and expired messages are deleted by every instance,
+    // each message exactly once
+    tokio::spawn(run_scheduled_message_worker(
+        pool.as_ref().clone(),
+        Duration::from_secs(messages_config.scheduler_interval),
+    ));
+    tokio::spawn(run_message_sweeper(
+        pool.as_ref().clone(),
+        hub.clone(),
+        media_store.clone(),
+        Duration::from_secs(messages_config.sweeper_interval),
+    ));
+    let hub = web::Data::from(hub);
+    let media_store = web::Data::from(media_store);
+    let max_file_size = web::Data::new(MaxFileSize(media_config.max_file_size));
+    let thumbnail_sizes = web::Data::new(ThumbnailSizes(media_config.thumbnail_sizes));
+    let storage_quota = web::Data::new(StorageQuota(media_config.quota_per_user));
+    let upload_chunk_size = web::Data::new(UploadChunkSize(media_config.upload_session.chunk_size));
 
-    let token_expire_interval = web::Data::new(TokenExpireInterval(token_expire_interval));
-    let token_secret = web::Data::new(TokenSecret(token_secret));
+    let token_expire_interval = web::Data::new(TokenExpireInterval(security.token_expire_interval));
+    let token_secret = web::Data::new(TokenSecret(Bytes::from(security.token_secret)));
+    let search_rate_limiter = web::Data::new(SearchRateLimiter(RateLimiter::new(
+        search.rate_limit.max_requests,
+        Duration::from_secs(search.rate_limit.interval),
+    )));
 
     let server = HttpServer::new(move || {
         App::new()
@@ -66,9 +149,200 @@ async fn run(
             .app_data(pool.clone())
             .app_data(token_expire_interval.clone())
             .app_data(token_secret.clone())
+            .app_data(search_rate_limiter.clone())
+            .app_data(hub.clone())
+            .app_data(presence.clone())
+            .app_data(chat_actions.clone())
+            .app_data(media_store.clone())
+            .app_data(max_file_size.clone())
+            .app_data(upload_chunk_size.clone())
+            .app_data(thumbnail_sizes.clone())
+            .app_data(storage_quota.clone())
             .route("/user/register", web::post().to(register))
             .route("/user/login", web::post().to(login))
+            .route("/user/profile", web::post().to(update_profile))
+            .route("/user/photo", web::post().to(set_profile_photo))
+            .route("/user/{user_id}/presence", web::get().to(get_presence))
+            .route("/users/search", web::get().to(search_users))
+            .route("/contact/add", web::post().to(add_contact))
+            .route("/contact/remove", web::post().to(remove_contact))
+            .route("/contact/list", web::get().to(list_contacts))
+            .route("/block/add", web::post().to(block_user))
+            .route("/block/remove", web::post().to(unblock_user))
+            .route("/block/list", web::get().to(list_blocked_users))
+            .route("/privacy/set", web::post().to(set_privacy))
+            .route("/privacy/list", web::get().to(list_privacy))
             .route("/chat/pm", web::post().to(create_pm))
+            .route("/chat/group", web::post().to(create_group))
+            .route("/chat/join", web::post().to(join_chat))
+            .route("/chat/{chat_id}/info", web::get().to(get_chat_info))
+            .route("/chat/{chat_id}/title", web::post().to(set_chat_title))
+            .route(
+                "/chat/{chat_id}/description",
+                web::post().to(set_chat_description),
+            )
+            .route("/chat/{chat_id}/photo", web::post().to(set_chat_photo))
+            .route("/chat/{chat_id}/leave", web::post().to(leave_chat))
+            .route("/chat/{chat_id}/member/add", web::post().to(add_member))
+            .route("/chat/{chat_id}/member/kick", web::post().to(kick_member))
+            .route("/chat/{chat_id}/member/ban", web::post().to(ban_member))
+            .route("/chat/{chat_id}/member/unban", web::post().to(unban_member))
+            .route(
+                "/chat/{chat_id}/member/restrict",
+                web::post().to(restrict_member),
+            )
+            .route(
+                "/chat/{chat_id}/member/promote",
+                web::post().to(promote_member),
+            )
+            .route(
+                "/chat/{chat_id}/member/demote",
+                web::post().to(demote_admin),
+            )
+            .route(
+                "/chat/{chat_id}/transfer-ownership",
+                web::post().to(transfer_ownership),
+            )
+            .route("/chat/{chat_id}/admins", web::get().to(list_admins))
+            .route("/chat/{chat_id}/admin-log", web::get().to(get_admin_log))
+            .route("/chat/{chat_id}/bans", web::get().to(list_bans))
+            .route(
+                "/chat/{chat_id}/restrictions",
+                web::get().to(list_restrictions),
+            )
+            .route(
+                "/chat/{chat_id}/invite-links",
+                web::post().to(create_invite_link),
+            )
+            .route(
+                "/chat/{chat_id}/invite-links",
+                web::get().to(list_invite_links),
+            )
+            .route(
+                "/chat/{chat_id}/invite-links/{link_id}/revoke",
+                web::post().to(revoke_invite_link),
+            )
+            .route(
+                "/chat/{chat_id}/invite-links/{link_id}/joins",
+                web::get().to(list_invite_link_joins),
+            )
+            .route(
+                "/chat/{chat_id}/join-requests",
+                web::get().to(list_join_requests),
+            )
+            .route(
+                "/chat/{chat_id}/join-requests/approve",
+                web::post().to(approve_join_requests),
+            )
+            .route(
+                "/chat/{chat_id}/join-requests/decline",
+                web::post().to(decline_join_requests),
+            )
+            .route("/chat/{chat_id}/action", web::post().to(send_chat_action))
+            .route("/chat/{chat_id}/messages", web::get().to(get_chat_history))
+            .route("/chat/{chat_id}/forum", web::post().to(toggle_topics))
+            .route("/chat/{chat_id}/topics", web::get().to(list_topics))
+            .route("/chat/{chat_id}/topics", web::post().to(create_topic))
+            .route(
+                "/chat/{chat_id}/topics/{topic_id}/rename",
+                web::post().to(rename_topic),
+            )
+            .route(
+                "/chat/{chat_id}/topics/{topic_id}/close",
+                web::post().to(close_topic),
+            )
+            .route(
+                "/chat/{chat_id}/topics/{topic_id}/reopen",
+                web::post().to(reopen_topic),
+            )
+            .route(
+                "/chat/{chat_id}/topics/{topic_id}/read",
+                web::post().to(read_topic),
+            )
+            .route(
+                "/chat/{chat_id}/reactions",
+                web::get().to(get_allowed_reactions),
+            )
+            .route(
+                "/chat/{chat_id}/reactions",
+                web::post().to(set_allowed_reactions),
+            )
+            .route(
+                "/chat/{chat_id}/pinned",
+                web::get().to(list_pinned_messages),
+            )
+            .route(
+                "/chat/{chat_id}/search",
+                web::get().to(search_chat_messages),
+            )
+            .route(
+                "/chat/{chat_id}/auto-delete",
+                web::get().to(get_auto_delete),
+            )
+            .route(
+                "/chat/{chat_id}/auto-delete",
+                web::post().to(set_auto_delete),
+            )
+            .route("/chat/{chat_id}/anti-spam", web::get().to(get_anti_spam))
+            .route("/chat/{chat_id}/anti-spam", web::post().to(set_anti_spam))
+            .route(
+                "/chat/{chat_id}/scheduled",
+                web::get().to(list_scheduled_messages),
+            )
+            .route("/message/send", web::post().to(send_message))
+            .route("/message/schedule", web::post().to(schedule_message))
+            .route(
+                "/message/scheduled/{scheduled_id}",
+                web::post().to(edit_scheduled_message),
+            )
+            .route(
+                "/message/scheduled/{scheduled_id}",
+                web::delete().to(cancel_scheduled_message),
+            )
+            .route("/messages/search", web::get().to(search_all_messages))
+            .route("/message/{message_id}", web::delete().to(delete_message))
+            .route("/message/{message_id}/pin", web::post().to(pin_message))
+            .route("/message/{message_id}/unpin", web::post().to(unpin_message))
+            .route("/message/{message_id}/poll/vote", web::post().to(vote_poll))
+            .route(
+                "/message/{message_id}/poll/retract",
+                web::post().to(retract_vote),
+            )
+            .route(
+                "/message/{message_id}/poll/close",
+                web::post().to(close_poll),
+            )
+            .route(
+                "/message/{message_id}/reaction/add",
+                web::post().to(add_reaction),
+            )
+            .route(
+                "/message/{message_id}/reaction/remove",
+                web::post().to(remove_reaction),
+            )
+            .route("/media/upload", web::post().to(upload_media))
+            .route("/media/session", web::post().to(create_upload_session))
+            .route(
+                "/media/session/{session_id}",
+                web::get().to(get_upload_session),
+            )
+            .route(
+                "/media/session/{session_id}/chunk/{chunk_index}",
+                web::put().to(upload_chunk),
+            )
+            .route(
+                "/media/session/{session_id}/finalize",
+                web::post().to(finalize_upload_session),
+            )
+            .route("/media/quota", web::get().to(get_storage_usage))
+            .route("/media/{media_id}", web::get().to(download_media))
+            .route("/media/{media_id}", web::delete().to(delete_media))
+            .route("/media/{media_id}/info", web::get().to(get_media_info))
+            .route(
+                "/media/{media_id}/thumbnail/{size}",
+                web::get().to(download_thumbnail),
+            )
+            .route("/updates", web::get().to(updates))
     })
     .listen(lst)?
     .run();
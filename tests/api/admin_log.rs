@@ -0,0 +1,345 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, spawn_app};
+
+/// Group of the test user with one more member
+async fn create_group_with_member(app: &TestApp) -> (i64, TestUser) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    (chat_id, member)
+}
+
+/// Actions of the admin log, the newest first
+async fn admin_log_actions(
+    app: &TestApp,
+    token: &str,
+    chat_id: i64,
+    query: &[(&str, &str)],
+) -> Vec<serde_json::Value> {
+    let res = app.get_admin_log(token, chat_id, query).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn administrative_actions_are_recorded() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let message_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "hi")
+        .await;
+
+    app.set_chat_title(&app.test_user.token, chat_id, "Cats")
+        .await;
+    app.set_chat_description(&app.test_user.token, chat_id, Some("Meow"))
+        .await;
+    app.pin_message(&app.test_user.token, message_id).await;
+    app.unpin_message(&app.test_user.token, message_id).await;
+    let (link_id, _) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+    app.restrict_member(
+        &app.test_user.token,
+        chat_id,
+        member.id,
+        &["send_media"],
+        None,
+    )
+    .await;
+    app.ban_member(&app.test_user.token, chat_id, member.id, None)
+        .await;
+    app.unban_member(&app.test_user.token, chat_id, member.id)
+        .await;
+
+    let actions = admin_log_actions(&app, &app.test_user.token, chat_id, &[]).await;
+    assert_eq!(
+        actions,
+        vec![
+            json!({ "type": "unban_member", "user_id": member.id }),
+            json!({ "type": "ban_member", "user_id": member.id, "until": null }),
+            json!({
+                "type": "restrict_member",
+                "user_id": member.id,
+                "permissions": ["send_media"],
+                "until": null,
+            }),
+            json!({ "type": "create_invite_link", "link_id": link_id }),
+            json!({ "type": "unpin_message", "message_id": message_id }),
+            json!({ "type": "pin_message", "message_id": message_id }),
+            json!({ "type": "change_description", "description": "Meow" }),
+            json!({ "type": "change_title", "title": "Cats" }),
+            json!({ "type": "add_member", "user_id": member.id }),
+        ]
+    );
+
+    let res = app
+        .get_admin_log(&app.test_user.token, chat_id, &[("limit", "2")])
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let entries = json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["actor_id"].as_i64().unwrap(), app.test_user.id);
+    assert!(entries[0]["created_at"].is_i64());
+
+    let before_id = entries[1]["entry_id"].as_i64().unwrap().to_string();
+    let actions = admin_log_actions(
+        &app,
+        &app.test_user.token,
+        chat_id,
+        &[("before_id", &before_id), ("limit", "1")],
+    )
+    .await;
+    assert_eq!(actions[0]["type"], "restrict_member");
+}
+
+#[tokio::test]
+async fn chat_settings_changes_are_recorded() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let flood_limit = json!({ "max_messages": 5, "interval": 10, "restrict_for": 60 });
+    app.set_anti_spam(
+        &app.test_user.token,
+        chat_id,
+        json!({ "slow_mode": 30, "flood_limit": flood_limit }),
+    )
+    .await;
+    app.set_auto_delete(&app.test_user.token, chat_id, Some("day"))
+        .await;
+    app.set_allowed_reactions(&app.test_user.token, chat_id, Some(&["👍"]))
+        .await;
+
+    let actions = admin_log_actions(&app, &app.test_user.token, chat_id, &[]).await;
+    assert_eq!(
+        actions,
+        vec![
+            json!({ "type": "change_allowed_reactions", "allowed": ["👍"] }),
+            json!({ "type": "change_auto_delete", "period": "day" }),
+            json!({ "type": "change_anti_spam", "slow_mode": 30, "flood_limit": flood_limit }),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn message_deletions_by_admins_are_recorded() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let own_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "oops")
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "spam")
+        .await;
+
+    // deleting their own messages is no administrative action
+    let res = app.delete_message(&member.token, own_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+    let res = app.delete_message(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+
+    let actions = admin_log_actions(
+        &app,
+        &app.test_user.token,
+        chat_id,
+        &[("action", "delete_message")],
+    )
+    .await;
+    assert_eq!(
+        actions,
+        vec![json!({
+            "type": "delete_message",
+            "message_id": message_id,
+            "sender_id": member.id,
+        })]
+    );
+}
+
+#[tokio::test]
+async fn topic_and_join_request_decisions_are_recorded() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    app.toggle_topics(&app.test_user.token, chat_id, true).await;
+    let topic_id = app
+        .create_topic_returns_id(&member.token, chat_id, "Memes")
+        .await;
+
+    // the creator manages their own topic without the admin log
+    let res = app
+        .rename_topic(&member.token, chat_id, topic_id, "Dank memes")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .rename_topic(&app.test_user.token, chat_id, topic_id, "Cat memes")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .close_topic(&app.test_user.token, chat_id, topic_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .reopen_topic(&app.test_user.token, chat_id, topic_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let (_, token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "requires_approval": true }),
+        )
+        .await;
+    let approved = app.create_test_user().await;
+    let declined = app.create_test_user().await;
+    for user in [&approved, &declined] {
+        app.request_to_join(&user.token, &token, "hello").await;
+    }
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[approved.id], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[declined.id], false)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let actions = admin_log_actions(&app, &app.test_user.token, chat_id, &[]).await;
+    let types: Vec<&str> = actions
+        .iter()
+        .map(|action| action["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "decline_join_requests",
+            "approve_join_requests",
+            "create_invite_link",
+            "reopen_topic",
+            "close_topic",
+            "rename_topic",
+            "toggle_topics",
+            "add_member",
+        ]
+    );
+    assert_eq!(actions[0]["user_ids"], json!([declined.id]));
+    assert_eq!(actions[1]["user_ids"], json!([approved.id]));
+    assert_eq!(actions[3]["topic_id"].as_i64().unwrap(), topic_id);
+    assert_eq!(
+        actions[5],
+        json!({ "type": "rename_topic", "topic_id": topic_id, "title": "Cat memes" })
+    );
+}
+
+#[tokio::test]
+async fn admin_log_is_filtered_by_action_and_actor() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let other = app.create_test_user().await;
+    app.add_member(&app.test_user.token, chat_id, &other.username)
+        .await;
+
+    app.promote_member(
+        &app.test_user.token,
+        chat_id,
+        member.id,
+        &["ban_users"],
+        Some("Bouncer"),
+    )
+    .await;
+    app.kick_member(&member.token, chat_id, other.id).await;
+    app.ban_member(&app.test_user.token, chat_id, other.id, None)
+        .await;
+
+    let actions = admin_log_actions(
+        &app,
+        &member.token,
+        chat_id,
+        &[("actor_id", &member.id.to_string())],
+    )
+    .await;
+    assert_eq!(
+        actions,
+        vec![json!({ "type": "kick_member", "user_id": other.id })]
+    );
+
+    let actions = admin_log_actions(
+        &app,
+        &member.token,
+        chat_id,
+        &[("action", "promote_member")],
+    )
+    .await;
+    assert_eq!(
+        actions,
+        vec![json!({
+            "type": "promote_member",
+            "user_id": member.id,
+            "rights": ["ban_users"],
+            "custom_title": "Bouncer",
+        })]
+    );
+
+    let actions = admin_log_actions(
+        &app,
+        &member.token,
+        chat_id,
+        &[
+            ("action", "kick_member"),
+            ("actor_id", &app.test_user.id.to_string()),
+        ],
+    )
+    .await;
+    assert!(actions.is_empty());
+
+    let res = app
+        .get_admin_log(&member.token, chat_id, &[("action", "kick_members")])
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_admins_read_the_admin_log() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = app.get_admin_log(&member.token, chat_id, &[]).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let stranger = app.create_test_user().await;
+    let res = app.get_admin_log(&stranger.token, chat_id, &[]).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // pins of private chats are not administrative
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &member.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&member.token, pm_id, "hi")
+        .await;
+    let res = app.pin_message(&member.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.get_admin_log(&app.test_user.token, pm_id, &[]).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM chat_admin_log WHERE chat_id = $1"#,
+        pm_id,
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,335 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, spawn_app};
+
+/// Group of the test user with one more member
+async fn create_group_with_member(app: &TestApp) -> (i64, TestUser) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    (chat_id, member)
+}
+
+#[tokio::test]
+async fn owner_promotes_member_with_rights() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let other = app.create_test_user().await;
+    app.add_member(&app.test_user.token, chat_id, &other.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&other.token, chat_id, "hi")
+        .await;
+
+    let res = app
+        .promote_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["pin_messages"],
+            Some(" Moderator "),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_admins(&other.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json["admins"],
+        json!([
+            {
+                "user_id": app.test_user.id,
+                "role": "owner",
+                "custom_title": null,
+                "rights": [
+                    "change_info",
+                    "ban_users",
+                    "invite_users",
+                    "pin_messages",
+                    "delete_messages",
+                    "manage_topics",
+                ],
+            },
+            {
+                "user_id": member.id,
+                "role": "admin",
+                "custom_title": "Moderator",
+                "rights": ["pin_messages"],
+            },
+        ])
+    );
+
+    // only the granted rights
+    let res = app.pin_message(&member.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.kick_member(&member.token, chat_id, other.id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .promote_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["ban_users"],
+            None,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.kick_member(&member.token, chat_id, other.id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    // only the owner manages the admins
+    let res = app
+        .promote_member(&member.token, chat_id, member.id, &["pin_messages"], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .promote_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &[],
+            Some(&"x".repeat(17)),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app
+        .promote_member(&app.test_user.token, chat_id, app.test_user.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app
+        .promote_member(&app.test_user.token, chat_id, other.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn promotion_lifts_restrictions() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    app.restrict_member(
+        &app.test_user.token,
+        chat_id,
+        member.id,
+        &["send_messages"],
+        None,
+    )
+    .await;
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .promote_member(&app.test_user.token, chat_id, member.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["restrictions"].as_array().unwrap().is_empty());
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn promotion_lifts_flood_restriction() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let flood_limit = json!({ "max_messages": 1, "interval": 60, "restrict_for": 3600 });
+    app.set_anti_spam(
+        &app.test_user.token,
+        chat_id,
+        json!({ "flood_limit": flood_limit }),
+    )
+    .await;
+    app.send_chat_message(&member.token, chat_id, "spam").await;
+    let res = app.send_chat_message(&member.token, chat_id, "spam").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .promote_member(&app.test_user.token, chat_id, member.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["restrictions"].as_array().unwrap().is_empty());
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn owner_demotes_admin() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let joiner = app.create_test_user().await;
+
+    app.promote_member(
+        &app.test_user.token,
+        chat_id,
+        member.id,
+        &["invite_users"],
+        Some("Doorman"),
+    )
+    .await;
+    let res = app
+        .add_member(&member.token, chat_id, &joiner.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app
+        .demote_admin(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .demote_admin(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.list_admins(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["admins"].as_array().unwrap().len(), 1);
+
+    let res = app
+        .kick_member(&app.test_user.token, chat_id, joiner.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .add_member(&member.token, chat_id, &joiner.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owner_transfers_ownership_to_admin() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    // only to admins
+    let res = app
+        .transfer_ownership(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &app.test_user.password,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    app.promote_member(&app.test_user.token, chat_id, member.id, &[], None)
+        .await;
+
+    let res = app
+        .transfer_ownership(&app.test_user.token, chat_id, member.id, "wrong password")
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .transfer_ownership(&member.token, chat_id, member.id, &member.password)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // the owner has to hand the chat over before leaving
+    let res = app.leave_chat(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app
+        .transfer_ownership(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &app.test_user.password,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_admins(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let admins = json["admins"].as_array().unwrap();
+    assert_eq!(admins[0]["user_id"].as_i64().unwrap(), member.id);
+    assert_eq!(admins[0]["role"], "owner");
+    assert_eq!(admins[1]["user_id"].as_i64().unwrap(), app.test_user.id);
+    assert_eq!(admins[1]["role"], "admin");
+    assert_eq!(admins[1]["rights"].as_array().unwrap().len(), 6);
+
+    // the previous owner no longer manages the admins
+    let res = app
+        .demote_admin(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.leave_chat(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn transfer_password_is_normalized() {
+    let app = spawn_app().await;
+
+    // fullwidth form of "password"
+    let res = app.register("owner0", "ｐａｓｓｗｏｒｄ").await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let token = json["token"].as_str().unwrap();
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(token).await;
+    app.add_member(token, chat_id, &member.username).await;
+    app.promote_member(token, chat_id, member.id, &[], None)
+        .await;
+
+    let res = app
+        .transfer_ownership(token, chat_id, member.id, "ｐａｓｓｗｏｒｄ")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn database_keeps_exactly_one_owner() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = sqlx::query!(
+        "UPDATE chat_participants SET role = 'owner' WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await;
+    assert!(res.is_err());
+
+    let res = sqlx::query!(
+        "DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        app.test_user.id,
+    )
+    .execute(&app.db)
+    .await;
+    assert!(res.is_err());
+
+    let res = sqlx::query!(
+        "UPDATE chat_participants SET role = 'admin' WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        app.test_user.id,
+    )
+    .execute(&app.db)
+    .await;
+    assert!(res.is_err());
+
+    // a private chat cannot become a group without an owner
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &member.username)
+        .await;
+    let res = sqlx::query!("UPDATE chats SET type = 'group' WHERE id = $1", pm_id)
+        .execute(&app.db)
+        .await;
+    assert!(res.is_err());
+}
@@ -0,0 +1,239 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, spawn_app};
+
+/// Group of the test user with one more member
+async fn create_group_with_member(app: &TestApp) -> (i64, TestUser) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    (chat_id, member)
+}
+
+#[tokio::test]
+async fn slow_mode_rejects_fast_members() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = app
+        .set_anti_spam(&app.test_user.token, chat_id, json!({ "slow_mode": 60 }))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_anti_spam(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["slow_mode"].as_i64().unwrap(), 60);
+    assert!(json["flood_limit"].is_null());
+
+    let res = app.send_chat_message(&member.token, chat_id, "first").await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app
+        .send_chat_message(&member.token, chat_id, "second")
+        .await;
+    assert_eq!(res.status().as_u16(), 429);
+
+    // admins are exempt
+    for _ in 0..2 {
+        let res = app
+            .send_chat_message(&app.test_user.token, chat_id, "admin")
+            .await;
+        assert_eq!(res.status().as_u16(), 201);
+    }
+
+    let res = app
+        .set_anti_spam(&app.test_user.token, chat_id, json!({}))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .send_chat_message(&member.token, chat_id, "second")
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn flood_limit_restricts_member() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let flood_limit = json!({ "max_messages": 3, "interval": 60, "restrict_for": 3600 });
+    let res = app
+        .set_anti_spam(
+            &app.test_user.token,
+            chat_id,
+            json!({ "flood_limit": flood_limit }),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    for _ in 0..3 {
+        let res = app.send_chat_message(&member.token, chat_id, "spam").await;
+        assert_eq!(res.status().as_u16(), 201);
+    }
+    let res = app.send_chat_message(&member.token, chat_id, "spam").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let restriction = &json["restrictions"][0];
+    assert_eq!(restriction["user_id"].as_i64().unwrap(), member.id);
+    assert!(restriction["restricted_by"].is_null());
+    assert_eq!(restriction["permissions"], json!(["send_messages"]));
+    assert!(restriction["until"].is_i64());
+
+    // the admins can lift it early
+    let res = app
+        .restrict_member(&app.test_user.token, chat_id, member.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    sqlx::query!(
+        "DELETE FROM messages WHERE chat_id = $1 AND sender_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let res = app.send_chat_message(&member.token, chat_id, "sorry").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn flood_restriction_is_kept_apart_from_admin_restriction() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hi")
+        .await;
+
+    let res = app
+        .restrict_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["add_reactions"],
+            None,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let flood_limit = json!({ "max_messages": 1, "interval": 60, "restrict_for": 60 });
+    app.set_anti_spam(
+        &app.test_user.token,
+        chat_id,
+        json!({ "flood_limit": flood_limit }),
+    )
+    .await;
+
+    let res = app.send_chat_message(&member.token, chat_id, "one").await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.send_chat_message(&member.token, chat_id, "two").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let restrictions = json["restrictions"].as_array().unwrap();
+    assert_eq!(restrictions.len(), 2);
+    assert!(restrictions[0]["restricted_by"].is_null());
+    assert_eq!(restrictions[0]["permissions"], json!(["send_messages"]));
+    assert!(restrictions[0]["until"].is_i64());
+    assert_eq!(
+        restrictions[1]["restricted_by"].as_i64().unwrap(),
+        app.test_user.id
+    );
+    assert_eq!(restrictions[1]["permissions"], json!(["add_reactions"]));
+    assert!(restrictions[1]["until"].is_null());
+
+    // the flood restriction expires on its own, the one of the admins stays
+    sqlx::query!(
+        "UPDATE chat_flood_restrictions SET until = now() WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "DELETE FROM messages WHERE chat_id = $1 AND sender_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let res = app.send_chat_message(&member.token, chat_id, "three").await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.add_reaction(&member.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn parallel_messages_count_towards_flood_limit() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let flood_limit = json!({ "max_messages": 3, "interval": 60, "restrict_for": 3600 });
+    app.set_anti_spam(
+        &app.test_user.token,
+        chat_id,
+        json!({ "flood_limit": flood_limit }),
+    )
+    .await;
+
+    let responses = futures_util::future::join_all(
+        (0..10).map(|_| app.send_chat_message(&member.token, chat_id, "spam")),
+    )
+    .await;
+    let sent = responses
+        .iter()
+        .filter(|res| res.status().as_u16() == 201)
+        .count();
+    assert_eq!(sent, 3);
+}
+
+#[tokio::test]
+async fn only_admins_change_anti_spam_settings() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = app
+        .set_anti_spam(&member.token, chat_id, json!({ "slow_mode": 10 }))
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let stranger = app.create_test_user().await;
+    let res = app.get_anti_spam(&stranger.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let invalid = [
+        json!({ "slow_mode": 0 }),
+        json!({ "slow_mode": 3601 }),
+        json!({ "flood_limit": { "max_messages": 0, "interval": 10, "restrict_for": 60 } }),
+        json!({ "flood_limit": { "max_messages": 5, "interval": 3601, "restrict_for": 60 } }),
+        json!({ "flood_limit": { "max_messages": 5, "interval": 10, "restrict_for": 0 } }),
+    ];
+    for settings in invalid {
+        let res = app
+            .set_anti_spam(&app.test_user.token, chat_id, settings)
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+
+    // no slow mode for private chats
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &member.username)
+        .await;
+    let res = app
+        .set_anti_spam(&app.test_user.token, pm_id, json!({ "slow_mode": 10 }))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
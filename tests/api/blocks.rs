@@ -0,0 +1,96 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn success_block_list_and_unblock() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+
+    let res = app.block_user(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.list_blocked_users(&app.test_user.token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let users = json["users"].as_array().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0]["id"].as_i64().unwrap(), peer.id);
+
+    let res = app.unblock_user(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_blocked_users(&app.test_user.token).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["users"].as_array().unwrap().is_empty());
+
+    // unblock again
+    let res = app.unblock_user(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_block_self() {
+    let app = spawn_app().await;
+
+    let res = app
+        .block_user(&app.test_user.token, &app.test_user.username)
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_create_pm_when_blocked_by_peer() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    app.block_user(&peer.token, &app.test_user.username).await;
+
+    let res = app.create_pm(&app.test_user.token, &peer.username).await;
+
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn failure_send_message_when_any_side_blocked() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    app.block_user(&app.test_user.token, &peer.username).await;
+
+    // the blocked side
+    let res = app.send_chat_message(&peer.token, chat_id, "hello").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // the blocking side
+    let res = app
+        .send_chat_message(&app.test_user.token, chat_id, "hello")
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // messages are accepted again after unblocking
+    app.unblock_user(&app.test_user.token, &peer.username).await;
+    let res = app.send_chat_message(&peer.token, chat_id, "hello").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn blocked_users_are_hidden_from_search() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    app.block_user(&app.test_user.token, &user.username).await;
+
+    let ids = app
+        .search_user_ids(&app.test_user.token, &user.username)
+        .await;
+    assert!(!ids.contains(&user.id));
+
+    // the blocked user cannot find the blocker either
+    let ids = app.search_user_ids(&user.token, "test_user").await;
+    assert!(!ids.contains(&app.test_user.id));
+}
@@ -0,0 +1,83 @@
+use crate::helpers::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn participants_receive_action_and_expiry() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let mut stream = app.connect_updates(&peer.token).await;
+
+    let res = app
+        .send_chat_action(&app.test_user.token, chat_id, "typing")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let event = TestApp::next_event(&mut stream, "chat_action").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["user_id"].as_i64().unwrap(), app.test_user.id);
+    assert_eq!(event["action"], "typing");
+
+    // the server cancels the action when the client stops repeating it
+    let event = TestApp::next_event(&mut stream, "chat_action").await;
+    assert_eq!(event["action"], "cancel");
+}
+
+#[tokio::test]
+async fn explicit_cancel_is_broadcast() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let mut stream = app.connect_updates(&peer.token).await;
+
+    app.send_chat_action(&app.test_user.token, chat_id, "record_voice")
+        .await;
+    let event = TestApp::next_event(&mut stream, "chat_action").await;
+    assert_eq!(event["action"], "record_voice");
+
+    app.send_chat_action(&app.test_user.token, chat_id, "cancel")
+        .await;
+    let event = TestApp::next_event(&mut stream, "chat_action").await;
+    assert_eq!(event["action"], "cancel");
+    assert_eq!(event["expires_in"].as_u64().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn failure_when_not_participant() {
+    let app = spawn_app().await;
+
+    let user1 = app.create_test_user().await;
+    let user2 = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&user1.token, &user2.username)
+        .await;
+
+    let res = app
+        .send_chat_action(&app.test_user.token, chat_id, "typing")
+        .await;
+
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn failure_with_unknown_action() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let res = app
+        .send_chat_action(&app.test_user.token, chat_id, "dancing")
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
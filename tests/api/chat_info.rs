@@ -0,0 +1,232 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, fake_png, spawn_app};
+
+/// Group of the test user with one more member
+async fn create_group_with_member(app: &TestApp) -> (i64, TestUser) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    (chat_id, member)
+}
+
+#[tokio::test]
+async fn owner_changes_title_and_description() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = app
+        .set_chat_title(&app.test_user.token, chat_id, "  Cats  ")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let service_message_id = json["service_message_id"].as_i64().unwrap();
+
+    let messages = app.get_chat_messages(&member.token, chat_id).await;
+    assert_eq!(
+        messages[0]["message_id"].as_i64().unwrap(),
+        service_message_id
+    );
+    assert_eq!(messages[0]["kind"], "service");
+    assert_eq!(
+        messages[0]["service"],
+        json!({ "type": "change_title", "title": "Cats" })
+    );
+
+    // the same title records nothing
+    let res = app
+        .set_chat_title(&app.test_user.token, chat_id, "Cats")
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["service_message_id"].is_null());
+
+    let res = app
+        .set_chat_description(&app.test_user.token, chat_id, Some("All about cats"))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_chat_info(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["type"], "group");
+    assert_eq!(json["title"], "Cats");
+    assert_eq!(json["description"], "All about cats");
+    assert!(json["photo_media_id"].is_null());
+    assert_eq!(json["member_count"].as_i64().unwrap(), 2);
+
+    let res = app
+        .set_chat_description(&app.test_user.token, chat_id, Some("  "))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.get_chat_info(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["description"].is_null());
+}
+
+#[tokio::test]
+async fn chat_photo_is_visible_to_members() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+
+    // nobody else can see the upload yet
+    let res = app.download_media(&member.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app
+        .set_chat_photo(&app.test_user.token, chat_id, Some(media_id))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.download_media(&member.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_chat_info(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["photo_media_id"].as_i64().unwrap(), media_id);
+
+    let res = app
+        .set_chat_photo(&app.test_user.token, chat_id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let messages = app.get_chat_messages(&member.token, chat_id).await;
+    assert_eq!(
+        messages[0]["service"],
+        json!({ "type": "change_photo", "media_id": null })
+    );
+    assert_eq!(
+        messages[1]["service"],
+        json!({ "type": "change_photo", "media_id": media_id })
+    );
+
+    // only images uploaded by the admin
+    let other_media_id = app.upload_media_returns_id(&member.token, fake_png()).await;
+    let res = app
+        .set_chat_photo(&app.test_user.token, chat_id, Some(other_media_id))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+    let text_media_id = app
+        .upload_media_returns_id(&app.test_user.token, b"plain text".to_vec())
+        .await;
+    let res = app
+        .set_chat_photo(&app.test_user.token, chat_id, Some(text_media_id))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn changing_info_needs_the_right() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+
+    let res = app.set_chat_title(&member.token, chat_id, "Dogs").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // an admin without the right to change the info
+    sqlx::query!(
+        "UPDATE chat_participants SET role = 'admin' WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    let res = app.set_chat_title(&member.token, chat_id, "Dogs").await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.set_chat_photo(&member.token, chat_id, None).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    sqlx::query!(
+        "UPDATE chat_participants SET permission = 1 WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    let res = app.set_chat_title(&member.token, chat_id, "Dogs").await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    for title in ["", "   ", &"x".repeat(129)] {
+        let res = app.set_chat_title(&member.token, chat_id, title).await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+    let res = app
+        .set_chat_description(&member.token, chat_id, Some(&"x".repeat(256)))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    // private chats have no title
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &member.username)
+        .await;
+    let res = app.set_chat_title(&app.test_user.token, pm_id, "Us").await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn membership_changes_are_recorded() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_group_with_member(&app).await;
+    let joiner = app.create_test_user().await;
+    let (_, token) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+
+    let res = app.join_chat(&joiner.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.leave_chat(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .kick_member(&app.test_user.token, chat_id, joiner.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let messages = app.get_chat_messages(&app.test_user.token, chat_id).await;
+    let events: Vec<(i64, serde_json::Value)> = messages
+        .iter()
+        .rev()
+        .map(|message| {
+            (
+                message["sender_id"].as_i64().unwrap(),
+                message["service"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (
+                app.test_user.id,
+                json!({ "type": "add_member", "user_id": member.id })
+            ),
+            (
+                joiner.id,
+                json!({ "type": "add_member", "user_id": joiner.id })
+            ),
+            (
+                member.id,
+                json!({ "type": "remove_member", "user_id": member.id })
+            ),
+            (
+                app.test_user.id,
+                json!({ "type": "remove_member", "user_id": joiner.id })
+            ),
+        ]
+    );
+
+    // no longer a member
+    let res = app.leave_chat(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.leave_chat(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
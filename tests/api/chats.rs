@@ -75,3 +75,45 @@ async fn return_previous_chat_if_pm_already_exists() {
 
     assert_eq!(pm_id, sec_pm_id);
 }
+
+#[tokio::test]
+async fn success_create_group_and_add_member() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+
+    let res = app
+        .add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // the new member can send messages
+    let res = app
+        .send_chat_message(&member.token, chat_id, "hello world")
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // add the member again
+    let res = app
+        .add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_add_member_by_non_admin() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let other = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let res = app
+        .add_member(&member.token, chat_id, &other.username)
+        .await;
+
+    assert_eq!(res.status().as_u16(), 403);
+}
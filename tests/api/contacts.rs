@@ -0,0 +1,74 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn success_add_and_list_contacts() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+
+    let res = app.add_contact(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // adding the same contact again is not an error
+    let res = app.add_contact(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.list_contacts(&app.test_user.token).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let contacts = json["contacts"].as_array().unwrap();
+    assert_eq!(contacts.len(), 1);
+    assert_eq!(contacts[0]["id"].as_i64().unwrap(), peer.id);
+    assert_eq!(contacts[0]["username"].as_str().unwrap(), peer.username);
+
+    // the contact list is not mutual
+    let res = app.list_contacts(&peer.token).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["contacts"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn success_remove_contact() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    app.add_contact(&app.test_user.token, &peer.username).await;
+
+    let res = app
+        .remove_contact(&app.test_user.token, &peer.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_contacts(&app.test_user.token).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["contacts"].as_array().unwrap().is_empty());
+
+    // remove again
+    let res = app
+        .remove_contact(&app.test_user.token, &peer.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_add_non_exist_user() {
+    let app = spawn_app().await;
+
+    let res = app
+        .add_contact(&app.test_user.token, "user_not_found")
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_add_self() {
+    let app = spawn_app().await;
+
+    let res = app
+        .add_contact(&app.test_user.token, &app.test_user.username)
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
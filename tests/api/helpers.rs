@@ -1,10 +1,16 @@
 use anyhow::Context;
+use futures_util::StreamExt;
 use sqlx::Executor;
-use std::sync::LazyLock;
+use std::{sync::LazyLock, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream,
+    tungstenite::{Message, client::IntoClientRequest},
+};
 
 use nyat::{
-    auth::{generate_token, hash_password},
-    configuration::{DatabaseConfig, load_config},
+    auth::{generate_token, hash_password, username_skeleton},
+    configuration::{DatabaseConfig, MediaStorageConfig, load_config},
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -23,119 +29,1452 @@ static TRACING: LazyLock<()> = LazyLock::new(|| {
         let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
         init_subscriber(subscriber);
     }
-});
+});
+
+pub const UPLOAD_CHUNK_SIZE: usize = 1024;
+pub const STORAGE_QUOTA: i64 = 2 * 1024 * 1024;
+
+pub type UpdatesStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct TestApp {
+    pub address: String,
+    pub port: u16,
+    pub db: PgPool,
+    pub test_user: TestUser,
+
+    http_client: reqwest::Client,
+}
+
+impl TestApp {
+    pub async fn register(&self, username: &str, password: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/user/register", self.address))
+            .json(&json!({
+                "username": username,
+                "password": password
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/user/login", self.address))
+            .json(&json!({
+                "username": username,
+                "password": password
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_test_user(&self) -> TestUser {
+        // request the register api
+        let username = Uuid::new_v4().to_string().replace("-", "_");
+        let password = Uuid::new_v4().to_string();
+        let res = self.register(&username, &password).await;
+
+        // make sure the request success
+        assert_eq!(res.status().as_u16(), 200);
+
+        // extract the token field from the response
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        let json = json.as_object().unwrap();
+
+        let token = json
+            .get("token")
+            .context("Field \"token\" not exist on register response")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // query the user id from the database
+        let id = sqlx::query!("SELECT id FROM users WHERE username = $1", username)
+            .fetch_one(&self.db)
+            .await
+            .unwrap()
+            .id;
+
+        TestUser {
+            id,
+            username,
+            password,
+            token,
+        }
+    }
+
+    pub async fn create_pm(&self, token: &str, peer_username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/pm", self.address))
+            .json(&json!({
+                "peer_username": peer_username
+            }))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_pm_returns_id(&self, token: &str, peer_username: &str) -> i64 {
+        let res = self.create_pm(token, peer_username).await;
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json.as_object()
+            .unwrap()
+            .get("chat_id")
+            .unwrap()
+            .as_i64()
+            .unwrap()
+    }
+
+    pub async fn add_contact(&self, token: &str, username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/contact/add", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "username": username,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn remove_contact(&self, token: &str, username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/contact/remove", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "username": username,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_contacts(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/contact/list", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn update_profile(
+        &self,
+        token: &str,
+        profile: serde_json::Value,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/user/profile", self.address))
+            .bearer_auth(token)
+            .json(&profile)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_profile_photo(&self, token: &str, media_id: Option<i64>) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/user/photo", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "media_id": media_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn search_users(&self, token: &str, q: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/users/search", self.address))
+            .bearer_auth(token)
+            .query(&[("q", q)])
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Search users and collect the ids of the results
+    pub async fn search_user_ids(&self, token: &str, q: &str) -> Vec<i64> {
+        let res = self.search_users(token, q).await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    pub async fn block_user(&self, token: &str, username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/block/add", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "username": username,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn unblock_user(&self, token: &str, username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/block/remove", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "username": username,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_blocked_users(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/block/list", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_group(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/group", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_group_returns_id(&self, token: &str) -> i64 {
+        let res = self.create_group(token).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["chat_id"].as_i64().unwrap()
+    }
+
+    pub async fn add_member(&self, token: &str, chat_id: i64, username: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/add", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "username": username,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn leave_chat(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/leave", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_chat_info(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/info", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_chat_title(
+        &self,
+        token: &str,
+        chat_id: i64,
+        title: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/title", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "title": title,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_chat_description(
+        &self,
+        token: &str,
+        chat_id: i64,
+        description: Option<&str>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/description", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "description": description,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_chat_photo(
+        &self,
+        token: &str,
+        chat_id: i64,
+        media_id: Option<i64>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/photo", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "media_id": media_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_invite_link(
+        &self,
+        token: &str,
+        chat_id: i64,
+        options: serde_json::Value,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/invite-links", self.address))
+            .bearer_auth(token)
+            .json(&options)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Returns the id and the token of the link
+    pub async fn create_invite_link_returns_token(
+        &self,
+        token: &str,
+        chat_id: i64,
+        options: serde_json::Value,
+    ) -> (i64, String) {
+        let res = self.create_invite_link(token, chat_id, options).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        (
+            json["link_id"].as_i64().unwrap(),
+            json["token"].as_str().unwrap().to_string(),
+        )
+    }
+
+    pub async fn list_invite_links(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/invite-links", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn revoke_invite_link(
+        &self,
+        token: &str,
+        chat_id: i64,
+        link_id: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/invite-links/{link_id}/revoke",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_invite_link_joins(
+        &self,
+        token: &str,
+        chat_id: i64,
+        link_id: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!(
+                "{}/chat/{chat_id}/invite-links/{link_id}/joins",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn join_chat(&self, token: &str, invite_token: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/join", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "token": invite_token,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn request_to_join(
+        &self,
+        token: &str,
+        invite_token: &str,
+        message: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/join", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "token": invite_token,
+                "message": message,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_join_requests(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/join-requests", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Approve or decline the join requests of the users
+    pub async fn decide_join_requests(
+        &self,
+        token: &str,
+        chat_id: i64,
+        user_ids: &[i64],
+        approve: bool,
+    ) -> reqwest::Response {
+        let decision = if approve { "approve" } else { "decline" };
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/join-requests/{decision}",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_ids": user_ids,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn kick_member(&self, token: &str, chat_id: i64, user_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/kick", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn ban_member(
+        &self,
+        token: &str,
+        chat_id: i64,
+        user_id: i64,
+        until: Option<i64>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/ban", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+                "until": until,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn unban_member(&self, token: &str, chat_id: i64, user_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/unban", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_bans(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/bans", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn restrict_member(
+        &self,
+        token: &str,
+        chat_id: i64,
+        user_id: i64,
+        permissions: &[&str],
+        until: Option<i64>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/restrict", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+                "permissions": permissions,
+                "until": until,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_restrictions(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/restrictions", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn promote_member(
+        &self,
+        token: &str,
+        chat_id: i64,
+        user_id: i64,
+        rights: &[&str],
+        custom_title: Option<&str>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/promote", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+                "rights": rights,
+                "custom_title": custom_title,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn demote_admin(&self, token: &str, chat_id: i64, user_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/member/demote", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn transfer_ownership(
+        &self,
+        token: &str,
+        chat_id: i64,
+        user_id: i64,
+        password: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/transfer-ownership",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_id": user_id,
+                "password": password,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_admins(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/admins", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_log(
+        &self,
+        token: &str,
+        chat_id: i64,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/admin-log", self.address))
+            .bearer_auth(token)
+            .query(query)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_privacy(&self, token: &str, rule: serde_json::Value) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/privacy/set", self.address))
+            .bearer_auth(token)
+            .json(&rule)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_privacy(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/privacy/list", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_presence(&self, token: &str, user_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/user/{user_id}/presence", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Open the real-time channel
+    pub async fn connect_updates(&self, token: &str) -> UpdatesStream {
+        let mut request = format!("ws://localhost:{}/updates", self.port)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("Authorization", format!("Bearer {token}").parse().unwrap());
+
+        let (stream, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        stream
+    }
+
+    /// Wait for the next event of the given type on the real-time channel
+    pub async fn next_event(stream: &mut UpdatesStream, event_type: &str) -> serde_json::Value {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let msg = stream.next().await.unwrap().unwrap();
+                if let Message::Text(text) = msg {
+                    let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    if event["type"] == event_type {
+                        return event;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for event")
+    }
+
+    pub async fn send_chat_action(
+        &self,
+        token: &str,
+        chat_id: i64,
+        action: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/action", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "action": action,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn upload_media(&self, token: &str, data: Vec<u8>) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/media/upload", self.address))
+            .bearer_auth(token)
+            .body(data)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn upload_media_returns_id(&self, token: &str, data: Vec<u8>) -> i64 {
+        let res = self.upload_media(token, data).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["media_id"].as_i64().unwrap()
+    }
+
+    pub async fn download_media(&self, token: &str, media_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/media/{media_id}", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn delete_media(&self, token: &str, media_id: i64) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/media/{media_id}", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_storage_usage(&self, token: &str) -> serde_json::Value {
+        let res = self
+            .http_client
+            .get(format!("{}/media/quota", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        res.json().await.unwrap()
+    }
+
+    pub async fn get_media_info(&self, token: &str, media_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/media/{media_id}/info", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn download_thumbnail(
+        &self,
+        token: &str,
+        media_id: i64,
+        size: u32,
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!(
+                "{}/media/{media_id}/thumbnail/{size}",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_upload_session(&self, token: &str, total_size: usize) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/media/session", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "total_size": total_size,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_upload_session_returns_id(&self, token: &str, total_size: usize) -> i64 {
+        let res = self.create_upload_session(token, total_size).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["session_id"].as_i64().unwrap()
+    }
+
+    pub async fn get_upload_session(&self, token: &str, session_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/media/session/{session_id}", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn upload_chunk(
+        &self,
+        token: &str,
+        session_id: i64,
+        chunk_index: i32,
+        data: Vec<u8>,
+    ) -> reqwest::Response {
+        self.http_client
+            .put(format!(
+                "{}/media/session/{session_id}/chunk/{chunk_index}",
+                self.address
+            ))
+            .bearer_auth(token)
+            .body(data)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn finalize_upload_session(
+        &self,
+        token: &str,
+        session_id: i64,
+        sha256: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/media/session/{session_id}/finalize",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "sha256": sha256,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn send_chat_message_with_media(
+        &self,
+        token: &str,
+        chat_id: i64,
+        content: &str,
+        media_ids: &[i64],
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "content": content,
+                "media_ids": media_ids,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn send_voice_message(
+        &self,
+        token: &str,
+        chat_id: i64,
+        media_ids: &[i64],
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "content": "",
+                "kind": "voice",
+                "media_ids": media_ids,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn send_chat_message(
+        &self,
+        token: &str,
+        chat_id: i64,
+        content: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "content": content,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn send_expiring_message(
+        &self,
+        token: &str,
+        chat_id: i64,
+        media_ids: &[i64],
+        ttl: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "content": "expiring",
+                "media_ids": media_ids,
+                "ttl": ttl,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn send_chat_message_returns_id(
+        &self,
+        token: &str,
+        chat_id: i64,
+        content: &str,
+    ) -> i64 {
+        let res = self.send_chat_message(token, chat_id, content).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["message_id"].as_i64().unwrap()
+    }
+
+    pub async fn get_chat_history(
+        &self,
+        token: &str,
+        chat_id: i64,
+        query: &[(&str, i64)],
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/messages", self.address))
+            .bearer_auth(token)
+            .query(query)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Load the history of the chat, the newest message first
+    pub async fn get_chat_messages(&self, token: &str, chat_id: i64) -> Vec<serde_json::Value> {
+        let res = self.get_chat_history(token, chat_id, &[]).await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["messages"].as_array().unwrap().clone()
+    }
+
+    pub async fn get_topic_messages(
+        &self,
+        token: &str,
+        chat_id: i64,
+        topic_id: i64,
+    ) -> Vec<serde_json::Value> {
+        let res = self
+            .get_chat_history(token, chat_id, &[("topic_id", topic_id)])
+            .await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["messages"].as_array().unwrap().clone()
+    }
+
+    pub async fn send_topic_message(
+        &self,
+        token: &str,
+        chat_id: i64,
+        topic_id: i64,
+        content: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "topic_id": topic_id,
+                "content": content,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn toggle_topics(
+        &self,
+        token: &str,
+        chat_id: i64,
+        enabled: bool,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/forum", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "enabled": enabled,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn list_topics(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/topics", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_topic(&self, token: &str, chat_id: i64, title: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/topics", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "title": title,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn create_topic_returns_id(&self, token: &str, chat_id: i64, title: &str) -> i64 {
+        let res = self.create_topic(token, chat_id, title).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["topic_id"].as_i64().unwrap()
+    }
+
+    pub async fn rename_topic(
+        &self,
+        token: &str,
+        chat_id: i64,
+        topic_id: i64,
+        title: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/topics/{topic_id}/rename",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "title": title,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn close_topic(&self, token: &str, chat_id: i64, topic_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/topics/{topic_id}/close",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn reopen_topic(
+        &self,
+        token: &str,
+        chat_id: i64,
+        topic_id: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/topics/{topic_id}/reopen",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
 
-pub struct TestApp {
-    pub address: String,
-    pub port: u16,
-    pub db: PgPool,
-    pub test_user: TestUser,
+    pub async fn read_topic(
+        &self,
+        token: &str,
+        chat_id: i64,
+        topic_id: i64,
+        message_id: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/chat/{chat_id}/topics/{topic_id}/read",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "message_id": message_id,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
 
-    http_client: reqwest::Client,
-}
+    pub async fn add_reaction(
+        &self,
+        token: &str,
+        message_id: i64,
+        emoji: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/message/{message_id}/reaction/add",
+                self.address
+            ))
+            .bearer_auth(token)
+            .json(&json!({
+                "emoji": emoji,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
 
-impl TestApp {
-    pub async fn register(&self, username: &str, password: &str) -> reqwest::Response {
+    pub async fn remove_reaction(
+        &self,
+        token: &str,
+        message_id: i64,
+        emoji: &str,
+    ) -> reqwest::Response {
         self.http_client
-            .post(format!("{}/user/register", self.address))
+            .post(format!(
+                "{}/message/{message_id}/reaction/remove",
+                self.address
+            ))
+            .bearer_auth(token)
             .json(&json!({
-                "username": username,
-                "password": password
+                "emoji": emoji,
             }))
             .send()
             .await
             .unwrap()
     }
 
-    pub async fn login(&self, username: &str, password: &str) -> reqwest::Response {
+    pub async fn set_allowed_reactions(
+        &self,
+        token: &str,
+        chat_id: i64,
+        allowed: Option<&[&str]>,
+    ) -> reqwest::Response {
         self.http_client
-            .post(format!("{}/user/login", self.address))
+            .post(format!("{}/chat/{chat_id}/reactions", self.address))
+            .bearer_auth(token)
             .json(&json!({
-                "username": username,
-                "password": password
+                "allowed": allowed,
             }))
             .send()
             .await
             .unwrap()
     }
 
-    pub async fn create_test_user(&self) -> TestUser {
-        // request the register api
-        let username = Uuid::new_v4().to_string().replace("-", "_");
-        let password = Uuid::new_v4().to_string();
-        let res = self.register(&username, &password).await;
+    pub async fn get_allowed_reactions(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/reactions", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
 
-        // make sure the request success
-        assert_eq!(res.status().as_u16(), 200);
+    pub async fn delete_message(&self, token: &str, message_id: i64) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/message/{message_id}", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
 
-        // extract the token field from the response
-        let json = res.json::<serde_json::Value>().await.unwrap();
-        let json = json.as_object().unwrap();
+    pub async fn pin_message(&self, token: &str, message_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/{message_id}/pin", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
 
-        let token = json
-            .get("token")
-            .context("Field \"token\" not exist on register response")
+    pub async fn unpin_message(&self, token: &str, message_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/{message_id}/unpin", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
             .unwrap()
-            .as_str()
+    }
+
+    pub async fn list_pinned_messages(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/pinned", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
             .unwrap()
-            .to_string();
+    }
 
-        // query the user id from the database
-        let id = sqlx::query!("SELECT id FROM users WHERE username = $1", username)
-            .fetch_one(&self.db)
+    pub async fn search_chat_messages(
+        &self,
+        token: &str,
+        chat_id: i64,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/search", self.address))
+            .bearer_auth(token)
+            .query(query)
+            .send()
             .await
             .unwrap()
-            .id;
+    }
 
-        TestUser {
-            id,
-            username,
-            password,
-            token,
-        }
+    pub async fn search_all_messages(
+        &self,
+        token: &str,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/messages/search", self.address))
+            .bearer_auth(token)
+            .query(query)
+            .send()
+            .await
+            .unwrap()
     }
 
-    pub async fn create_pm(&self, token: &str, peer_username: &str) -> reqwest::Response {
+    pub async fn send_poll(
+        &self,
+        token: &str,
+        chat_id: i64,
+        question: &str,
+        poll: serde_json::Value,
+    ) -> reqwest::Response {
         self.http_client
-            .post(format!("{}/chat/pm", self.address))
+            .post(format!("{}/message/send", self.address))
+            .bearer_auth(token)
             .json(&json!({
-                "peer_username": peer_username
+                "chat_id": chat_id,
+                "content": question,
+                "kind": "poll",
+                "poll": poll,
             }))
-            .bearer_auth(token)
             .send()
             .await
             .unwrap()
     }
 
-    pub async fn create_pm_returns_id(&self, token: &str, peer_username: &str) -> i64 {
-        let res = self.create_pm(token, peer_username).await;
+    pub async fn send_poll_returns_id(
+        &self,
+        token: &str,
+        chat_id: i64,
+        poll: serde_json::Value,
+    ) -> i64 {
+        let res = self.send_poll(token, chat_id, "Question?", poll).await;
+        assert_eq!(res.status().as_u16(), 201);
 
         let json = res.json::<serde_json::Value>().await.unwrap();
-        json.as_object()
+        json["message_id"].as_i64().unwrap()
+    }
+
+    pub async fn vote_poll(
+        &self,
+        token: &str,
+        message_id: i64,
+        options: &[i16],
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/{message_id}/poll/vote", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "options": options,
+            }))
+            .send()
+            .await
             .unwrap()
-            .get("chat_id")
+    }
+
+    pub async fn retract_vote(&self, token: &str, message_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/message/{message_id}/poll/retract",
+                self.address
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
             .unwrap()
-            .as_i64()
+    }
+
+    pub async fn close_poll(&self, token: &str, message_id: i64) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/{message_id}/poll/close", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
             .unwrap()
     }
 
-    pub async fn send_chat_message(
+    pub async fn set_auto_delete(
+        &self,
+        token: &str,
+        chat_id: i64,
+        period: Option<&str>,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/auto-delete", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "period": period,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_auto_delete(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/auto-delete", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_anti_spam(
+        &self,
+        token: &str,
+        chat_id: i64,
+        settings: serde_json::Value,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/chat/{chat_id}/anti-spam", self.address))
+            .bearer_auth(token)
+            .json(&settings)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_anti_spam(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/anti-spam", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn schedule_message(
         &self,
         token: &str,
         chat_id: i64,
         content: &str,
+        send_at: i64,
     ) -> reqwest::Response {
         self.http_client
-            .post(format!("{}/message/send", self.address))
+            .post(format!("{}/message/schedule", self.address))
             .bearer_auth(token)
             .json(&json!({
                 "chat_id": chat_id,
                 "content": content,
+                "send_at": send_at,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn schedule_message_returns_id(
+        &self,
+        token: &str,
+        chat_id: i64,
+        content: &str,
+        send_at: i64,
+    ) -> i64 {
+        let res = self
+            .schedule_message(token, chat_id, content, send_at)
+            .await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        json["scheduled_id"].as_i64().unwrap()
+    }
+
+    pub async fn schedule_poll(
+        &self,
+        token: &str,
+        chat_id: i64,
+        poll: serde_json::Value,
+        send_at: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/schedule", self.address))
+            .bearer_auth(token)
+            .json(&json!({
+                "chat_id": chat_id,
+                "content": "Question?",
+                "kind": "poll",
+                "poll": poll,
+                "send_at": send_at,
             }))
             .send()
             .await
             .unwrap()
     }
+
+    pub async fn list_scheduled_messages(&self, token: &str, chat_id: i64) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/chat/{chat_id}/scheduled", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn edit_scheduled_message(
+        &self,
+        token: &str,
+        scheduled_id: i64,
+        edit: serde_json::Value,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/message/scheduled/{scheduled_id}", self.address))
+            .bearer_auth(token)
+            .json(&edit)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn cancel_scheduled_message(
+        &self,
+        token: &str,
+        scheduled_id: i64,
+    ) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/message/scheduled/{scheduled_id}", self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
@@ -153,6 +1492,28 @@ pub async fn spawn_app() -> TestApp {
         // use a random database name
         c.database.set_db_name(&Uuid::new_v4().to_string());
 
+        // idle users go offline quickly
+        c.presence.online_timeout = 1;
+
+        // due scheduled messages are posted and expired messages are deleted quickly
+        c.messages.scheduler_interval = 1;
+        c.messages.sweeper_interval = 1;
+
+        // store the media of each test in its own directory
+        c.media.max_file_size = 1024 * 1024;
+        c.media.upload_session.chunk_size = UPLOAD_CHUNK_SIZE;
+        c.media.upload_session.gc_interval = 1;
+        c.media.quota_per_user = STORAGE_QUOTA;
+        c.media.retention.grace_period = 1;
+        c.media.retention.gc_interval = 1;
+        c.media.storage = MediaStorageConfig::Local {
+            path: std::env::temp_dir()
+                .join("nyat-test-media")
+                .join(Uuid::new_v4().to_string())
+                .to_string_lossy()
+                .to_string(),
+        };
+
         c
     };
 
@@ -218,9 +1579,10 @@ async fn insert_test_user(pool: &PgPool, token_secret: &[u8]) -> TestUser {
     let password = "testtest";
     let hashed_password = hash_password(password).unwrap();
     let test_user_id = sqlx::query!(
-        "INSERT INTO users (username, password) VALUES ($1, $2) RETURNING (id);",
+        "INSERT INTO users (username, password, username_skeleton) VALUES ($1, $2, $3) RETURNING (id);",
         username,
-        hashed_password
+        hashed_password,
+        username_skeleton(username),
     )
     .fetch_one(pool)
     .await
@@ -237,3 +1599,15 @@ async fn insert_test_user(pool: &PgPool, token_secret: &[u8]) -> TestUser {
         token,
     }
 }
+
+/// A tiny PNG image, the content is unique for every call
+pub fn fake_png() -> Vec<u8> {
+    let seed = Uuid::new_v4();
+    let image = image::RgbImage::from_fn(4, 4, |x, y| {
+        image::Rgb([seed.as_bytes()[(y * 4 + x) as usize], 0, 0])
+    });
+
+    let mut buf = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
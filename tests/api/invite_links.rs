@@ -0,0 +1,191 @@
+use serde_json::json;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn join_group_by_link() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (link_id, token) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+
+    // members only
+    let res = app.send_chat_message(&user.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["chat_id"].as_i64().unwrap(), chat_id);
+
+    let res = app.send_chat_message(&user.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app
+        .list_invite_link_joins(&app.test_user.token, chat_id, link_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["uses"].as_i64().unwrap(), 1);
+    assert_eq!(json["joins"][0]["user_id"].as_i64().unwrap(), user.id);
+    assert_eq!(json["joins"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn link_stops_working_after_max_uses() {
+    let app = spawn_app().await;
+
+    let first = app.create_test_user().await;
+    let second = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (_, token) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({ "max_uses": 1 }))
+        .await;
+
+    let res = app.join_chat(&first.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.join_chat(&second.token, &token).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn expired_link_cannot_be_used() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let expires_at = time::OffsetDateTime::now_utc().unix_timestamp() + 3600;
+    let (link_id, token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "expires_at": expires_at }),
+        )
+        .await;
+
+    sqlx::query!(
+        "UPDATE invite_links SET expires_at = now() WHERE id = $1",
+        link_id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    // the expiry must be within the next year
+    for expires_at in [expires_at - 7200, expires_at + 400 * 24 * 3600, i64::MAX] {
+        let res = app
+            .create_invite_link(
+                &app.test_user.token,
+                chat_id,
+                json!({ "expires_at": expires_at }),
+            )
+            .await;
+        assert_eq!(res.status().as_u16(), 400, "{expires_at}");
+    }
+}
+
+#[tokio::test]
+async fn revoked_link_cannot_be_used() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (link_id, token) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+
+    let res = app
+        .revoke_invite_link(&app.test_user.token, chat_id, link_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .revoke_invite_link(&app.test_user.token, chat_id, link_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.list_invite_links(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["links"][0]["link_id"].as_i64().unwrap(), link_id);
+    assert_eq!(json["links"][0]["revoked"], true);
+}
+
+#[tokio::test]
+async fn link_requiring_approval_files_join_request() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (_, token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "requires_approval": true }),
+        )
+        .await;
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // not a member until approved
+    let res = app.send_chat_message(&user.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn only_admins_manage_invite_links() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let (link_id, _) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+
+    let res = app
+        .create_invite_link(&member.token, chat_id, json!({}))
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.list_invite_links(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .revoke_invite_link(&member.token, chat_id, link_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .list_invite_link_joins(&member.token, chat_id, link_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // no links for private chats
+    let peer = app.create_test_user().await;
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let res = app
+        .create_invite_link(&app.test_user.token, pm_id, json!({}))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_join_with_unknown_token() {
+    let app = spawn_app().await;
+
+    let res = app.join_chat(&app.test_user.token, "not-a-token").await;
+    assert_eq!(res.status().as_u16(), 404);
+}
@@ -0,0 +1,203 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, spawn_app};
+
+/// Group of the test user with a link requiring approval
+async fn create_group_with_approval_link(app: &TestApp) -> (i64, i64, String) {
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (link_id, token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "requires_approval": true }),
+        )
+        .await;
+
+    (chat_id, link_id, token)
+}
+
+async fn pending_user_ids(app: &TestApp, chat_id: i64) -> Vec<i64> {
+    let res = app.list_join_requests(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["requests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|request| request["user_id"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn approved_applicant_joins_and_is_notified() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let (chat_id, link_id, token) = create_group_with_approval_link(&app).await;
+
+    let res = app
+        .request_to_join(&user.token, &token, "let me in please")
+        .await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    let res = app.list_join_requests(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let request = &json["requests"][0];
+    assert_eq!(request["user_id"].as_i64().unwrap(), user.id);
+    assert_eq!(request["link_id"].as_i64().unwrap(), link_id);
+    assert_eq!(request["message"], "let me in please");
+
+    let mut updates = app.connect_updates(&user.token).await;
+
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[user.id], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["user_ids"], json!([user.id]));
+
+    let event = TestApp::next_event(&mut updates, "join_request_decided").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["approved"], true);
+
+    let res = app.send_chat_message(&user.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 201);
+    assert!(pending_user_ids(&app, chat_id).await.is_empty());
+
+    // the approval counts as a use of the link
+    let res = app
+        .list_invite_link_joins(&app.test_user.token, chat_id, link_id)
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["uses"].as_i64().unwrap(), 1);
+    assert_eq!(json["joins"][0]["user_id"].as_i64().unwrap(), user.id);
+}
+
+#[tokio::test]
+async fn declined_applicant_is_notified() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let (chat_id, _, token) = create_group_with_approval_link(&app).await;
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    let mut updates = app.connect_updates(&user.token).await;
+
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[user.id], false)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let event = TestApp::next_event(&mut updates, "join_request_decided").await;
+    assert_eq!(event["approved"], false);
+
+    let res = app.send_chat_message(&user.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // the request is gone
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[user.id], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    // and can be filed again
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn decide_join_requests_in_bulk() {
+    let app = spawn_app().await;
+
+    let (chat_id, _, token) = create_group_with_approval_link(&app).await;
+    let mut applicants = Vec::new();
+    for _ in 0..3 {
+        let user = app.create_test_user().await;
+        let res = app.join_chat(&user.token, &token).await;
+        assert_eq!(res.status().as_u16(), 202);
+        applicants.push(user);
+    }
+    let stranger = app.create_test_user().await;
+
+    let res = app
+        .decide_join_requests(
+            &app.test_user.token,
+            chat_id,
+            &[applicants[0].id, applicants[1].id, stranger.id],
+            true,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let mut approved: Vec<i64> = json["user_ids"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| id.as_i64().unwrap())
+        .collect();
+    approved.sort();
+    assert_eq!(approved, vec![applicants[0].id, applicants[1].id]);
+
+    assert_eq!(
+        pending_user_ids(&app, chat_id).await,
+        vec![applicants[2].id]
+    );
+
+    let res = app
+        .decide_join_requests(&app.test_user.token, chat_id, &[], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_request_twice_or_as_member() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let member = app.create_test_user().await;
+    let (chat_id, _, token) = create_group_with_approval_link(&app).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let other = app.create_test_user().await;
+    let res = app
+        .request_to_join(&other.token, &token, &"x".repeat(257))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_admins_manage_join_requests() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let member = app.create_test_user().await;
+    let (chat_id, _, token) = create_group_with_approval_link(&app).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let res = app.join_chat(&user.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    let res = app.list_join_requests(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .decide_join_requests(&member.token, chat_id, &[user.id], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .decide_join_requests(&user.token, chat_id, &[user.id], true)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+}
@@ -1,5 +1,30 @@
+mod admin_log;
+mod admins;
+mod anti_spam;
+mod blocks;
+mod chat_actions;
+mod chat_info;
 mod chats;
+mod contacts;
 mod helpers;
+mod invite_links;
+mod join_requests;
 mod login;
+mod media;
+mod message_expiry;
+mod message_search;
 mod messages;
+mod moderation;
+mod opus;
+mod pins;
+mod polls;
+mod presence;
+mod privacy;
+mod quota;
+mod reactions;
 mod register;
+mod scheduled_messages;
+mod search;
+mod topics;
+mod upload_sessions;
+mod voice;
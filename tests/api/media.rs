@@ -0,0 +1,336 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
+use image::{ImageFormat, Rgb, RgbImage};
+use nyat::media::{LocalMediaStore, MediaStore, S3MediaStore, media_key};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::helpers::{fake_png, spawn_app};
+
+#[tokio::test]
+async fn success_upload_with_sniffed_content_type() {
+    let app = spawn_app().await;
+
+    let data = fake_png();
+    let res = app.upload_media(&app.test_user.token, data.clone()).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["content_type"], "image/png");
+    assert_eq!(json["size"].as_u64().unwrap(), data.len() as u64);
+    assert_eq!(json["sha256"].as_str().unwrap().len(), 64);
+}
+
+#[tokio::test]
+async fn unknown_content_is_octet_stream() {
+    let app = spawn_app().await;
+
+    let res = app
+        .upload_media(&app.test_user.token, b"just some text".to_vec())
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["content_type"], "application/octet-stream");
+}
+
+#[tokio::test]
+async fn same_content_is_deduplicated() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let data = fake_png();
+
+    let first = app
+        .upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+    let second = app.upload_media_returns_id(&user.token, data).await;
+    assert_eq!(first, second);
+
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM media"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn failure_when_file_too_large() {
+    let app = spawn_app().await;
+
+    let res = app
+        .upload_media(&app.test_user.token, vec![0; 1024 * 1024 + 1])
+        .await;
+
+    assert_eq!(res.status().as_u16(), 413);
+}
+
+#[tokio::test]
+async fn failure_with_empty_file() {
+    let app = spawn_app().await;
+
+    let res = app.upload_media(&app.test_user.token, Vec::new()).await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn uploader_can_download() {
+    let app = spawn_app().await;
+
+    let data = fake_png();
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+
+    let res = app.download_media(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+        "image/png"
+    );
+    assert_eq!(res.bytes().await.unwrap().to_vec(), data);
+}
+
+#[tokio::test]
+async fn only_chat_participants_can_download_attached_media() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let stranger = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+
+    // not attached yet
+    let res = app.download_media(&peer.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app
+        .send_chat_message_with_media(&app.test_user.token, chat_id, "", &[media_id])
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.download_media(&peer.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.download_media(&stranger.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn failure_attach_media_uploaded_by_others() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let other = app.create_test_user().await;
+    let media_id = app.upload_media_returns_id(&other.token, fake_png()).await;
+
+    let res = app
+        .send_chat_message_with_media(&app.test_user.token, chat_id, "", &[media_id])
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+async fn store_round_trip(store: &dyn MediaStore) {
+    let data = fake_png();
+    let key = media_key(&format!("{:x}", Sha256::digest(&data)));
+
+    store
+        .put(&key, Bytes::from(data.clone()), "image/png")
+        .await
+        .unwrap();
+    assert_eq!(store.get(&key).await.unwrap().unwrap().to_vec(), data);
+
+    store.delete(&key).await.unwrap();
+    assert!(store.get(&key).await.unwrap().is_none());
+
+    // deleting a missing key is not an error
+    store.delete(&key).await.unwrap();
+}
+
+#[tokio::test]
+async fn local_store_round_trip() {
+    let path = std::env::temp_dir()
+        .join("nyat-test-media")
+        .join(Uuid::new_v4().to_string());
+    let store = LocalMediaStore::new(path.to_str().unwrap());
+
+    store_round_trip(&store).await;
+}
+
+/// Needs the MinIO services of docker-compose.yaml
+#[tokio::test]
+#[ignore]
+async fn s3_store_round_trip() {
+    let store = S3MediaStore::new(
+        "nyat",
+        "us-east-1",
+        Some("http://localhost:9000"),
+        "minioadmin",
+        "minioadmin",
+    )
+    .unwrap();
+
+    store_round_trip(&store).await;
+}
+
+fn png_image(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 128]));
+    let mut buf = Cursor::new(Vec::new());
+    image.write_to(&mut buf, ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+/// JPEG with an EXIF block holding the orientation and a fake GPS position
+fn jpeg_with_exif(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, _| Rgb([x as u8, 0, 0]));
+    let mut jpeg = Cursor::new(Vec::new());
+    image.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+    let jpeg = jpeg.into_inner();
+
+    // little endian TIFF with a single IFD entry for the orientation
+    let mut exif = b"Exif\0\0II\x2a\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+    exif.extend_from_slice(&orientation.to_le_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    exif.extend_from_slice(b"GPS 48.8584N 2.2945E");
+
+    let mut data = jpeg[..2].to_vec();
+    data.extend_from_slice(&[0xff, 0xe1]);
+    data.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    data.extend_from_slice(&exif);
+    data.extend_from_slice(&jpeg[2..]);
+    data
+}
+
+#[tokio::test]
+async fn image_gets_metadata_and_thumbnails() {
+    let app = spawn_app().await;
+
+    let res = app
+        .upload_media(&app.test_user.token, png_image(1000, 500))
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["width"], 1000);
+    assert_eq!(json["height"], 500);
+    assert!(!json["blurhash"].as_str().unwrap().is_empty());
+    assert_eq!(
+        json["thumbnails"],
+        serde_json::json!([
+            {"size": 90, "width": 90, "height": 45},
+            {"size": 320, "width": 320, "height": 160},
+            {"size": 800, "width": 800, "height": 400},
+        ])
+    );
+
+    let media_id = json["media_id"].as_i64().unwrap();
+    let res = app.get_media_info(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.json::<serde_json::Value>().await.unwrap(), json);
+
+    let res = app
+        .download_thumbnail(&app.test_user.token, media_id, 320)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+        "image/jpeg"
+    );
+    let thumbnail = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (320, 160));
+
+    let res = app
+        .download_thumbnail(&app.test_user.token, media_id, 1000)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn small_image_has_no_larger_thumbnails() {
+    let app = spawn_app().await;
+
+    let res = app
+        .upload_media(&app.test_user.token, png_image(100, 50))
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json["thumbnails"],
+        serde_json::json!([{"size": 90, "width": 90, "height": 45}])
+    );
+}
+
+#[tokio::test]
+async fn exif_is_stripped_and_orientation_applied() {
+    let app = spawn_app().await;
+
+    // rotated by 90 degrees
+    let data = jpeg_with_exif(40, 20, 6);
+    let res = app.upload_media(&app.test_user.token, data).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["content_type"], "image/jpeg");
+    assert_eq!(json["width"], 20);
+    assert_eq!(json["height"], 40);
+
+    let res = app
+        .download_media(&app.test_user.token, json["media_id"].as_i64().unwrap())
+        .await;
+    let stored = res.bytes().await.unwrap().to_vec();
+
+    assert!(!stored.windows(4).any(|w| w == b"Exif"));
+    assert!(!stored.windows(3).any(|w| w == b"GPS"));
+    let image = image::load_from_memory(&stored).unwrap();
+    assert_eq!((image.width(), image.height()), (20, 40));
+}
+
+#[tokio::test]
+async fn media_info_of_others_is_not_accessible() {
+    let app = spawn_app().await;
+
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, png_image(200, 200))
+        .await;
+
+    let other = app.create_test_user().await;
+
+    let res = app.get_media_info(&other.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.download_thumbnail(&other.token, media_id, 90).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn images_which_cannot_be_stripped_are_rejected() {
+    let app = spawn_app().await;
+
+    // TIFF is not decoded, so its metadata could not be removed
+    let mut tiff = b"II\x2a\0\x08\0\0\0".to_vec();
+    tiff.extend_from_slice(b"GPS 48.8584N 2.2945E");
+    let res = app.upload_media(&app.test_user.token, tiff).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    // the EXIF block survives, but the image data is cut off
+    let mut jpeg = jpeg_with_exif(40, 20, 1);
+    jpeg.truncate(jpeg.len() / 2);
+    let res = app.upload_media(&app.test_user.token, jpeg).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let usage = app.get_storage_usage(&app.test_user.token).await;
+    assert_eq!(usage["used"], 0);
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::helpers::{TestApp, fake_png, spawn_app};
+
+#[tokio::test]
+async fn expired_message_is_deleted_with_event() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let kept = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "kept")
+        .await;
+
+    let mut updates = app.connect_updates(&peer.token).await;
+
+    let res = app
+        .send_expiring_message(&app.test_user.token, chat_id, &[], 1)
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let message_id = json["message_id"].as_i64().unwrap();
+
+    let event = TestApp::next_event(&mut updates, "messages_deleted").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["message_ids"], serde_json::json!([message_id]));
+
+    let messages = app.get_chat_messages(&peer.token, chat_id).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["message_id"].as_i64().unwrap(), kept);
+    assert!(messages[0]["expires_at"].is_null());
+}
+
+#[tokio::test]
+async fn failure_send_message_with_bad_ttl() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    for ttl in [0, -1, 30 * 24 * 60 * 60 + 1] {
+        let res = app
+            .send_expiring_message(&app.test_user.token, chat_id, &[], ttl)
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn chat_auto_delete_sets_expiry_of_new_messages() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let before = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "before")
+        .await;
+
+    let res = app
+        .set_auto_delete(&app.test_user.token, chat_id, Some("day"))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_auto_delete(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["period"], "day");
+
+    let after = app
+        .send_chat_message_returns_id(&member.token, chat_id, "after")
+        .await;
+    // a shorter TTL of the message wins over the period of the chat
+    let res = app
+        .send_expiring_message(&member.token, chat_id, &[], 60)
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let messages = app.get_chat_messages(&member.token, chat_id).await;
+    // the oldest one records adding the member
+    assert_eq!(messages.len(), 4);
+
+    let expires_at = messages[0]["expires_at"].as_i64().unwrap();
+    assert!((now + 55..=now + 65).contains(&expires_at));
+    assert_eq!(messages[1]["message_id"].as_i64().unwrap(), after);
+    let expires_at = messages[1]["expires_at"].as_i64().unwrap();
+    assert!((now + 86395..=now + 86405).contains(&expires_at));
+    assert_eq!(messages[2]["message_id"].as_i64().unwrap(), before);
+    assert!(messages[2]["expires_at"].is_null());
+
+    let res = app
+        .set_auto_delete(&app.test_user.token, chat_id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.get_auto_delete(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert!(json["period"].is_null());
+}
+
+#[tokio::test]
+async fn only_admins_can_set_auto_delete_in_group() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let stranger = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let res = app
+        .set_auto_delete(&member.token, chat_id, Some("week"))
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .set_auto_delete(&stranger.token, chat_id, Some("week"))
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.get_auto_delete(&stranger.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .set_auto_delete(&app.test_user.token, chat_id, Some("fortnight"))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn both_sides_can_set_auto_delete_in_private_chat() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let res = app
+        .set_auto_delete(&peer.token, chat_id, Some("month"))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.block_user(&app.test_user.token, &peer.username).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.set_auto_delete(&peer.token, chat_id, None).await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn expired_message_purges_media_only_it_references() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let orphan = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+    let shared = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+    let res = app
+        .send_chat_message_with_media(&app.test_user.token, chat_id, "", &[shared])
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let mut updates = app.connect_updates(&peer.token).await;
+
+    let res = app
+        .send_expiring_message(&app.test_user.token, chat_id, &[orphan, shared], 2)
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // the expiring message is the last reference of the orphan
+    for media_id in [orphan, shared] {
+        let res = app.delete_media(&app.test_user.token, media_id).await;
+        assert_eq!(res.status().as_u16(), 204);
+    }
+
+    TestApp::next_event(&mut updates, "messages_deleted").await;
+
+    // the media are purged right after the event is pushed
+    let remaining = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let remaining = sqlx::query_scalar!("SELECT id FROM media ORDER BY id")
+                .fetch_all(&app.db)
+                .await
+                .unwrap();
+            if remaining.len() == 1 {
+                return remaining;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("Timed out waiting for the media purge");
+    assert_eq!(remaining, vec![shared]);
+}
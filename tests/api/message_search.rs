@@ -0,0 +1,175 @@
+use crate::helpers::{TestApp, fake_png, spawn_app};
+
+async fn found_message_ids(res: reqwest::Response) -> Vec<i64> {
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["message_id"].as_i64().unwrap())
+        .collect()
+}
+
+async fn set_created_at(app: &TestApp, message_id: i64, timestamp: i64) {
+    sqlx::query!(
+        "UPDATE messages SET created_at = to_timestamp($2) WHERE id = $1",
+        message_id,
+        timestamp as f64,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn search_in_chat_with_highlighted_snippet() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let first = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "the cat <sleeps> here")
+        .await;
+    app.send_chat_message_returns_id(&peer.token, chat_id, "a dog barks")
+        .await;
+    let third = app
+        .send_chat_message_returns_id(&peer.token, chat_id, "Cat again")
+        .await;
+
+    let res = app
+        .search_chat_messages(&app.test_user.token, chat_id, &[("q", "cat")])
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["message_id"].as_i64().unwrap(), third);
+    assert_eq!(messages[0]["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(messages[1]["message_id"].as_i64().unwrap(), first);
+    assert_eq!(messages[1]["snippet"], "the <b>cat</b> &lt;sleeps&gt; here");
+}
+
+#[tokio::test]
+async fn global_search_covers_only_own_chats() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let group_id = app.create_group_returns_id(&app.test_user.token).await;
+    let in_pm = app
+        .send_chat_message_returns_id(&peer.token, pm_id, "meeting tomorrow")
+        .await;
+    let in_group = app
+        .send_chat_message_returns_id(&app.test_user.token, group_id, "the meeting is moved")
+        .await;
+
+    let res = app
+        .search_all_messages(&app.test_user.token, &[("q", "meeting")])
+        .await;
+    assert_eq!(found_message_ids(res).await, vec![in_group, in_pm]);
+
+    // the peer is not in the group
+    let res = app
+        .search_all_messages(&peer.token, &[("q", "meeting")])
+        .await;
+    assert_eq!(found_message_ids(res).await, vec![in_pm]);
+}
+
+#[tokio::test]
+async fn search_filters() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+
+    let res = app
+        .send_chat_message_with_media(&app.test_user.token, chat_id, "report photo", &[media_id])
+        .await;
+    let with_image = res.json::<serde_json::Value>().await.unwrap()["message_id"]
+        .as_i64()
+        .unwrap();
+    let old = app
+        .send_chat_message_returns_id(&peer.token, chat_id, "old report")
+        .await;
+    let new = app
+        .send_chat_message_returns_id(&peer.token, chat_id, "new report")
+        .await;
+    set_created_at(&app, with_image, 1_700_000_500).await;
+    set_created_at(&app, old, 1_600_000_000).await;
+    set_created_at(&app, new, 1_700_000_000).await;
+
+    let peer_id = peer.id.to_string();
+    let res = app
+        .search_chat_messages(
+            &app.test_user.token,
+            chat_id,
+            &[("q", "report"), ("sender_id", &peer_id)],
+        )
+        .await;
+    assert_eq!(found_message_ids(res).await, vec![new, old]);
+
+    let res = app
+        .search_chat_messages(
+            &app.test_user.token,
+            chat_id,
+            &[
+                ("q", "report"),
+                ("from", "1650000000"),
+                ("to", "1700000001"),
+            ],
+        )
+        .await;
+    assert_eq!(found_message_ids(res).await, vec![new]);
+
+    let res = app
+        .search_chat_messages(
+            &app.test_user.token,
+            chat_id,
+            &[("q", "report"), ("media_type", "image")],
+        )
+        .await;
+    assert_eq!(found_message_ids(res).await, vec![with_image]);
+
+    let res = app
+        .search_chat_messages(
+            &app.test_user.token,
+            chat_id,
+            &[("q", "report"), ("media_type", "video")],
+        )
+        .await;
+    assert!(found_message_ids(res).await.is_empty());
+}
+
+#[tokio::test]
+async fn failure_search_with_empty_query() {
+    let app = spawn_app().await;
+
+    let res = app
+        .search_all_messages(&app.test_user.token, &[("q", "  ")])
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_search_chat_when_not_participant() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let stranger = app.create_test_user().await;
+
+    let res = app
+        .search_chat_messages(&stranger.token, chat_id, &[("q", "hello")])
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+}
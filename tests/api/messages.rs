@@ -40,3 +40,126 @@ async fn failure_when_no_permission() {
         .await;
     assert_eq!(res.status().as_u16(), 403);
 }
+
+#[tokio::test]
+async fn history_is_paginated_newest_first() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let mut message_ids = Vec::new();
+    for i in 0..5 {
+        let message_id = app
+            .send_chat_message_returns_id(&app.test_user.token, chat_id, &format!("message {i}"))
+            .await;
+        message_ids.push(message_id);
+    }
+
+    let res = app
+        .get_chat_history(&peer.token, chat_id, &[("limit", 2)])
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["message_id"].as_i64().unwrap(), message_ids[4]);
+    assert_eq!(messages[0]["content"], "message 4");
+    assert_eq!(messages[0]["sender_id"].as_i64().unwrap(), app.test_user.id);
+    assert_eq!(messages[0]["kind"], "text");
+
+    let res = app
+        .get_chat_history(&peer.token, chat_id, &[("before_id", message_ids[1])])
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["message_id"].as_i64().unwrap(), message_ids[0]);
+}
+
+#[tokio::test]
+async fn failure_read_history_when_not_participant() {
+    let app = spawn_app().await;
+
+    let user1 = app.create_test_user().await;
+    let user2 = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&user1.token, &user2.username)
+        .await;
+
+    let res = app
+        .get_chat_history(&app.test_user.token, chat_id, &[])
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn sender_and_admins_delete_messages() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let other = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    for user in [&member, &other] {
+        app.add_member(&app.test_user.token, chat_id, &user.username)
+            .await;
+    }
+    let first_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "first")
+        .await;
+    let second_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "second")
+        .await;
+
+    // the other members and admins without the right cannot delete it
+    let res = app.delete_message(&other.token, first_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    app.promote_member(
+        &app.test_user.token,
+        chat_id,
+        other.id,
+        &["pin_messages"],
+        None,
+    )
+    .await;
+    let res = app.delete_message(&other.token, first_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let stranger = app.create_test_user().await;
+    let res = app.delete_message(&stranger.token, first_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.delete_message(&member.token, first_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+    let res = app.delete_message(&app.test_user.token, second_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+    let res = app.delete_message(&app.test_user.token, second_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let messages = app.get_chat_messages(&member.token, chat_id).await;
+    assert!(
+        messages.iter().all(
+            |message| ![first_id, second_id].contains(&message["message_id"].as_i64().unwrap())
+        )
+    );
+}
+
+#[tokio::test]
+async fn failure_delete_message_of_peer() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hi")
+        .await;
+
+    let res = app.delete_message(&peer.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.delete_message(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+}
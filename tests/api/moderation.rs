@@ -0,0 +1,401 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, fake_png, spawn_app};
+
+/// Group of the test user with one more member and an invite link
+async fn create_group_with_member(app: &TestApp) -> (i64, TestUser, String) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let (_, token) = app
+        .create_invite_link_returns_token(&app.test_user.token, chat_id, json!({}))
+        .await;
+
+    (chat_id, member, token)
+}
+
+fn in_an_hour() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp() + 3600
+}
+
+#[tokio::test]
+async fn kicked_member_can_rejoin() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, token) = create_group_with_member(&app).await;
+    let mut updates = app.connect_updates(&member.token).await;
+
+    let res = app
+        .kick_member(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let event = TestApp::next_event(&mut updates, "removed_from_chat").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["banned"], false);
+
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // no longer a member
+    let res = app
+        .kick_member(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn banned_user_cannot_rejoin_until_unbanned() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, token) = create_group_with_member(&app).await;
+    let (_, approval_token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "requires_approval": true }),
+        )
+        .await;
+    let mut updates = app.connect_updates(&member.token).await;
+
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, member.id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let event = TestApp::next_event(&mut updates, "removed_from_chat").await;
+    assert_eq!(event["banned"], true);
+
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // by any link or by an admin
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.join_chat(&member.token, &approval_token).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.list_bans(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["bans"][0]["user_id"].as_i64().unwrap(), member.id);
+    assert_eq!(
+        json["bans"][0]["banned_by"].as_i64().unwrap(),
+        app.test_user.id
+    );
+    assert!(json["bans"][0]["until"].is_null());
+
+    let res = app
+        .unban_member(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .unban_member(&app.test_user.token, chat_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn ban_expires() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, token) = create_group_with_member(&app).await;
+
+    // the until time must be within the next year
+    for until in [1, in_an_hour() + 400 * 24 * 3600, i64::MAX] {
+        let res = app
+            .ban_member(&app.test_user.token, chat_id, member.id, Some(until))
+            .await;
+        assert_eq!(res.status().as_u16(), 400, "{until}");
+        let res = app
+            .restrict_member(
+                &app.test_user.token,
+                chat_id,
+                member.id,
+                &["send_media"],
+                Some(until),
+            )
+            .await;
+        assert_eq!(res.status().as_u16(), 400, "{until}");
+    }
+
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, member.id, Some(in_an_hour()))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    sqlx::query!(
+        "UPDATE chat_bans SET until = now() WHERE chat_id = $1",
+        chat_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let res = app.join_chat(&member.token, &token).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_bans(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["bans"], json!([]));
+}
+
+#[tokio::test]
+async fn ban_user_before_joining() {
+    let app = spawn_app().await;
+
+    let applicant = app.create_test_user().await;
+    let stranger = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let (_, token) = app
+        .create_invite_link_returns_token(
+            &app.test_user.token,
+            chat_id,
+            json!({ "requires_approval": true }),
+        )
+        .await;
+
+    let res = app.join_chat(&applicant.token, &token).await;
+    assert_eq!(res.status().as_u16(), 202);
+
+    // the pending request is dropped
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, applicant.id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.list_join_requests(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["requests"], json!([]));
+
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, stranger.id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.join_chat(&stranger.token, &token).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, i64::MAX, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn moderation_outlives_deleted_admin() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, token) = create_group_with_member(&app).await;
+    let admin = app.create_test_user().await;
+    let stranger = app.create_test_user().await;
+    app.add_member(&app.test_user.token, chat_id, &admin.username)
+        .await;
+    app.promote_member(
+        &app.test_user.token,
+        chat_id,
+        admin.id,
+        &["ban_users"],
+        None,
+    )
+    .await;
+
+    let res = app
+        .ban_member(&admin.token, chat_id, stranger.id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .restrict_member(&admin.token, chat_id, member.id, &["send_messages"], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    // the admin log keeps its actors, so it is cleared first
+    sqlx::query!("DELETE FROM chat_admin_log WHERE actor_id = $1", admin.id)
+        .execute(&app.db)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = $1", admin.id)
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let res = app.list_bans(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["bans"][0]["user_id"].as_i64().unwrap(), stranger.id);
+    assert!(json["bans"][0]["banned_by"].is_null());
+    let res = app.join_chat(&stranger.token, &token).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json["restrictions"][0]["user_id"].as_i64().unwrap(),
+        member.id
+    );
+    assert!(json["restrictions"][0]["restricted_by"].is_null());
+    let res = app.send_chat_message(&member.token, chat_id, "hi").await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn restricted_member_cannot_send() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, _) = create_group_with_member(&app).await;
+    let media_id = app.upload_media_returns_id(&member.token, fake_png()).await;
+
+    let res = app
+        .restrict_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["send_media"],
+            None,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .send_chat_message_with_media(&member.token, chat_id, "photo", &[media_id])
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.send_chat_message(&member.token, chat_id, "text").await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // replaces the previous restriction
+    let res = app
+        .restrict_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["send_messages"],
+            None,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.send_chat_message(&member.token, chat_id, "text").await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.send_chat_action(&member.token, chat_id, "typing").await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let restriction = &json["restrictions"][0];
+    assert_eq!(restriction["user_id"].as_i64().unwrap(), member.id);
+    assert_eq!(restriction["permissions"], json!(["send_messages"]));
+
+    // an empty list lifts the restriction
+    let res = app
+        .restrict_member(&app.test_user.token, chat_id, member.id, &[], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .send_chat_message_with_media(&member.token, chat_id, "photo", &[media_id])
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn timed_restriction_expires() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, _) = create_group_with_member(&app).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hi")
+        .await;
+
+    let res = app
+        .restrict_member(
+            &app.test_user.token,
+            chat_id,
+            member.id,
+            &["add_reactions", "send_polls"],
+            Some(in_an_hour()),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.add_reaction(&member.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .send_poll(
+            &member.token,
+            chat_id,
+            "Question?",
+            json!({ "options": ["A", "B"] }),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    sqlx::query!(
+        "UPDATE chat_restrictions SET until = now() WHERE chat_id = $1",
+        chat_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let res = app.add_reaction(&member.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.list_restrictions(&app.test_user.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["restrictions"], json!([]));
+}
+
+#[tokio::test]
+async fn only_admins_moderate_members() {
+    let app = spawn_app().await;
+
+    let (chat_id, member, _) = create_group_with_member(&app).await;
+    let other = app.create_test_user().await;
+    app.add_member(&app.test_user.token, chat_id, &other.username)
+        .await;
+
+    let res = app.kick_member(&member.token, chat_id, other.id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.ban_member(&member.token, chat_id, other.id, None).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .restrict_member(&member.token, chat_id, other.id, &["send_messages"], None)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.list_bans(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.list_restrictions(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // admins are out of reach
+    let res = app
+        .kick_member(&app.test_user.token, chat_id, app.test_user.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .ban_member(&app.test_user.token, chat_id, app.test_user.id, None)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // no moderation of private chats
+    let pm_id = app
+        .create_pm_returns_id(&app.test_user.token, &member.username)
+        .await;
+    let res = app
+        .kick_member(&app.test_user.token, pm_id, member.id)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
@@ -0,0 +1,181 @@
+use nyat::media::{LoudnessDecoder, SILENCE_DB, process_voice};
+
+const HYBRID_RECORDING: &[u8] = include_bytes!("fixtures/voice_hybrid_cbr.opus");
+const CELT_RECORDING: &[u8] = include_bytes!("fixtures/voice_celt_cbr.opus");
+
+/// TOC byte of a mono CELT packet with 20 ms frames
+const CELT_20MS: u8 = 31 << 3;
+
+/// Packets of the first logical stream, without the headers
+fn audio_packets(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet_open = false;
+    while !data.is_empty() {
+        let segments = data[26] as usize;
+        let lacing = &data[27..27 + segments];
+        let mut body = &data[27 + segments..];
+        for &lacing in lacing {
+            let (segment, remaining) = body.split_at(lacing as usize);
+            body = remaining;
+            if packet_open {
+                packets.last_mut().unwrap().extend_from_slice(segment);
+            } else {
+                packets.push(segment.to_vec());
+            }
+            packet_open = lacing == 255;
+        }
+        data = body;
+    }
+    packets.split_off(2)
+}
+
+/// Deterministic xorshift, so a failure can be reproduced
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+}
+
+#[test]
+fn empty_frames_are_silence() {
+    let mut decoder = LoudnessDecoder::default();
+
+    // one frame, two frames of the same length and two coded lengths
+    for packet in [
+        vec![CELT_20MS],
+        vec![CELT_20MS | 1, 0, 0],
+        vec![CELT_20MS | 2, 1, 0, 0],
+        vec![8 << 3, 0],
+    ] {
+        assert_eq!(decoder.packet_loudness(&packet), Some(SILENCE_DB));
+    }
+}
+
+#[test]
+fn celt_silence_flag_is_silence() {
+    let mut decoder = LoudnessDecoder::default();
+
+    // the first symbol decodes to the silence flag when the range coder starts at zero
+    assert_eq!(
+        decoder.packet_loudness(&[CELT_20MS, 0xff, 0xff]),
+        Some(SILENCE_DB)
+    );
+    assert!(decoder.packet_loudness(&[CELT_20MS, 0x00, 0x00]).unwrap() > SILENCE_DB);
+}
+
+#[test]
+fn frame_lengths_are_read_from_two_bytes() {
+    let mut decoder = LoudnessDecoder::default();
+
+    // 252 + 4 * 1 bytes for the first frame, nothing for the second one
+    let mut packet = vec![CELT_20MS | 2, 252, 1];
+    packet.extend(std::iter::repeat_n(0xff, 256));
+    assert_eq!(decoder.packet_loudness(&packet), Some(SILENCE_DB));
+
+    packet.pop();
+    assert_eq!(decoder.packet_loudness(&packet), None);
+}
+
+#[test]
+fn malformed_packets_are_not_measured() {
+    let mut decoder = LoudnessDecoder::default();
+
+    for packet in [
+        // no TOC byte
+        vec![],
+        // two frames of the same length from an odd length
+        vec![CELT_20MS | 1, 0xff, 0xff, 0xff],
+        // the first frame is longer than the packet
+        vec![CELT_20MS | 2, 5, 0xff],
+        // no frame count
+        vec![CELT_20MS | 3],
+        // zero frames
+        vec![CELT_20MS | 3, 0x00, 0xff],
+        // the padding is longer than the packet
+        vec![CELT_20MS | 3, 0x41, 10, 0xff],
+        // the padding length is cut off
+        vec![CELT_20MS | 3, 0x41, 255],
+        // three frames of the same length from four bytes
+        vec![CELT_20MS | 3, 0x03, 0xff, 0xff, 0xff, 0xff],
+        // the coded lengths are longer than the packet
+        vec![CELT_20MS | 3, 0x83, 2, 2, 0xff],
+    ] {
+        assert_eq!(decoder.packet_loudness(&packet), None, "{packet:?}");
+    }
+}
+
+#[test]
+fn recordings_are_measured_packet_by_packet() {
+    for recording in [HYBRID_RECORDING, CELT_RECORDING] {
+        let packets = audio_packets(recording);
+        // 20 ms packets of the 4 seconds and the pre-skip
+        assert_eq!(packets.len(), 201);
+
+        let mut decoder = LoudnessDecoder::default();
+        for packet in &packets {
+            let level = decoder.packet_loudness(packet).unwrap();
+            assert!((SILENCE_DB..=120.0).contains(&level), "{level}");
+        }
+
+        let voice = process_voice(recording).unwrap();
+        assert_eq!(voice.duration_ms, 4000);
+    }
+}
+
+#[test]
+fn truncated_packets_do_not_panic() {
+    for recording in [HYBRID_RECORDING, CELT_RECORDING] {
+        let mut decoder = LoudnessDecoder::default();
+        for packet in audio_packets(recording) {
+            for length in 0..packet.len() {
+                decoder.packet_loudness(&packet[..length]);
+            }
+        }
+    }
+}
+
+#[test]
+fn truncated_recordings_do_not_panic() {
+    for recording in [HYBRID_RECORDING, CELT_RECORDING] {
+        for length in 0..recording.len() {
+            // only the recordings cut at a page boundary are still well-formed
+            if let Some(voice) = process_voice(&recording[..length]) {
+                assert!(voice.duration_ms < 4000);
+            }
+        }
+    }
+}
+
+#[test]
+fn corrupted_recordings_do_not_panic() {
+    let mut random = Random(0x9e3779b97f4a7c15);
+    for recording in [HYBRID_RECORDING, CELT_RECORDING] {
+        for position in 0..recording.len() {
+            let mut data = recording.to_vec();
+            data[position] ^= (random.next() as u8) | 1;
+            process_voice(&data);
+        }
+    }
+}
+
+#[test]
+fn random_packets_do_not_panic() {
+    let mut random = Random(0x2545f4914f6cdd1d);
+    let mut decoder = LoudnessDecoder::default();
+    for toc in 0..=u8::MAX {
+        for length in 0..300 {
+            let mut packet = random.bytes(length);
+            packet.insert(0, toc);
+            decoder.packet_loudness(&packet);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::helpers::{TestApp, spawn_app};
+
+async fn pinned_message_ids(app: &TestApp, token: &str, chat_id: i64) -> Vec<i64> {
+    let res = app.list_pinned_messages(token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["message_id"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn both_sides_can_pin_in_private_chat() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let first = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "first")
+        .await;
+    let second = app
+        .send_chat_message_returns_id(&peer.token, chat_id, "second")
+        .await;
+
+    let res = app.pin_message(&peer.token, first).await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.pin_message(&app.test_user.token, second).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // the latest pin comes first
+    assert_eq!(
+        pinned_message_ids(&app, &peer.token, chat_id).await,
+        vec![second, first]
+    );
+
+    let res = app.list_pinned_messages(&peer.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["messages"][0]["content"], "second");
+    assert_eq!(
+        json["messages"][0]["pinned_by"].as_i64().unwrap(),
+        app.test_user.id
+    );
+
+    let res = app.unpin_message(&peer.token, second).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        pinned_message_ids(&app, &peer.token, chat_id).await,
+        vec![first]
+    );
+
+    let res = app.unpin_message(&peer.token, second).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn pin_records_service_message() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    app.pin_message(&peer.token, message_id).await;
+
+    let messages = app.get_chat_messages(&app.test_user.token, chat_id).await;
+    assert_eq!(messages[0]["kind"], "service");
+    assert_eq!(messages[0]["sender_id"].as_i64().unwrap(), peer.id);
+    assert_eq!(
+        messages[0]["service"],
+        serde_json::json!({"type": "pin_message", "message_id": message_id})
+    );
+    assert!(messages[1]["service"].is_null());
+}
+
+#[tokio::test]
+async fn failure_pin_twice() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    let res = app.pin_message(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 201);
+    let res = app.pin_message(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_admins_can_pin_in_groups() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "hello")
+        .await;
+
+    let res = app.pin_message(&member.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.pin_message(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.unpin_message(&member.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    // members can still see the pinned messages
+    assert_eq!(
+        pinned_message_ids(&app, &member.token, chat_id).await,
+        vec![message_id]
+    );
+}
+
+#[tokio::test]
+async fn failure_list_pinned_when_not_participant() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let stranger = app.create_test_user().await;
+
+    let res = app.list_pinned_messages(&stranger.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+    let res = app.pin_message(&stranger.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
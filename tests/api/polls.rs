@@ -0,0 +1,267 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser, spawn_app};
+
+/// Group of the test user with two more members
+async fn create_group_with_members(app: &TestApp) -> (i64, TestUser, TestUser) {
+    let first = app.create_test_user().await;
+    let second = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &first.username)
+        .await;
+    app.add_member(&app.test_user.token, chat_id, &second.username)
+        .await;
+
+    (chat_id, first, second)
+}
+
+fn votes(poll: &serde_json::Value) -> Vec<i64> {
+    poll["options"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|option| option["votes"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn vote_in_single_choice_poll() {
+    let app = spawn_app().await;
+
+    let (chat_id, first, second) = create_group_with_members(&app).await;
+    let message_id = app
+        .send_poll_returns_id(
+            &app.test_user.token,
+            chat_id,
+            json!({ "options": ["Yes", "No", "Maybe"] }),
+        )
+        .await;
+
+    let mut updates = app.connect_updates(&second.token).await;
+
+    let res = app.vote_poll(&first.token, message_id, &[1]).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(votes(&json["poll"]), vec![0, 1, 0]);
+    assert_eq!(json["poll"]["options"][1]["chosen"], true);
+    // voters of anonymous polls are hidden
+    assert!(json["poll"]["options"][1]["voters"].is_null());
+
+    let event = TestApp::next_event(&mut updates, "poll_update").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["message_id"].as_i64().unwrap(), message_id);
+    assert_eq!(event["votes"], json!([0, 1, 0]));
+    assert_eq!(event["total_voters"].as_i64().unwrap(), 1);
+
+    // one option only
+    let res = app.vote_poll(&second.token, message_id, &[0, 1]).await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.vote_poll(&second.token, message_id, &[3]).await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.vote_poll(&second.token, message_id, &[]).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.vote_poll(&second.token, message_id, &[1]).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let messages = app.get_chat_messages(&app.test_user.token, chat_id).await;
+    let poll = &messages[0]["poll"];
+    assert_eq!(messages[0]["kind"], "poll");
+    assert_eq!(messages[0]["content"], "Question?");
+    assert_eq!(votes(poll), vec![0, 2, 0]);
+    assert_eq!(poll["total_voters"].as_i64().unwrap(), 2);
+    assert_eq!(poll["options"][1]["chosen"], false);
+}
+
+#[tokio::test]
+async fn retract_and_vote_again() {
+    let app = spawn_app().await;
+
+    let (chat_id, first, _) = create_group_with_members(&app).await;
+    let message_id = app
+        .send_poll_returns_id(
+            &app.test_user.token,
+            chat_id,
+            json!({ "options": ["A", "B"] }),
+        )
+        .await;
+
+    let res = app.retract_vote(&first.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.vote_poll(&first.token, message_id, &[0]).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    // the vote must be retracted to change it
+    let res = app.vote_poll(&first.token, message_id, &[1]).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.retract_vote(&first.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(votes(&json["poll"]), vec![0, 0]);
+
+    let res = app.vote_poll(&first.token, message_id, &[1]).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(votes(&json["poll"]), vec![0, 1]);
+}
+
+#[tokio::test]
+async fn public_multiple_choice_poll_shows_voters() {
+    let app = spawn_app().await;
+
+    let (chat_id, first, second) = create_group_with_members(&app).await;
+    let message_id = app
+        .send_poll_returns_id(
+            &app.test_user.token,
+            chat_id,
+            json!({
+                "options": ["A", "B", "C"],
+                "multiple_choice": true,
+                "anonymous": false,
+            }),
+        )
+        .await;
+
+    let res = app.vote_poll(&first.token, message_id, &[0, 2, 2]).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.vote_poll(&second.token, message_id, &[2]).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+
+    let poll = &json["poll"];
+    assert_eq!(votes(poll), vec![1, 0, 2]);
+    assert_eq!(poll["total_voters"].as_i64().unwrap(), 2);
+    assert_eq!(poll["options"][0]["voters"], json!([first.id]));
+    assert_eq!(poll["options"][1]["voters"], json!([]));
+    assert_eq!(poll["options"][2]["voters"], json!([first.id, second.id]));
+}
+
+#[tokio::test]
+async fn quiz_reveals_answer_after_voting() {
+    let app = spawn_app().await;
+
+    let (chat_id, first, _) = create_group_with_members(&app).await;
+    let message_id = app
+        .send_poll_returns_id(
+            &app.test_user.token,
+            chat_id,
+            json!({
+                "options": ["3", "4"],
+                "quiz": { "correct_option": 1, "explanation": "2 + 2 = 4" },
+            }),
+        )
+        .await;
+
+    let messages = app.get_chat_messages(&first.token, chat_id).await;
+    let poll = &messages[0]["poll"];
+    assert_eq!(poll["quiz"], true);
+    assert!(poll["correct_option"].is_null());
+    assert!(poll["explanation"].is_null());
+
+    let res = app.vote_poll(&first.token, message_id, &[0]).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["poll"]["correct_option"].as_i64().unwrap(), 1);
+    assert_eq!(json["poll"]["explanation"], "2 + 2 = 4");
+
+    // quiz answers are final
+    let res = app.retract_vote(&first.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_creator_can_close_poll() {
+    let app = spawn_app().await;
+
+    let (chat_id, first, second) = create_group_with_members(&app).await;
+    let message_id = app
+        .send_poll_returns_id(&first.token, chat_id, json!({ "options": ["A", "B"] }))
+        .await;
+
+    // even the owner of the group cannot close it
+    let res = app.close_poll(&app.test_user.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let mut updates = app.connect_updates(&second.token).await;
+
+    let res = app.close_poll(&first.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["poll"]["closed"], true);
+
+    let event = TestApp::next_event(&mut updates, "poll_update").await;
+    assert_eq!(event["closed"], true);
+
+    let res = app.vote_poll(&second.token, message_id, &[0]).await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.close_poll(&first.token, message_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_vote_in_poll_of_other_chat() {
+    let app = spawn_app().await;
+
+    let (chat_id, _, _) = create_group_with_members(&app).await;
+    let stranger = app.create_test_user().await;
+    let message_id = app
+        .send_poll_returns_id(
+            &app.test_user.token,
+            chat_id,
+            json!({ "options": ["A", "B"] }),
+        )
+        .await;
+
+    let res = app.vote_poll(&stranger.token, message_id, &[0]).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    // not a poll
+    let text_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "text")
+        .await;
+    let res = app.vote_poll(&app.test_user.token, text_id, &[0]).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn failure_send_invalid_poll() {
+    let app = spawn_app().await;
+
+    let (chat_id, _, _) = create_group_with_members(&app).await;
+
+    let invalid = [
+        json!({ "options": ["Only"] }),
+        json!({ "options": ["A", " "] }),
+        json!({ "options": vec!["A"; 11] }),
+        json!({ "options": ["A", "x".repeat(101)] }),
+        json!({ "options": ["A", "B"], "quiz": { "correct_option": 2 } }),
+        json!({
+            "options": ["A", "B"],
+            "multiple_choice": true,
+            "quiz": { "correct_option": 0 },
+        }),
+        json!({
+            "options": ["A", "B"],
+            "quiz": { "correct_option": 0, "explanation": "x".repeat(201) },
+        }),
+    ];
+    for poll in invalid {
+        let res = app
+            .send_poll(&app.test_user.token, chat_id, "Question?", poll)
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+
+    // the question is required
+    let res = app
+        .send_poll(
+            &app.test_user.token,
+            chat_id,
+            "",
+            json!({ "options": ["A", "B"] }),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
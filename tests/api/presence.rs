@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::helpers::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn active_user_is_online() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    // any authenticated request counts as activity
+    app.list_contacts(&user.token).await;
+
+    let res = app.get_presence(&app.test_user.token, user.id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["status"], "online");
+    assert!(json["last_seen_at"].is_i64());
+}
+
+#[tokio::test]
+async fn idle_user_goes_offline_with_last_seen() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    app.list_contacts(&user.token).await;
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let res = app.get_presence(&app.test_user.token, user.id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["status"], "offline");
+
+    // the last seen time is persisted
+    let last_seen_at = sqlx::query_scalar!("SELECT last_seen_at FROM users WHERE id = $1", user.id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        json["last_seen_at"].as_i64().unwrap(),
+        last_seen_at.unix_timestamp()
+    );
+    // rounded down to a minute
+    assert_eq!(last_seen_at.unix_timestamp() % 60, 0);
+}
+
+#[tokio::test]
+async fn connected_user_stays_online() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let _stream = app.connect_updates(&user.token).await;
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let res = app.get_presence(&app.test_user.token, user.id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["status"], "online");
+}
+
+#[tokio::test]
+async fn presence_hidden_by_privacy_settings() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    app.set_privacy(
+        &user.token,
+        json!({ "key": "last_seen", "visibility": "nobody" }),
+    )
+    .await;
+
+    let res = app.get_presence(&app.test_user.token, user.id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["status"], "hidden");
+    assert!(json["last_seen_at"].is_null());
+}
+
+#[tokio::test]
+async fn contacts_receive_presence_changes() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    app.add_contact(&app.test_user.token, &user.username).await;
+
+    let mut stream = app.connect_updates(&app.test_user.token).await;
+
+    // the contact comes online
+    let user_stream = app.connect_updates(&user.token).await;
+    let event = TestApp::next_event(&mut stream, "presence").await;
+    assert_eq!(event["user_id"].as_i64().unwrap(), user.id);
+    assert_eq!(event["online"], true);
+
+    // and goes offline after leaving
+    drop(user_stream);
+    let event = TestApp::next_event(&mut stream, "presence").await;
+    assert_eq!(event["user_id"].as_i64().unwrap(), user.id);
+    assert_eq!(event["online"], false);
+}
@@ -0,0 +1,162 @@
+use serde_json::json;
+
+use crate::helpers::{TestApp, fake_png, spawn_app};
+
+/// Profile photo of the user found by the username, as seen by the viewer
+async fn photo_seen_by(app: &TestApp, token: &str, username: &str) -> Option<i64> {
+    let res = app.search_users(token, username).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["users"][0]["photo_media_id"].as_i64()
+}
+
+#[tokio::test]
+async fn rules_default_to_everybody() {
+    let app = spawn_app().await;
+
+    let res = app.list_privacy(&app.test_user.token).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let rules = json["rules"].as_array().unwrap();
+    assert_eq!(rules.len(), 4);
+    assert!(rules.iter().all(|rule| rule["visibility"] == "everybody"));
+}
+
+#[tokio::test]
+async fn success_set_rule_with_exceptions() {
+    let app = spawn_app().await;
+
+    let friend = app.create_test_user().await;
+
+    let res = app
+        .set_privacy(
+            &app.test_user.token,
+            json!({
+                "key": "last_seen",
+                "visibility": "nobody",
+                "allow": [friend.username],
+            }),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.list_privacy(&app.test_user.token).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let rule = json["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|rule| rule["key"] == "last_seen")
+        .unwrap();
+    assert_eq!(rule["visibility"], "nobody");
+    assert_eq!(rule["allow"][0]["id"].as_i64().unwrap(), friend.id);
+}
+
+#[tokio::test]
+async fn failure_when_user_both_allowed_and_denied() {
+    let app = spawn_app().await;
+
+    let friend = app.create_test_user().await;
+
+    let res = app
+        .set_privacy(
+            &app.test_user.token,
+            json!({
+                "key": "private_chats",
+                "visibility": "contacts",
+                "allow": [friend.username],
+                "deny": [friend.username],
+            }),
+        )
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn private_chats_restricted_to_contacts() {
+    let app = spawn_app().await;
+
+    let stranger = app.create_test_user().await;
+    let friend = app.create_test_user().await;
+    app.add_contact(&app.test_user.token, &friend.username)
+        .await;
+
+    app.set_privacy(
+        &app.test_user.token,
+        json!({ "key": "private_chats", "visibility": "contacts" }),
+    )
+    .await;
+
+    let res = app
+        .create_pm(&stranger.token, &app.test_user.username)
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app.create_pm(&friend.token, &app.test_user.username).await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn deny_exception_overrides_everybody() {
+    let app = spawn_app().await;
+
+    let stranger = app.create_test_user().await;
+
+    app.set_privacy(
+        &app.test_user.token,
+        json!({
+            "key": "group_invites",
+            "visibility": "everybody",
+            "deny": [stranger.username],
+        }),
+    )
+    .await;
+
+    let chat_id = app.create_group_returns_id(&stranger.token).await;
+    let res = app
+        .add_member(&stranger.token, chat_id, &app.test_user.username)
+        .await;
+
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn profile_photo_is_hidden_from_non_contacts() {
+    let app = spawn_app().await;
+
+    let owner = app.create_test_user().await;
+    let friend = app.create_test_user().await;
+    let stranger = app.create_test_user().await;
+
+    let media_id = app.upload_media_returns_id(&owner.token, fake_png()).await;
+    let res = app.set_profile_photo(&owner.token, Some(media_id)).await;
+    assert_eq!(res.status().as_u16(), 200);
+    app.add_contact(&owner.token, &friend.username).await;
+    let res = app
+        .set_privacy(
+            &owner.token,
+            json!({
+                "key": "profile_photo",
+                "visibility": "contacts",
+            }),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    assert_eq!(
+        photo_seen_by(&app, &friend.token, &owner.username).await,
+        Some(media_id)
+    );
+    assert_eq!(
+        photo_seen_by(&app, &stranger.token, &owner.username).await,
+        None
+    );
+
+    let res = app.download_media(&friend.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.download_media(&stranger.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
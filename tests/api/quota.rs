@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::helpers::{STORAGE_QUOTA, TestApp, fake_png, spawn_app};
+
+/// Unique content of 900 KiB, two of them fit into the quota
+fn large_file() -> Vec<u8> {
+    let mut data = Uuid::new_v4().to_string().into_bytes();
+    data.resize(900 * 1024, 0);
+    data
+}
+
+async fn wait_until_purged(app: &TestApp, media_id: i64) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM media WHERE id = $1) AS "exists!""#,
+                media_id,
+            )
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+            if !exists {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await
+    .expect("Media not purged");
+}
+
+#[tokio::test]
+async fn usage_counts_distinct_media() {
+    let app = spawn_app().await;
+
+    let usage = app.get_storage_usage(&app.test_user.token).await;
+    assert_eq!(usage["used"], 0);
+    assert_eq!(usage["quota"], STORAGE_QUOTA);
+
+    let data = large_file();
+    app.upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+    app.upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+
+    let usage = app.get_storage_usage(&app.test_user.token).await;
+    assert_eq!(usage["used"].as_u64().unwrap(), data.len() as u64);
+
+    // deduplicated content still counts for every user referencing it
+    let other = app.create_test_user().await;
+    app.upload_media_returns_id(&other.token, data.clone())
+        .await;
+
+    let usage = app.get_storage_usage(&other.token).await;
+    assert_eq!(usage["used"].as_u64().unwrap(), data.len() as u64);
+}
+
+#[tokio::test]
+async fn failure_when_quota_exceeded() {
+    let app = spawn_app().await;
+
+    let first = app
+        .upload_media_returns_id(&app.test_user.token, large_file())
+        .await;
+    app.upload_media_returns_id(&app.test_user.token, large_file())
+        .await;
+
+    let res = app.upload_media(&app.test_user.token, large_file()).await;
+    assert_eq!(res.status().as_u16(), 413);
+
+    let res = app
+        .create_upload_session(&app.test_user.token, 900 * 1024)
+        .await;
+    assert_eq!(res.status().as_u16(), 413);
+
+    // deleting an upload frees its space
+    let res = app.delete_media(&app.test_user.token, first).await;
+    assert_eq!(res.status().as_u16(), 204);
+
+    let res = app.upload_media(&app.test_user.token, large_file()).await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn failure_delete_media_of_others() {
+    let app = spawn_app().await;
+
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+
+    let other = app.create_test_user().await;
+    let res = app.delete_media(&other.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app.download_media(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unreferenced_media_is_purged() {
+    let app = spawn_app().await;
+
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, fake_png())
+        .await;
+
+    let res = app.delete_media(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+
+    wait_until_purged(&app, media_id).await;
+
+    let res = app.download_media(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn media_attached_to_messages_is_kept() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let data = large_file();
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+    let res = app
+        .send_chat_message_with_media(&app.test_user.token, chat_id, "", &[media_id])
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = app.delete_media(&app.test_user.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 204);
+
+    // the sent message still counts for the sender
+    let usage = app.get_storage_usage(&app.test_user.token).await;
+    assert_eq!(usage["used"].as_u64().unwrap(), data.len() as u64);
+
+    // longer than the grace period
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let res = app.download_media(&peer.token, media_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.bytes().await.unwrap().to_vec(), data);
+}
+
+#[tokio::test]
+async fn referencing_media_again_restarts_grace_period() {
+    let app = spawn_app().await;
+
+    let data = fake_png();
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, data.clone())
+        .await;
+
+    // marked while nothing referenced it
+    sqlx::query!(
+        "UPDATE media SET orphaned_at = now() - interval '1 hour' WHERE id = $1",
+        media_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let other = app.create_test_user().await;
+    app.upload_media_returns_id(&other.token, data).await;
+
+    let orphaned_at = sqlx::query_scalar!("SELECT orphaned_at FROM media WHERE id = $1", media_id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert!(orphaned_at.is_none());
+}
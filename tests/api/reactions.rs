@@ -0,0 +1,210 @@
+use crate::helpers::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn reactions_are_counted_in_history() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    let res = app.add_reaction(&peer.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 201);
+    app.add_reaction(&peer.token, message_id, "❤️").await;
+    app.add_reaction(&app.test_user.token, message_id, "👍")
+        .await;
+    // adding the same reaction twice counts once
+    app.add_reaction(&app.test_user.token, message_id, "👍")
+        .await;
+
+    let messages = app.get_chat_messages(&app.test_user.token, chat_id).await;
+    assert_eq!(
+        messages[0]["reactions"],
+        serde_json::json!([
+            {"emoji": "👍", "count": 2, "chosen": true},
+            {"emoji": "❤️", "count": 1, "chosen": false},
+        ])
+    );
+
+    let res = app.remove_reaction(&peer.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 200);
+    // ties are ordered by the first reaction
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json["reactions"],
+        serde_json::json!([
+            {"emoji": "❤️", "count": 1, "chosen": true},
+            {"emoji": "👍", "count": 1, "chosen": false},
+        ])
+    );
+
+    let res = app.remove_reaction(&peer.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn participants_receive_reaction_events() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    let mut stream = app.connect_updates(&app.test_user.token).await;
+
+    app.add_reaction(&peer.token, message_id, "🔥").await;
+    let event = TestApp::next_event(&mut stream, "reaction").await;
+    assert_eq!(event["chat_id"].as_i64().unwrap(), chat_id);
+    assert_eq!(event["message_id"].as_i64().unwrap(), message_id);
+    assert_eq!(event["user_id"].as_i64().unwrap(), peer.id);
+    assert_eq!(event["emoji"], "🔥");
+    assert_eq!(event["added"], true);
+
+    app.remove_reaction(&peer.token, message_id, "🔥").await;
+    let event = TestApp::next_event(&mut stream, "reaction").await;
+    assert_eq!(event["added"], false);
+}
+
+#[tokio::test]
+async fn failure_react_with_invalid_emoji() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    for emoji in [
+        "",
+        "a",
+        "ok",
+        "👍 👍",
+        "啊",
+        "a\u{FE0F}\u{20E3}",
+        "10\u{FE0F}\u{20E3}",
+    ] {
+        let res = app
+            .add_reaction(&app.test_user.token, message_id, emoji)
+            .await;
+        assert_eq!(res.status().as_u16(), 400, "{emoji}");
+    }
+}
+
+#[tokio::test]
+async fn success_react_with_keycap_emoji() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    for emoji in [
+        "1\u{FE0F}\u{20E3}",
+        "#\u{FE0F}\u{20E3}",
+        "*\u{FE0F}\u{20E3}",
+    ] {
+        let res = app
+            .add_reaction(&app.test_user.token, message_id, emoji)
+            .await;
+        assert_eq!(res.status().as_u16(), 201, "{emoji}");
+    }
+}
+
+#[tokio::test]
+async fn failure_react_when_not_participant() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    let stranger = app.create_test_user().await;
+    let res = app.add_reaction(&stranger.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn failure_react_when_blocked() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    app.block_user(&app.test_user.token, &peer.username).await;
+
+    let res = app.add_reaction(&peer.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn admins_restrict_allowed_reactions() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&app.test_user.token, chat_id, "hello")
+        .await;
+
+    let res = app
+        .set_allowed_reactions(&member.token, chat_id, Some(&["👍"]))
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = app
+        .set_allowed_reactions(&app.test_user.token, chat_id, Some(&["👍", "❤️", "👍"]))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_allowed_reactions(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["allowed"], serde_json::json!(["👍", "❤️"]));
+
+    let res = app.add_reaction(&member.token, message_id, "🔥").await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.add_reaction(&member.token, message_id, "❤️").await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    // an empty list disables reactions, none allows every emoji again
+    app.set_allowed_reactions(&app.test_user.token, chat_id, Some(&[]))
+        .await;
+    let res = app.add_reaction(&member.token, message_id, "👍").await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    app.set_allowed_reactions(&app.test_user.token, chat_id, None)
+        .await;
+    let res = app.add_reaction(&member.token, message_id, "🔥").await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn failure_set_allowed_reactions_in_private_chat() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let res = app
+        .set_allowed_reactions(&app.test_user.token, chat_id, Some(&["👍"]))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
@@ -31,7 +31,16 @@ async fn failure_with_weak_password() {
 }
 
 #[tokio::test]
-async fn failure_with_non_ascii_char_in_username() {
+async fn success_with_single_script_unicode_username() {
+    let app = spawn_app().await;
+
+    let res = app.register("павел", "strong_password").await;
+
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn failure_with_mixed_script_username() {
     let app = spawn_app().await;
 
     let res = app.register("不是ascii", "strong_password").await;
@@ -40,12 +49,70 @@ async fn failure_with_non_ascii_char_in_username() {
 }
 
 #[tokio::test]
-async fn failure_with_non_ascii_char_in_password() {
+async fn failure_with_invalid_char_in_username() {
     let app = spawn_app().await;
 
-    let res = app.register("ascii", "不是ascii-1111111").await;
+    let res = app.register("user 0", "strong_password").await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_when_username_is_confusable_with_exist_username() {
+    let app = spawn_app().await;
+
+    let res = app.register("pavel", "strong_password").await;
+    assert_eq!(res.status().as_u16(), 200);
 
+    // "раvеl" with Cyrillic letters
+    let res = app
+        .register("\u{440}\u{430}v\u{435}l", "strong_password")
+        .await;
     assert_eq!(res.status().as_u16(), 400);
+
+    // whole-script Cyrillic look-alike
+    let res = app.register("\u{440}\u{430}", "strong_password").await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.register("pa", "strong_password").await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn concurrent_look_alike_registrations_are_rejected() {
+    let app = spawn_app().await;
+
+    let responses = futures_util::future::join_all(
+        ["look_alike", "LOOK_ALIKE", "look_alike"]
+            .into_iter()
+            .map(|username| app.register(username, "strong_password")),
+    )
+    .await;
+
+    // exactly one of them is registered, the others are rejected as bad requests
+    let statuses: Vec<u16> = responses.iter().map(|res| res.status().as_u16()).collect();
+    assert_eq!(statuses.iter().filter(|&&status| status == 200).count(), 1);
+    assert_eq!(statuses.iter().filter(|&&status| status == 400).count(), 2);
+}
+
+#[tokio::test]
+async fn success_with_non_ascii_char_in_password() {
+    let app = spawn_app().await;
+
+    let res = app.register("ascii", "不是ascii-1111111").await;
+
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn password_is_normalized_before_hashing() {
+    let app = spawn_app().await;
+
+    // fullwidth form of "password"
+    let res = app.register("user0", "ｐａｓｓｗｏｒｄ").await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.login("user0", "password").await;
+    assert_eq!(res.status().as_u16(), 200);
 }
 
 #[tokio::test]
@@ -0,0 +1,314 @@
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::helpers::{TestApp, spawn_app};
+
+fn in_an_hour() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp() + 3600
+}
+
+/// Make the scheduled message due immediately
+async fn make_due(app: &TestApp, scheduled_id: i64) {
+    sqlx::query!(
+        "UPDATE scheduled_messages SET send_at = now() WHERE id = $1",
+        scheduled_id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+async fn wait_until_handled(app: &TestApp, scheduled_id: i64) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM scheduled_messages WHERE id = $1) AS "exists!""#,
+                scheduled_id,
+            )
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+            if !exists {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await
+    .expect("Scheduled message was not handled");
+}
+
+#[tokio::test]
+async fn due_message_is_posted_once() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let scheduled_id = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "good morning", in_an_hour())
+        .await;
+    assert!(app.get_chat_messages(&peer.token, chat_id).await.is_empty());
+
+    make_due(&app, scheduled_id).await;
+    wait_until_handled(&app, scheduled_id).await;
+
+    let messages = app.get_chat_messages(&peer.token, chat_id).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["content"], "good morning");
+    assert_eq!(messages[0]["sender_id"].as_i64().unwrap(), app.test_user.id);
+
+    // later runs of the worker do not post it again
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(app.get_chat_messages(&peer.token, chat_id).await.len(), 1);
+}
+
+#[tokio::test]
+async fn message_is_dropped_when_sender_left() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+
+    let scheduled_id = app
+        .schedule_message_returns_id(&member.token, chat_id, "hello", in_an_hour())
+        .await;
+
+    sqlx::query!(
+        "DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2",
+        chat_id,
+        member.id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    make_due(&app, scheduled_id).await;
+    wait_until_handled(&app, scheduled_id).await;
+
+    // only the service message of adding the member
+    assert!(
+        app.get_chat_messages(&app.test_user.token, chat_id)
+            .await
+            .iter()
+            .all(|message| message["kind"] == "service")
+    );
+}
+
+#[tokio::test]
+async fn failing_message_does_not_hold_back_others() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    // inserting this message fails in the database
+    sqlx::raw_sql(
+        r#"
+        CREATE FUNCTION reject_message() RETURNS trigger AS $$
+        BEGIN
+          IF NEW.content = 'rejected' THEN
+            RAISE EXCEPTION 'message rejected';
+          END IF;
+          RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        CREATE TRIGGER reject_message BEFORE INSERT ON messages
+          FOR EACH ROW EXECUTE FUNCTION reject_message();
+        "#,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let rejected_id = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "rejected", in_an_hour())
+        .await;
+    let scheduled_id = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "hello", in_an_hour())
+        .await;
+
+    make_due(&app, rejected_id).await;
+    make_due(&app, scheduled_id).await;
+    wait_until_handled(&app, rejected_id).await;
+    wait_until_handled(&app, scheduled_id).await;
+
+    let messages = app.get_chat_messages(&peer.token, chat_id).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["content"], "hello");
+}
+
+#[tokio::test]
+async fn due_messages_count_towards_flood_limit() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let flood_limit = json!({ "max_messages": 2, "interval": 60, "restrict_for": 3600 });
+    app.set_anti_spam(
+        &app.test_user.token,
+        chat_id,
+        json!({ "flood_limit": flood_limit }),
+    )
+    .await;
+
+    let mut scheduled_ids = Vec::new();
+    for content in ["one", "two", "three"] {
+        scheduled_ids.push(
+            app.schedule_message_returns_id(&member.token, chat_id, content, in_an_hour())
+                .await,
+        );
+    }
+    for &scheduled_id in &scheduled_ids {
+        make_due(&app, scheduled_id).await;
+    }
+    for &scheduled_id in &scheduled_ids {
+        wait_until_handled(&app, scheduled_id).await;
+    }
+
+    let messages = app.get_chat_messages(&member.token, chat_id).await;
+    let posted = messages
+        .iter()
+        .filter(|message| ["one", "two", "three"].contains(&message["content"].as_str().unwrap()))
+        .count();
+    assert_eq!(posted, 2);
+
+    let res = app.send_chat_message(&member.token, chat_id, "four").await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn list_edit_and_cancel() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let send_at = in_an_hour();
+    let first = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "first", send_at + 60)
+        .await;
+    let second = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "second", send_at)
+        .await;
+
+    let res = app
+        .edit_scheduled_message(&app.test_user.token, first, json!({"content": "edited"}))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app
+        .edit_scheduled_message(
+            &app.test_user.token,
+            second,
+            json!({"send_at": send_at + 120}),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .list_scheduled_messages(&app.test_user.token, chat_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["scheduled_id"].as_i64().unwrap(), first);
+    assert_eq!(messages[0]["content"], "edited");
+    assert_eq!(messages[1]["scheduled_id"].as_i64().unwrap(), second);
+    assert_eq!(messages[1]["send_at"].as_i64().unwrap(), send_at + 120);
+
+    let res = app
+        .cancel_scheduled_message(&app.test_user.token, first)
+        .await;
+    assert_eq!(res.status().as_u16(), 204);
+    let res = app
+        .cancel_scheduled_message(&app.test_user.token, first)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn failure_with_bad_send_time() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    for send_at in [now - 10, now + 2 * 365 * 24 * 60 * 60] {
+        let res = app
+            .schedule_message(&app.test_user.token, chat_id, "hello", send_at)
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn failure_schedule_when_not_participant() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let stranger = app.create_test_user().await;
+
+    let res = app
+        .schedule_message(&stranger.token, chat_id, "hello", in_an_hour())
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn failure_edit_scheduled_message_of_others() {
+    let app = spawn_app().await;
+
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    let scheduled_id = app
+        .schedule_message_returns_id(&app.test_user.token, chat_id, "hello", in_an_hour())
+        .await;
+
+    let other = app.create_test_user().await;
+    let res = app
+        .edit_scheduled_message(&other.token, scheduled_id, json!({"content": "hacked"}))
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+    let res = app
+        .cancel_scheduled_message(&other.token, scheduled_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn scheduled_poll_is_posted_with_options() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    let res = app
+        .schedule_poll(
+            &app.test_user.token,
+            chat_id,
+            json!({ "options": ["Tea", "Coffee"] }),
+            in_an_hour(),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["poll"]["options"], json!(["Tea", "Coffee"]));
+    let scheduled_id = json["scheduled_id"].as_i64().unwrap();
+
+    make_due(&app, scheduled_id).await;
+    wait_until_handled(&app, scheduled_id).await;
+
+    let messages = app.get_chat_messages(&peer.token, chat_id).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["kind"], "poll");
+    assert_eq!(messages[0]["poll"]["options"][1]["text"], "Coffee");
+}
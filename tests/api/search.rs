@@ -0,0 +1,101 @@
+use serde_json::json;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn search_by_username_prefix() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+
+    let ids = app
+        .search_user_ids(&app.test_user.token, &user.username[..8])
+        .await;
+
+    assert!(ids.contains(&user.id));
+    // the caller is never in the result
+    assert!(!ids.contains(&app.test_user.id));
+}
+
+#[tokio::test]
+async fn search_by_display_name_with_typo() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let res = app
+        .update_profile(&user.token, json!({ "display_name": "Catherine Nyanko" }))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let ids = app
+        .search_user_ids(&app.test_user.token, "Catherine Nyanco")
+        .await;
+
+    assert!(ids.contains(&user.id));
+}
+
+#[tokio::test]
+async fn wildcards_in_query_are_matched_literally() {
+    let app = spawn_app().await;
+
+    app.create_test_user().await;
+
+    let ids = app.search_user_ids(&app.test_user.token, "%").await;
+
+    assert!(ids.is_empty());
+}
+
+#[tokio::test]
+async fn opted_out_users_are_hidden() {
+    let app = spawn_app().await;
+
+    let user = app.create_test_user().await;
+    let res = app
+        .update_profile(&user.token, json!({ "searchable": false }))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let ids = app
+        .search_user_ids(&app.test_user.token, &user.username)
+        .await;
+
+    assert!(!ids.contains(&user.id));
+}
+
+#[tokio::test]
+async fn failure_with_empty_query() {
+    let app = spawn_app().await;
+
+    let res = app.search_users(&app.test_user.token, "  ").await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_with_too_long_query() {
+    let app = spawn_app().await;
+
+    let res = app
+        .search_users(&app.test_user.token, &"a".repeat(64))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .search_users(&app.test_user.token, &"a".repeat(65))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn failure_when_rate_limited() {
+    let app = spawn_app().await;
+
+    let mut statuses = Vec::new();
+    for _ in 0..31 {
+        let res = app.search_users(&app.test_user.token, "test").await;
+        statuses.push(res.status().as_u16());
+    }
+
+    assert!(statuses[..30].iter().all(|status| *status == 200));
+    assert_eq!(statuses[30], 429);
+}
@@ -0,0 +1,230 @@
+use crate::helpers::{TestApp, TestUser, spawn_app};
+
+/// Group of the test user with one more member and the topics enabled
+async fn create_forum_with_member(app: &TestApp) -> (i64, TestUser) {
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let res = app.toggle_topics(&app.test_user.token, chat_id, true).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    (chat_id, member)
+}
+
+async fn list_topics(app: &TestApp, token: &str, chat_id: i64) -> Vec<serde_json::Value> {
+    let res = app.list_topics(token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    json["topics"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn enabling_topics_moves_messages_into_general_topic() {
+    let app = spawn_app().await;
+
+    let member = app.create_test_user().await;
+    let chat_id = app.create_group_returns_id(&app.test_user.token).await;
+    app.add_member(&app.test_user.token, chat_id, &member.username)
+        .await;
+    let message_id = app
+        .send_chat_message_returns_id(&member.token, chat_id, "hi")
+        .await;
+
+    // only the admins with the right to change the info
+    let res = app.toggle_topics(&member.token, chat_id, true).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.toggle_topics(&app.test_user.token, chat_id, true).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let topics = list_topics(&app, &member.token, chat_id).await;
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0]["title"], "General");
+    assert_eq!(topics[0]["is_general"], true);
+    let general_id = topics[0]["topic_id"].as_i64().unwrap();
+
+    let messages = app
+        .get_topic_messages(&member.token, chat_id, general_id)
+        .await;
+    assert!(
+        messages
+            .iter()
+            .any(|message| message["message_id"].as_i64().unwrap() == message_id)
+    );
+    assert!(
+        messages
+            .iter()
+            .all(|message| message["topic_id"].as_i64().unwrap() == general_id)
+    );
+
+    let res = app.get_chat_info(&member.token, chat_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["topics_enabled"], true);
+
+    // the General topic survives disabling and enabling the topics again
+    app.toggle_topics(&app.test_user.token, chat_id, false)
+        .await;
+    let res = app.list_topics(&member.token, chat_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+    app.toggle_topics(&app.test_user.token, chat_id, true).await;
+    let topics = list_topics(&app, &member.token, chat_id).await;
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0]["topic_id"].as_i64().unwrap(), general_id);
+}
+
+#[tokio::test]
+async fn messages_are_sent_into_topics() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_forum_with_member(&app).await;
+    let topic_id = app
+        .create_topic_returns_id(&member.token, chat_id, "Cats")
+        .await;
+
+    let res = app
+        .send_topic_message(&member.token, chat_id, topic_id, "meow")
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["topic_id"].as_i64().unwrap(), topic_id);
+
+    // without a topic the message goes into the General topic
+    let res = app.send_chat_message(&member.token, chat_id, "hello").await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_ne!(json["topic_id"].as_i64().unwrap(), topic_id);
+
+    let messages = app
+        .get_topic_messages(&member.token, chat_id, topic_id)
+        .await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["content"], "meow");
+
+    // topics of another chat are unknown
+    let other_chat_id = app.create_group_returns_id(&member.token).await;
+    let res = app
+        .send_topic_message(&member.token, other_chat_id, topic_id, "meow")
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+    app.toggle_topics(&member.token, other_chat_id, true).await;
+    let res = app
+        .send_topic_message(&member.token, other_chat_id, topic_id, "meow")
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn topics_are_managed_by_creator_and_admins() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_forum_with_member(&app).await;
+    let admin = app.create_test_user().await;
+    app.add_member(&app.test_user.token, chat_id, &admin.username)
+        .await;
+    app.promote_member(
+        &app.test_user.token,
+        chat_id,
+        admin.id,
+        &["manage_topics"],
+        None,
+    )
+    .await;
+
+    let res = app.create_topic(&member.token, chat_id, "   ").await;
+    assert_eq!(res.status().as_u16(), 400);
+    let topic_id = app
+        .create_topic_returns_id(&app.test_user.token, chat_id, "Cats")
+        .await;
+
+    let res = app
+        .rename_topic(&member.token, chat_id, topic_id, "Dogs")
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .rename_topic(&admin.token, chat_id, topic_id, "Dogs")
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let own_topic_id = app
+        .create_topic_returns_id(&member.token, chat_id, "Birds")
+        .await;
+    let res = app.close_topic(&member.token, chat_id, own_topic_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.close_topic(&member.token, chat_id, own_topic_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+    let res = app.reopen_topic(&member.token, chat_id, own_topic_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.reopen_topic(&member.token, chat_id, own_topic_id).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app.close_topic(&member.token, chat_id, topic_id).await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app.close_topic(&admin.token, chat_id, topic_id).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let topics = list_topics(&app, &member.token, chat_id).await;
+    let general_id = topics[0]["topic_id"].as_i64().unwrap();
+    assert_eq!(topics[1]["title"], "Dogs");
+    assert_eq!(topics[1]["closed"], true);
+    let res = app
+        .close_topic(&app.test_user.token, chat_id, general_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_admins_write_into_closed_topics() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_forum_with_member(&app).await;
+    let topic_id = app
+        .create_topic_returns_id(&member.token, chat_id, "Cats")
+        .await;
+    app.close_topic(&app.test_user.token, chat_id, topic_id)
+        .await;
+
+    let res = app
+        .send_topic_message(&member.token, chat_id, topic_id, "meow")
+        .await;
+    assert_eq!(res.status().as_u16(), 403);
+    let res = app
+        .send_topic_message(&app.test_user.token, chat_id, topic_id, "closed")
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn unread_messages_are_counted_per_topic() {
+    let app = spawn_app().await;
+
+    let (chat_id, member) = create_forum_with_member(&app).await;
+    let topic_id = app
+        .create_topic_returns_id(&app.test_user.token, chat_id, "Cats")
+        .await;
+    app.send_topic_message(&app.test_user.token, chat_id, topic_id, "one")
+        .await;
+    let res = app
+        .send_topic_message(&app.test_user.token, chat_id, topic_id, "two")
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    let message_id = json["message_id"].as_i64().unwrap();
+
+    let topics = list_topics(&app, &member.token, chat_id).await;
+    assert_eq!(topics[1]["unread_count"].as_i64().unwrap(), 2);
+    assert_eq!(topics[1]["last_message_id"].as_i64().unwrap(), message_id);
+    // own messages are never unread
+    let topics = list_topics(&app, &app.test_user.token, chat_id).await;
+    assert_eq!(topics[1]["unread_count"].as_i64().unwrap(), 0);
+
+    let res = app
+        .read_topic(&member.token, chat_id, topic_id, message_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let topics = list_topics(&app, &member.token, chat_id).await;
+    assert_eq!(topics[1]["unread_count"].as_i64().unwrap(), 0);
+
+    let res = app
+        .read_topic(&member.token, chat_id, topic_id, message_id + 100)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use std::io::Cursor;
+
+use image::{ImageFormat, RgbImage};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, UPLOAD_CHUNK_SIZE, spawn_app};
+
+/// A PNG spanning three chunks, the last one is partial
+fn large_png() -> Vec<u8> {
+    // noise does not compress, so the size barely depends on the pixels
+    let seed = Uuid::new_v4();
+    let (width, height) = (24, 30);
+    let pixels: Vec<u8> = (0u32..)
+        .flat_map(|block| Sha256::digest(format!("{seed}-{block}")))
+        .take((width * height * 3) as usize)
+        .collect();
+    let image = RgbImage::from_raw(width, height, pixels).unwrap();
+
+    let mut buf = Cursor::new(Vec::new());
+    image.write_to(&mut buf, ImageFormat::Png).unwrap();
+    let data = buf.into_inner();
+    assert!((UPLOAD_CHUNK_SIZE * 2 + 1..UPLOAD_CHUNK_SIZE * 3).contains(&data.len()));
+    data
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+async fn upload_all_chunks(app: &TestApp, token: &str, session_id: i64, data: &[u8]) {
+    // the order of the chunks does not matter
+    for (chunk_index, chunk) in data.chunks(UPLOAD_CHUNK_SIZE).enumerate().rev() {
+        let res = app
+            .upload_chunk(token, session_id, chunk_index as i32, chunk.to_vec())
+            .await;
+        assert_eq!(res.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn success_upload_in_chunks() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let res = app
+        .create_upload_session(&app.test_user.token, data.len())
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json["chunk_size"].as_u64().unwrap(),
+        UPLOAD_CHUNK_SIZE as u64
+    );
+    assert_eq!(json["chunk_count"], 3);
+    let session_id = json["session_id"].as_i64().unwrap();
+
+    upload_all_chunks(&app, &app.test_user.token, session_id, &data).await;
+
+    let res = app
+        .finalize_upload_session(&app.test_user.token, session_id, &sha256_hex(&data))
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["content_type"], "image/png");
+    assert_eq!(json["size"].as_u64().unwrap(), data.len() as u64);
+
+    let res = app
+        .download_media(&app.test_user.token, json["media_id"].as_i64().unwrap())
+        .await;
+    assert_eq!(res.bytes().await.unwrap().to_vec(), data);
+
+    // the session is gone once finalized
+    let res = app
+        .get_upload_session(&app.test_user.token, session_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn session_reports_missing_chunks() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let session_id = app
+        .create_upload_session_returns_id(&app.test_user.token, data.len())
+        .await;
+
+    let res = app
+        .upload_chunk(
+            &app.test_user.token,
+            session_id,
+            1,
+            data[UPLOAD_CHUNK_SIZE..UPLOAD_CHUNK_SIZE * 2].to_vec(),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .get_upload_session(&app.test_user.token, session_id)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["missing"], serde_json::json!([0, 2]));
+
+    let res = app
+        .finalize_upload_session(&app.test_user.token, session_id, &sha256_hex(&data))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn checksum_mismatch_keeps_session() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let session_id = app
+        .create_upload_session_returns_id(&app.test_user.token, data.len())
+        .await;
+
+    let mut corrupted = data.clone();
+    corrupted[UPLOAD_CHUNK_SIZE] ^= 0xff;
+    upload_all_chunks(&app, &app.test_user.token, session_id, &corrupted).await;
+
+    let res = app
+        .finalize_upload_session(&app.test_user.token, session_id, &sha256_hex(&data))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    // the broken chunk can be uploaded again
+    let res = app
+        .upload_chunk(
+            &app.test_user.token,
+            session_id,
+            1,
+            data[UPLOAD_CHUNK_SIZE..UPLOAD_CHUNK_SIZE * 2].to_vec(),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .finalize_upload_session(&app.test_user.token, session_id, &sha256_hex(&data))
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn failure_with_bad_chunks() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let session_id = app
+        .create_upload_session_returns_id(&app.test_user.token, data.len())
+        .await;
+
+    let test_cases = [
+        (0, vec![0; UPLOAD_CHUNK_SIZE - 1], "short chunk"),
+        (0, vec![0; UPLOAD_CHUNK_SIZE + 1], "long chunk"),
+        (2, vec![0; UPLOAD_CHUNK_SIZE], "long last chunk"),
+        (3, vec![0; 100], "index out of range"),
+        (-1, vec![0; 100], "negative index"),
+    ];
+
+    for (chunk_index, chunk, desc) in test_cases {
+        let res = app
+            .upload_chunk(&app.test_user.token, session_id, chunk_index, chunk)
+            .await;
+
+        assert_eq!(res.status().as_u16(), 400, "Case: {desc}");
+    }
+}
+
+#[tokio::test]
+async fn failure_with_bad_total_size() {
+    let app = spawn_app().await;
+
+    let res = app.create_upload_session(&app.test_user.token, 0).await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app
+        .create_upload_session(&app.test_user.token, 1024 * 1024 + 1)
+        .await;
+    assert_eq!(res.status().as_u16(), 413);
+}
+
+#[tokio::test]
+async fn sessions_of_others_are_not_accessible() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let session_id = app
+        .create_upload_session_returns_id(&app.test_user.token, data.len())
+        .await;
+
+    let other = app.create_test_user().await;
+
+    let res = app.get_upload_session(&other.token, session_id).await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app
+        .upload_chunk(
+            &other.token,
+            session_id,
+            0,
+            data[..UPLOAD_CHUNK_SIZE].to_vec(),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+
+    let res = app
+        .finalize_upload_session(&other.token, session_id, &sha256_hex(&data))
+        .await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn abandoned_sessions_are_collected() {
+    let app = spawn_app().await;
+
+    let data = large_png();
+    let session_id = app
+        .create_upload_session_returns_id(&app.test_user.token, data.len())
+        .await;
+    let res = app
+        .upload_chunk(
+            &app.test_user.token,
+            session_id,
+            0,
+            data[..UPLOAD_CHUNK_SIZE].to_vec(),
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    // pretend the last chunk was uploaded long ago
+    sqlx::query!(
+        "UPDATE upload_sessions SET updated_at = now() - interval '2 days' WHERE id = $1",
+        session_id,
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let res = app
+                .get_upload_session(&app.test_user.token, session_id)
+                .await;
+            if res.status().as_u16() == 404 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await
+    .expect("Upload session not collected");
+}
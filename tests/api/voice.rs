@@ -0,0 +1,242 @@
+use crate::helpers::{TestApp, spawn_app};
+
+/// Samples Opus skips at the start of the decoded stream
+const PRE_SKIP: u16 = 312;
+
+fn ogg_page(flags: u8, granule: i64, sequence: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+
+    let mut page = b"OggS".to_vec();
+    page.push(0);
+    page.push(flags);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&1u32.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    // the checksum is not verified
+    page.extend_from_slice(&[0; 4]);
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+    page
+}
+
+/// Ogg Opus stream of audio packets with the given sizes, 20 ms each
+fn ogg_opus(packet_sizes: &[usize]) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(1);
+    head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&48000u32.to_le_bytes());
+    head.extend_from_slice(&[0; 3]);
+
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&[0; 8]);
+
+    let packets: Vec<Vec<u8>> = packet_sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &size)| vec![i as u8; size])
+        .collect();
+
+    let mut data = ogg_page(0x02, 0, 0, &[head]);
+    data.extend(ogg_page(0, 0, 1, &[tags]));
+
+    // a page holds at most 255 segments
+    let pages: Vec<&[Vec<u8>]> = packets.chunks(50).collect();
+    let mut samples = 0;
+    for (i, page) in pages.iter().enumerate() {
+        samples += page.len() as i64 * 960;
+        let flags = if i == pages.len() - 1 { 0x04 } else { 0 };
+        data.extend(ogg_page(
+            flags,
+            samples + PRE_SKIP as i64,
+            i as u32 + 2,
+            page,
+        ));
+    }
+    data
+}
+
+/// Overwrite the granule position of the last page
+fn with_last_granule(mut data: Vec<u8>, granule: i64) -> Vec<u8> {
+    let last_page = data
+        .windows(4)
+        .rposition(|window| window == b"OggS")
+        .unwrap();
+    data[last_page + 6..last_page + 14].copy_from_slice(&granule.to_le_bytes());
+    data
+}
+
+/// 4 seconds of synthetic speech encoded with a constant bitrate of 16 kbit/s:
+/// silence, loud, silence, quiet, silence, medium, faint, the loudest part and
+/// silence again
+const HYBRID_RECORDING: &[u8] = include_bytes!("fixtures/voice_hybrid_cbr.opus");
+/// The same speech encoded by the low delay CELT mode
+const CELT_RECORDING: &[u8] = include_bytes!("fixtures/voice_celt_cbr.opus");
+
+async fn upload_waveform(app: &TestApp, data: Vec<u8>) -> Vec<u64> {
+    let res = app.upload_media(&app.test_user.token, data).await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["content_type"], "audio/opus");
+    assert_eq!(json["duration_ms"], 4000);
+
+    json["waveform"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_u64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn voice_note_gets_duration_and_waveform() {
+    let app = spawn_app().await;
+
+    let waveform = upload_waveform(&app, HYBRID_RECORDING.to_vec()).await;
+    assert_eq!(waveform.len(), 100);
+    assert_eq!(waveform[0], 0);
+    assert_eq!(waveform[99], 0);
+    assert_eq!(*waveform.iter().max().unwrap(), 31);
+    // the quiet part, the loud part and the loudest part
+    assert!(waveform[38] < waveform[17]);
+    assert!(waveform[17] < waveform[80]);
+}
+
+#[tokio::test]
+async fn constant_bitrate_waveform_follows_loudness() {
+    let app = spawn_app().await;
+
+    // every packet has the same size
+    let waveform = upload_waveform(&app, CELT_RECORDING.to_vec()).await;
+    assert_eq!(waveform.len(), 100);
+    assert_eq!(waveform[0], 0);
+    assert_eq!(waveform[99], 0);
+    assert_eq!(*waveform.iter().max().unwrap(), 31);
+    assert!(waveform[38] < waveform[17]);
+    assert!(waveform[17] < waveform[80]);
+}
+
+#[tokio::test]
+async fn long_recording_waveform_is_downsampled() {
+    let app = spawn_app().await;
+
+    let sizes: Vec<usize> = (0..1000).map(|i| 10 + i / 10).collect();
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, ogg_opus(&sizes))
+        .await;
+
+    let res = app.get_media_info(&app.test_user.token, media_id).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["duration_ms"], 20000);
+
+    let waveform = json["waveform"].as_array().unwrap();
+    assert_eq!(waveform.len(), 100);
+}
+
+#[tokio::test]
+async fn forged_granule_position_is_not_a_voice_note() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+
+    // three packets hold at most 360 ms
+    for granule in [
+        i64::MAX,
+        i64::MAX / 1000 + 1,
+        3 * 5760 + PRE_SKIP as i64 + 1,
+    ] {
+        let data = with_last_granule(ogg_opus(&[10, 20, 30]), granule);
+        let res = app.upload_media(&app.test_user.token, data).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let json = res.json::<serde_json::Value>().await.unwrap();
+        assert!(json["duration_ms"].is_null());
+        assert!(json["waveform"].is_null());
+
+        let media_id = json["media_id"].as_i64().unwrap();
+        let res = app
+            .send_voice_message(&app.test_user.token, chat_id, &[media_id])
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+
+    // the longest duration the packets can hold is kept
+    let data = with_last_granule(ogg_opus(&[10, 20, 30]), 3 * 5760 + PRE_SKIP as i64);
+    let res = app.upload_media(&app.test_user.token, data).await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(json["duration_ms"], 360);
+}
+
+#[tokio::test]
+async fn other_media_has_no_waveform() {
+    let app = spawn_app().await;
+
+    let res = app
+        .upload_media(&app.test_user.token, b"just some text".to_vec())
+        .await;
+    let json = res.json::<serde_json::Value>().await.unwrap();
+
+    assert!(json["duration_ms"].is_null());
+    assert!(json["waveform"].is_null());
+}
+
+#[tokio::test]
+async fn success_send_voice_message() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let media_id = app
+        .upload_media_returns_id(&app.test_user.token, ogg_opus(&[10, 20, 30]))
+        .await;
+
+    let res = app
+        .send_voice_message(&app.test_user.token, chat_id, &[media_id])
+        .await;
+    assert_eq!(res.status().as_u16(), 201);
+
+    let message_id = res.json::<serde_json::Value>().await.unwrap()["message_id"]
+        .as_i64()
+        .unwrap();
+    let kind = sqlx::query_scalar!("SELECT kind FROM messages WHERE id = $1", message_id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(kind, "voice");
+}
+
+#[tokio::test]
+async fn failure_send_voice_message_without_voice_note() {
+    let app = spawn_app().await;
+
+    let peer = app.create_test_user().await;
+    let chat_id = app
+        .create_pm_returns_id(&app.test_user.token, &peer.username)
+        .await;
+    let voice_id = app
+        .upload_media_returns_id(&app.test_user.token, ogg_opus(&[10, 20, 30]))
+        .await;
+    let text_id = app
+        .upload_media_returns_id(&app.test_user.token, b"just some text".to_vec())
+        .await;
+
+    for media_ids in [vec![], vec![text_id], vec![voice_id, text_id]] {
+        let res = app
+            .send_voice_message(&app.test_user.token, chat_id, &media_ids)
+            .await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}